@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use systemd_boot_conf::entry::Entry;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Entry::parse(data);
+});
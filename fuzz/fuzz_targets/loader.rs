@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use systemd_boot_conf::loader::LoaderConf;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = LoaderConf::parse(data);
+});
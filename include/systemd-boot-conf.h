@@ -0,0 +1,94 @@
+#ifndef SYSTEMD_BOOT_CONF_H
+#define SYSTEMD_BOOT_CONF_H
+
+/* Generated by cbindgen from src/capi.rs. Do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct sbc_conf sbc_conf;
+
+/*
+ Load the configuration of the ESP mounted at `efi_mount`, or return null on failure.
+ */
+sbc_conf *sbc_conf_new(const char *efi_mount);
+
+/*
+ Free a configuration, which may be null.
+ */
+void sbc_conf_free(sbc_conf *conf);
+
+/*
+ The number of entries.
+ */
+size_t sbc_conf_entry_count(const sbc_conf *conf);
+
+/*
+ The id of the entry at `index`, or null if there is none.
+ */
+char *sbc_conf_entry_id(const sbc_conf *conf, size_t index);
+
+/*
+ The title of the entry at `index`, or null if there is none.
+ */
+char *sbc_conf_entry_title(const sbc_conf *conf, size_t index);
+
+/*
+ The kernel options of the entry at `index` as a command line, separated by spaces and quoted
+ where needed, or null if there is none.
+ */
+char *sbc_conf_entry_options(const sbc_conf *conf, size_t index);
+
+/*
+ The default entry of the loader conf, or null if it has none.
+ */
+char *sbc_conf_default(const sbc_conf *conf);
+
+/*
+ The timeout of the loader conf in seconds, or `-1` if it has none.
+ */
+int64_t sbc_conf_timeout(const sbc_conf *conf);
+
+/*
+ Boot the entry or UKI with this id by default, which takes effect once the loader conf is
+ written with `sbc_conf_write_loader`.
+ */
+int sbc_conf_set_default(sbc_conf *conf, const char *id);
+
+/*
+ Show the menu for this many seconds, which takes effect once the loader conf is written with
+ `sbc_conf_write_loader`.
+ */
+void sbc_conf_set_timeout(sbc_conf *conf, uint32_t seconds);
+
+/*
+ Write the loader conf.
+ */
+int sbc_conf_write_loader(const sbc_conf *conf);
+
+/*
+ Write the loader conf and every entry into the ESP tree at `target`.
+ */
+int sbc_conf_persist_to(const sbc_conf *conf, const char *target);
+
+/*
+ The message of the last failure on this thread, with its causes, or null if nothing failed.
+
+ The string is valid until the next call which fails on this thread, and is not freed.
+ */
+const char *sbc_last_error(void);
+
+/*
+ The stable code of the last failure on this thread, such as `entry-not-found`, or null if
+ nothing failed.
+
+ The string is valid until the next call which fails on this thread, and is not freed.
+ */
+const char *sbc_last_error_code(void);
+
+/*
+ Free a string which was returned by this library, which may be null.
+ */
+void sbc_string_free(char *string);
+
+#endif /* SYSTEMD_BOOT_CONF_H */
@@ -0,0 +1,168 @@
+//! The EFI architectures which systemd-boot is built for, and the names of its images and of the
+//! fallback loaders for each of them.
+
+use crate::entry::HOST_ARCHITECTURE;
+use once_cell::sync::Lazy;
+use std::fmt;
+use std::fs;
+
+/// Where the kernel reports the word size of the firmware, which is 32 on the x86-64 machines
+/// with 32-bit firmware that boot in mixed mode.
+const FW_PLATFORM_SIZE: &str = "/sys/firmware/efi/fw_platform_size";
+
+/// An EFI architecture, by the name that systemd-boot and the `architecture` key of entries use.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum EfiArch {
+    X64,
+    Ia32,
+    Aa64,
+    Arm,
+    Riscv64,
+    LoongArch64,
+}
+
+impl EfiArch {
+    pub const ALL: &'static [EfiArch] = &[
+        EfiArch::X64,
+        EfiArch::Ia32,
+        EfiArch::Aa64,
+        EfiArch::Arm,
+        EfiArch::Riscv64,
+        EfiArch::LoongArch64,
+    ];
+
+    /// Parse the EFI name of an architecture, ignoring its case.
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|arch| arch.as_str().eq_ignore_ascii_case(name))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EfiArch::X64 => "x64",
+            EfiArch::Ia32 => "ia32",
+            EfiArch::Aa64 => "aa64",
+            EfiArch::Arm => "arm",
+            EfiArch::Riscv64 => "riscv64",
+            EfiArch::LoongArch64 => "loongarch64",
+        }
+    }
+
+    /// The architecture of a PE image, by its `IMAGE_FILE_MACHINE_*`.
+    pub fn from_machine(machine: u16) -> Option<Self> {
+        match machine {
+            0x8664 => Some(EfiArch::X64),
+            0x014c => Some(EfiArch::Ia32),
+            0xaa64 => Some(EfiArch::Aa64),
+            0x01c2 | 0x01c4 => Some(EfiArch::Arm),
+            0x5064 => Some(EfiArch::Riscv64),
+            0x6264 => Some(EfiArch::LoongArch64),
+            _ => None,
+        }
+    }
+
+    /// The architecture this crate was compiled for.
+    pub fn host() -> Option<Self> {
+        HOST_ARCHITECTURE.and_then(Self::parse)
+    }
+
+    /// The architecture of a machine, by the name that `uname -m` gives it.
+    pub fn from_uname(machine: &str) -> Option<Self> {
+        match machine {
+            "x86_64" => Some(EfiArch::X64),
+            "i386" | "i486" | "i586" | "i686" => Some(EfiArch::Ia32),
+            "aarch64" | "arm64" => Some(EfiArch::Aa64),
+            "riscv64" => Some(EfiArch::Riscv64),
+            "loongarch64" => Some(EfiArch::LoongArch64),
+            _ if machine.starts_with("arm") => Some(EfiArch::Arm),
+            _ => None,
+        }
+    }
+
+    /// The architecture of the firmware of this machine, which entries and loaders must be built
+    /// for to boot, and which need not be the one this crate was compiled for.
+    ///
+    /// The machine type is taken from the kernel, falling back to the architecture this crate was
+    /// compiled for, and its word size from the firmware, so that it is `ia32` on x86-64 machines
+    /// with 32-bit firmware, and `x64` for a 32-bit build on 64-bit firmware.
+    pub fn firmware() -> Option<Self> {
+        static FIRMWARE: Lazy<Option<EfiArch>> = Lazy::new(|| {
+            let machine = machine().or_else(EfiArch::host)?;
+            let size = fs::read_to_string(FW_PLATFORM_SIZE).ok();
+            Some(machine.with_word_size(size.as_deref().map(str::trim)))
+        });
+
+        *FIRMWARE
+    }
+
+    /// The architecture of the same family with the given word size of the firmware, if known.
+    fn with_word_size(self, size: Option<&str>) -> Self {
+        match (self, size) {
+            (EfiArch::X64, Some("32")) => EfiArch::Ia32,
+            (EfiArch::Ia32, Some("64")) => EfiArch::X64,
+            (EfiArch::Aa64, Some("32")) => EfiArch::Arm,
+            (EfiArch::Arm, Some("64")) => EfiArch::Aa64,
+            (arch, _) => arch,
+        }
+    }
+
+    /// The file name of systemd-boot, such as `systemd-bootaa64.efi`.
+    pub fn loader_name(self) -> String {
+        format!("systemd-boot{}.efi", self.as_str())
+    }
+
+    /// The file name of the removable media fallback loader, such as `BOOTAA64.EFI`.
+    pub fn fallback_name(self) -> String {
+        format!("BOOT{}.EFI", self.as_str().to_ascii_uppercase())
+    }
+}
+
+/// The architecture of the running kernel.
+#[cfg(unix)]
+fn machine() -> Option<EfiArch> {
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+
+    let machine = unsafe { std::ffi::CStr::from_ptr(name.machine.as_ptr()) };
+    EfiArch::from_uname(machine.to_str().ok()?)
+}
+
+#[cfg(not(unix))]
+fn machine() -> Option<EfiArch> {
+    None
+}
+
+impl fmt::Display for EfiArch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_word_size() {
+        let x64 = EfiArch::from_uname("x86_64").unwrap();
+        let ia32 = EfiArch::from_uname("i686").unwrap();
+        assert_eq!(x64.with_word_size(Some("32")), EfiArch::Ia32);
+        assert_eq!(x64.with_word_size(Some("64")), EfiArch::X64);
+        assert_eq!(ia32.with_word_size(Some("64")), EfiArch::X64);
+        assert_eq!(ia32.with_word_size(None), EfiArch::Ia32);
+
+        let arm = EfiArch::from_uname("armv7l").unwrap();
+        assert_eq!(arm.with_word_size(Some("64")), EfiArch::Aa64);
+        assert_eq!(EfiArch::Aa64.with_word_size(Some("32")), EfiArch::Arm);
+        assert_eq!(
+            EfiArch::Riscv64.with_word_size(Some("32")),
+            EfiArch::Riscv64
+        );
+        assert_eq!(EfiArch::from_uname("s390x"), None);
+    }
+}
@@ -0,0 +1,264 @@
+//! A record of every change that is made to the configuration through this crate, kept in an
+//! append-only file or in the systemd journal.
+
+use crate::events::{ChangeEvent, ChangeListener, FieldChange};
+use crate::hooks::WriteHook;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where audit records are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AuditSink {
+    /// Append a line for each record to this file.
+    File(PathBuf),
+    /// Send each record to the systemd journal, with its fields as journal fields.
+    #[cfg(feature = "journald")]
+    Journald,
+}
+
+/// A change that was made, and the user and process which made it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuditRecord {
+    pub time: SystemTime,
+    /// The real user id of the process, on Unix.
+    pub uid: Option<u32>,
+    pub pid: u32,
+    /// The kind of change, such as `write`, `entry-removed`, or `default-changed`.
+    pub operation: &'static str,
+    /// What was changed, such as the `path` of a file, or the `old` and `new` default.
+    pub fields: Vec<(String, String)>,
+}
+
+impl AuditRecord {
+    fn new(operation: &'static str, fields: Vec<(String, String)>) -> Self {
+        #[cfg(unix)]
+        let uid = Some(unsafe { libc::getuid() });
+        #[cfg(not(unix))]
+        let uid = None;
+
+        Self {
+            time: SystemTime::now(),
+            uid,
+            pid: std::process::id(),
+            operation,
+            fields,
+        }
+    }
+
+    fn from_event(event: &ChangeEvent) -> Self {
+        let field = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+        let optional = |value: Option<String>| value.unwrap_or_default();
+
+        let (operation, fields) = match event {
+            ChangeEvent::EntryAdded { id } => ("entry-added", vec![field("entry", id)]),
+            ChangeEvent::EntryModified { id, changes } => {
+                let mut fields = vec![field("entry", id)];
+                fields.extend(changes.iter().flat_map(change_fields));
+                ("entry-modified", fields)
+            }
+            ChangeEvent::EntryRemoved { id } => ("entry-removed", vec![field("entry", id)]),
+            ChangeEvent::EntryRenamed { from, to } => {
+                ("entry-renamed", vec![field("old", from), field("new", to)])
+            }
+            ChangeEvent::DefaultChanged { old, new } => (
+                "default-changed",
+                vec![
+                    field("old", &optional(old.clone())),
+                    field("new", &optional(new.clone())),
+                ],
+            ),
+            ChangeEvent::TimeoutChanged { old, new } => (
+                "timeout-changed",
+                vec![
+                    field("old", &optional(old.map(|t| t.to_string()))),
+                    field("new", &optional(new.map(|t| t.to_string()))),
+                ],
+            ),
+        };
+
+        Self::new(operation, fields)
+    }
+}
+
+/// The old and new values of a key of an entry, as `old.<key>` and `new.<key>` fields.
+fn change_fields(change: &FieldChange) -> Vec<(String, String)> {
+    vec![
+        (["old.", &change.key].concat(), change.old.join("\n")),
+        (["new.", &change.key].concat(), change.new.join("\n")),
+    ]
+}
+
+/// Formats the record as one line of `key=value` fields, led by a UTC timestamp.
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        write!(f, "{}", Timestamp(seconds))?;
+        if let Some(uid) = self.uid {
+            write!(f, " uid={}", uid)?;
+        }
+
+        write!(f, " pid={} op={}", self.pid, self.operation)?;
+        for (key, value) in &self.fields {
+            write!(f, " {}={:?}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records every file that is written, and every change to entries and the loader conf.
+///
+/// Register it with `SystemdBootConfBuilder::audit_log`, which runs it both as a write hook and
+/// as a change listener. A write which cannot be recorded is reported as a failure to write the
+/// file; other records which cannot be kept are logged as warnings.
+#[derive(Debug)]
+pub struct AuditLog {
+    sink: AuditSink,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(sink: AuditSink) -> Self {
+        Self {
+            sink,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Keep records in an append-only file, which is created if it does not exist.
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(AuditSink::File(path.into()))
+    }
+
+    pub fn sink(&self) -> &AuditSink {
+        &self.sink
+    }
+
+    /// Keep a record.
+    pub fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|why| why.into_inner());
+
+        match self.sink {
+            AuditSink::File(ref path) => {
+                let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+                writeln!(file, "{}", record)
+            }
+            #[cfg(feature = "journald")]
+            AuditSink::Journald => journald::send(record),
+        }
+    }
+}
+
+impl WriteHook for AuditLog {
+    fn after_write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let record = AuditRecord::new(
+            "write",
+            vec![
+                ("path".to_owned(), path.display().to_string()),
+                ("bytes".to_owned(), contents.len().to_string()),
+            ],
+        );
+
+        self.record(&record)
+    }
+}
+
+impl ChangeListener for AuditLog {
+    fn changed(&self, event: &ChangeEvent) {
+        let record = AuditRecord::from_event(event);
+        if let Err(why) = self.record(&record) {
+            warn!(
+                "failed to record {} in the audit log: {}",
+                record.operation, why
+            );
+        }
+    }
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp in UTC.
+struct Timestamp(u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = (self.0 / 86400) as i64;
+        let time = self.0 % 86400;
+
+        // The civil date of a day count, from Howard Hinnant's `civil_from_days`.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60,
+            time % 60
+        )
+    }
+}
+
+#[cfg(feature = "journald")]
+mod journald {
+    use super::AuditRecord;
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+
+    /// The socket of the journal's native protocol.
+    const SOCKET: &str = "/run/systemd/journal/socket";
+
+    pub(super) fn send(record: &AuditRecord) -> io::Result<()> {
+        let mut message = Vec::new();
+        let mut field = |key: &str, value: &str| {
+            message.extend_from_slice(key.as_bytes());
+            if value.contains('\n') {
+                // Values with line breaks are sent with their length, rather than terminated.
+                message.push(b'\n');
+                message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                message.extend_from_slice(value.as_bytes());
+                message.push(b'\n');
+            } else {
+                message.push(b'=');
+                message.extend_from_slice(value.as_bytes());
+                message.push(b'\n');
+            }
+        };
+
+        field("MESSAGE", &record.to_string());
+        field("SYSLOG_IDENTIFIER", "systemd-boot-conf");
+        field("AUDIT_OPERATION", record.operation);
+        for (key, value) in &record.fields {
+            let key = key
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' => c.to_ascii_uppercase(),
+                    'A'..='Z' | '0'..='9' => c,
+                    _ => '_',
+                })
+                .collect::<String>();
+            field(&["AUDIT_", &key].concat(), value);
+        }
+
+        UnixDatagram::unbound()?
+            .send_to(&message, SOCKET)
+            .map(|_| ())
+    }
+}
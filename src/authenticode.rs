@@ -0,0 +1,356 @@
+//! Authenticode signatures of PE images, which secure boot firmware verifies before executing
+//! boot loaders and kernels.
+//!
+//! This reports who signed an image, and whether the image still matches the digest that was
+//! signed. It does not verify the signature or the certificate chain, which is left to the
+//! firmware and the keys enrolled in it.
+
+use crate::pe::{self, PeError, PeImage};
+use crate::ErrorKind;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+
+/// `WIN_CERT_TYPE_PKCS_SIGNED_DATA`
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+/// 1.2.840.113549.1.7.2, PKCS #7 signed data.
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+
+/// 1.3.6.1.4.1.311.2.1.4, `SpcIndirectDataContent`.
+const OID_SPC_INDIRECT_DATA: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x01, 0x04];
+
+/// 2.16.840.1.101.3.4.2.1, SHA-256.
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// 2.5.4.3, the common name of a distinguished name.
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// 2.5.4.10, the organization of a distinguished name.
+const OID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AuthenticodeError {
+    #[error("image is not a valid PE image")]
+    Image(#[source] PeError),
+    #[error("signature is malformed")]
+    InvalidSignature,
+    #[error("error reading image")]
+    Open(#[source] io::Error),
+}
+
+impl AuthenticodeError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AuthenticodeError::Open(why) => ErrorKind::from_io(why),
+            _ => ErrorKind::InvalidData,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// An Authenticode signature of an image.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    /// The common name of the certificate which made the signature, if it is embedded.
+    pub signer: Option<String>,
+    /// The common name of the issuer of the signing certificate.
+    pub issuer: Option<String>,
+    /// Whether the image matches the digest that was signed, or `None` if the digest uses an
+    /// algorithm other than SHA-256.
+    pub digest_matches: Option<bool>,
+}
+
+/// The signatures of an image which the configuration boots.
+#[derive(Debug)]
+pub struct PayloadSignature {
+    pub path: PathBuf,
+    /// The signatures of the image, which is empty if the image is not signed.
+    pub signatures: Result<Vec<Signature>, AuthenticodeError>,
+}
+
+impl PayloadSignature {
+    /// Whether the image has a signature which matches its contents.
+    pub fn is_signed(&self) -> bool {
+        self.signatures.as_ref().is_ok_and(|signatures| {
+            signatures
+                .iter()
+                .any(|signature| signature.digest_matches != Some(false))
+        })
+    }
+}
+
+/// The Authenticode signatures of a PE image, which is empty if the image is not signed.
+pub fn signatures(data: &[u8]) -> Result<Vec<Signature>, AuthenticodeError> {
+    let image = PeImage::parse(data).map_err(AuthenticodeError::Image)?;
+
+    let (offset, size) = match image.data_directory(pe::SECURITY_DIRECTORY) {
+        Some((offset, size)) => (offset as usize, size as usize),
+        None => return Ok(Vec::new()),
+    };
+
+    // The certificate table is addressed by its file offset, rather than a virtual address.
+    let table = data
+        .get(offset..offset + size)
+        .ok_or(AuthenticodeError::InvalidSignature)?;
+
+    let mut image_digest = None;
+    let mut signatures = Vec::new();
+    let mut rest = table;
+
+    while rest.len() >= 8 {
+        let length = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let cert_type = u16::from_le_bytes([rest[6], rest[7]]);
+
+        if length < 8 || length > rest.len() {
+            return Err(AuthenticodeError::InvalidSignature);
+        }
+
+        if cert_type == WIN_CERT_TYPE_PKCS_SIGNED_DATA {
+            let signed = parse_signed_data(&rest[8..length])?;
+
+            let digest_matches = if signed.algorithm == OID_SHA256 {
+                let digest = image_digest.get_or_insert_with(|| digest(&image, offset));
+                Some(digest[..] == *signed.digest)
+            } else {
+                None
+            };
+
+            signatures.push(Signature {
+                signer: signed.signer,
+                issuer: signed.issuer,
+                digest_matches,
+            });
+        }
+
+        // Entries are aligned to 8 bytes.
+        let next = (length + 7) & !7;
+        rest = rest.get(next..).unwrap_or(&[]);
+    }
+
+    Ok(signatures)
+}
+
+/// The Authenticode digest of an image, which covers all of it except for the checksum, the
+/// certificate table, and the data directory entry which locates the certificate table.
+fn digest(image: &PeImage, table_offset: usize) -> Vec<u8> {
+    let data = image.data();
+    let checksum = image.optional_header_offset + 64;
+    let directories = image.optional_header_offset + if image.pe32_plus { 112 } else { 96 };
+    let security_entry = directories + pe::SECURITY_DIRECTORY * 8;
+    let end = table_offset.min(data.len());
+
+    let mut hasher = Sha256::new();
+    hasher.update(&data[..checksum]);
+    hasher.update(&data[checksum + 4..security_entry]);
+    hasher.update(&data[security_entry + 8..end]);
+    hasher.finalize().to_vec()
+}
+
+struct SignedData<'a> {
+    algorithm: &'a [u8],
+    digest: &'a [u8],
+    signer: Option<String>,
+    issuer: Option<String>,
+}
+
+fn parse_signed_data(data: &[u8]) -> Result<SignedData<'_>, AuthenticodeError> {
+    parse_signed_data_inner(data).ok_or(AuthenticodeError::InvalidSignature)
+}
+
+fn parse_signed_data_inner(data: &[u8]) -> Option<SignedData<'_>> {
+    let content_info = expect(data, TAG_SEQUENCE)?.0;
+    let (oid, rest) = expect(content_info, TAG_OID)?;
+    if oid != OID_SIGNED_DATA {
+        return None;
+    }
+
+    let signed = expect(expect(rest, TAG_CONTEXT_0)?.0, TAG_SEQUENCE)?.0;
+    let mut children = Children(signed);
+
+    let _version = children.expect(TAG_INTEGER)?;
+    let _digest_algorithms = children.expect(TAG_SET)?;
+    let content = children.expect(TAG_SEQUENCE)?;
+
+    let (oid, rest) = expect(content, TAG_OID)?;
+    if oid != OID_SPC_INDIRECT_DATA {
+        return None;
+    }
+
+    let indirect = expect(expect(rest, TAG_CONTEXT_0)?.0, TAG_SEQUENCE)?.0;
+    let mut indirect = Children(indirect);
+    let _data = indirect.expect(TAG_SEQUENCE)?;
+    let mut digest_info = Children(indirect.expect(TAG_SEQUENCE)?);
+    let algorithm = expect(digest_info.expect(TAG_SEQUENCE)?, TAG_OID)?.0;
+    let digest = digest_info.expect(TAG_OCTET_STRING)?;
+
+    let mut certificates = None;
+    let mut signer_infos = None;
+    for (tag, value, _) in children {
+        match tag {
+            TAG_CONTEXT_0 => certificates = Some(value),
+            TAG_SET => signer_infos = Some(value),
+            _ => (),
+        }
+    }
+
+    // The signer is identified by the issuer and serial number of its certificate.
+    let signer_id = signer_infos.and_then(|infos| {
+        let info = expect(infos, TAG_SEQUENCE)?.0;
+        let mut info = Children(info);
+        let _version = info.expect(TAG_INTEGER)?;
+        let mut id = Children(info.expect(TAG_SEQUENCE)?);
+        let (_, _, issuer) = id.next()?;
+        let (_, _, serial) = id.next()?;
+        Some((issuer, serial))
+    });
+
+    let signer_cert = certificates.and_then(|certs| {
+        let (issuer, serial) = signer_id?;
+        Children(certs).find_map(|(_, cert, _)| {
+            let tbs = Certificate::parse(cert)?;
+            if tbs.issuer == issuer && tbs.serial == serial {
+                Some(tbs)
+            } else {
+                None
+            }
+        })
+    });
+
+    let (signer, issuer) = match signer_cert {
+        Some(cert) => (name(cert.subject), name(cert.issuer)),
+        None => (None, signer_id.and_then(|(issuer, _)| name(issuer))),
+    };
+
+    Some(SignedData {
+        algorithm,
+        digest,
+        signer,
+        issuer,
+    })
+}
+
+/// The fields of a certificate, each as a complete DER element.
+struct Certificate<'a> {
+    serial: &'a [u8],
+    issuer: &'a [u8],
+    subject: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    fn parse(cert: &'a [u8]) -> Option<Self> {
+        let tbs = expect(cert, TAG_SEQUENCE)?.0;
+        let mut fields = Children(tbs);
+
+        let (mut tag, _, mut serial) = fields.next()?;
+        if tag == TAG_CONTEXT_0 {
+            let next = fields.next()?;
+            tag = next.0;
+            serial = next.2;
+        }
+
+        if tag != TAG_INTEGER {
+            return None;
+        }
+
+        let _signature = fields.next()?;
+        let (_, _, issuer) = fields.next()?;
+        let _validity = fields.next()?;
+        let (_, _, subject) = fields.next()?;
+
+        Some(Certificate {
+            serial,
+            issuer,
+            subject,
+        })
+    }
+}
+
+/// The common name of a distinguished name, or its organization if it has no common name.
+fn name(element: &[u8]) -> Option<String> {
+    let rdns = expect(element, TAG_SEQUENCE)?.0;
+    let mut organization = None;
+
+    for (_, rdn, _) in Children(rdns) {
+        for (_, attribute, _) in Children(rdn) {
+            let (oid, rest) = expect(attribute, TAG_OID)?;
+            let value = read(rest)?.1;
+            let value = String::from_utf8_lossy(value).into_owned();
+
+            if oid == OID_COMMON_NAME {
+                return Some(value);
+            } else if oid == OID_ORGANIZATION {
+                organization = Some(value);
+            }
+        }
+    }
+
+    organization
+}
+
+/// Iterates over the elements in the contents of a constructed element, yielding the tag,
+/// contents, and complete encoding of each.
+struct Children<'a>(&'a [u8]);
+
+impl<'a> Children<'a> {
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.next()? {
+            (t, value, _) if t == tag => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = (u8, &'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tag, value, rest) = read(self.0)?;
+        let element = &self.0[..self.0.len() - rest.len()];
+        self.0 = rest;
+        Some((tag, value, element))
+    }
+}
+
+fn expect(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match read(data)? {
+        (t, value, rest) if t == tag => Some((value, rest)),
+        _ => None,
+    }
+}
+
+/// Reads a DER element, returning its tag, its contents, and the data following it.
+fn read(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+
+    let (len, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        // Indefinite lengths are not valid DER.
+        if count == 0 || count > 4 {
+            return None;
+        }
+
+        let bytes = data.get(2..2 + count)?;
+        let len = bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, 2 + count)
+    };
+
+    let value = data.get(header..header.checked_add(len)?)?;
+    Some((tag, value, &data[header + len..]))
+}
@@ -0,0 +1,119 @@
+//! A common interface to boot loaders which boot Boot Loader Specification entries, so that
+//! consumers may support loaders other than systemd-boot without changing their call sites.
+
+use crate::efivars::EfiVars;
+use crate::entry::Entry;
+use crate::{Error, SystemdBootConf};
+use std::error::Error as StdError;
+
+/// The operations which updaters and installers perform on a boot loader.
+pub trait BootLoaderBackend {
+    type Error: StdError + Send + Sync + 'static;
+
+    /// The name of the boot loader, such as `systemd-boot`.
+    fn name(&self) -> &str;
+
+    /// The entries, in the order that the boot menu shows them.
+    fn entries(&self) -> Vec<&Entry>;
+
+    /// The id of the entry which is booted by default, as configured.
+    fn default_entry(&self) -> Option<&str>;
+
+    /// Boot this entry by default, and write the change.
+    fn set_default_entry(&mut self, id: &str) -> Result<(), Self::Error>;
+
+    /// The entry which will be booted once on the next boot, instead of the default.
+    fn oneshot_entry(&self) -> Result<Option<String>, Self::Error>;
+
+    /// Boot an entry once on the next boot, or clear the choice with `None`.
+    fn set_oneshot_entry(&mut self, id: Option<&str>) -> Result<(), Self::Error>;
+
+    /// Add an entry and write it, returning the id which it was given.
+    fn add_entry(&mut self, entry: Entry) -> Result<String, Self::Error>;
+
+    /// Remove an entry along with its file.
+    fn remove_entry(&mut self, id: &str) -> Result<Entry, Self::Error>;
+}
+
+/// systemd-boot, through its loader configuration and EFI variables.
+#[derive(Debug)]
+pub struct SystemdBoot {
+    pub conf: SystemdBootConf,
+    pub efivars: EfiVars,
+}
+
+impl SystemdBoot {
+    pub fn new(conf: SystemdBootConf, efivars: EfiVars) -> Self {
+        Self { conf, efivars }
+    }
+}
+
+impl BootLoaderBackend for SystemdBoot {
+    type Error = Error;
+
+    fn name(&self) -> &str {
+        "systemd-boot"
+    }
+
+    fn entries(&self) -> Vec<&Entry> {
+        self.conf.entries_in_boot_order()
+    }
+
+    fn default_entry(&self) -> Option<&str> {
+        self.conf.loader_conf.default.as_deref()
+    }
+
+    /// The id may also be the file name of a unified kernel image in `EFI/Linux`.
+    fn set_default_entry(&mut self, id: &str) -> Result<(), Error> {
+        let previous = self.conf.loader_conf.default.replace(id.to_owned());
+
+        if self.conf.resolve_default().is_none() {
+            self.conf.loader_conf.default = previous;
+            return Err(Error::NotFound);
+        }
+
+        self.conf.overwrite_loader_conf()
+    }
+
+    fn oneshot_entry(&self) -> Result<Option<String>, Error> {
+        self.efivars.oneshot_entry().map_err(Error::EfiVar)
+    }
+
+    /// systemd-boot refers to entries by their id without a boot counter.
+    fn set_oneshot_entry(&mut self, id: Option<&str>) -> Result<(), Error> {
+        let id = match id {
+            Some(id) => Some(
+                self.conf
+                    .entries
+                    .iter()
+                    .find(|entry| entry.id == id || entry.base_id() == id)
+                    .ok_or(Error::NotFound)?
+                    .base_id(),
+            ),
+            None => None,
+        };
+
+        self.efivars.set_oneshot_entry(id).map_err(Error::EfiVar)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<String, Error> {
+        let id = self.conf.add_entry(entry)?.id.clone();
+
+        let written = match self.conf.find(&id) {
+            Some(entry) => self.conf.write_entry_conf(entry),
+            None => Err(Error::NotFound),
+        };
+
+        // Leave the configuration as it is on disk if the entry could not be written.
+        if let Err(why) = written {
+            self.conf.remove_entry(&id)?;
+            return Err(why);
+        }
+
+        Ok(id)
+    }
+
+    fn remove_entry(&mut self, id: &str) -> Result<Entry, Error> {
+        self.conf.delete_entry(id)
+    }
+}
@@ -0,0 +1,191 @@
+//! Writing large numbers of entries, such as those generated for filesystem snapshots, without
+//! holding them all in memory.
+
+use crate::entry::{Entry, EntryKind};
+use crate::events::ChangeEvent;
+use crate::{Error, SystemdBootConf};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+/// The progress of a `BatchWriter`, given to its progress callback after each entry is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BatchProgress<'a> {
+    /// The number of entries which have been written so far.
+    pub written: usize,
+    /// The id of the entry which was just written.
+    pub id: &'a str,
+}
+
+/// A callback which is given the progress of a `BatchWriter`.
+type ProgressCallback<'a> = Box<dyn Fn(&BatchProgress) + Send + Sync + 'a>;
+
+/// The entries written by a `BatchWriter`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BatchReport {
+    pub written: usize,
+    /// The size of the entry files which were written, in bytes.
+    pub bytes: u64,
+}
+
+/// Streams entries from an iterator to the entries directory, created by
+/// `SystemdBootConf::batch_writer`.
+///
+/// Each entry is named, given microcode, and validated as `add_entry` would, then written and
+/// dropped, so that memory use does not grow with the number of entries. The entries are not
+/// added to the configuration in memory; call `load_entries` to load them afterwards.
+pub struct BatchWriter<'a> {
+    conf: &'a SystemdBootConf,
+    threads: usize,
+    progress: Option<ProgressCallback<'a>>,
+}
+
+impl fmt::Debug for BatchWriter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchWriter")
+            .field("threads", &self.threads)
+            .field("progress", &self.progress.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl<'a> BatchWriter<'a> {
+    pub(crate) fn new(conf: &'a SystemdBootConf) -> Self {
+        Self {
+            conf,
+            threads: 1,
+            progress: None,
+        }
+    }
+
+    /// Write entries from this many threads at once. Entries are written in order by a single
+    /// thread by default.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Call this function after each entry is written. With several threads, it may be called
+    /// from any of them, and progress may be reported out of order.
+    pub fn progress<F: Fn(&BatchProgress) + Send + Sync + 'a>(mut self, callback: F) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Write every entry of the iterator, stopping at the first which fails.
+    ///
+    /// Entries whose ids are already loaded are refused, while entry files of the same id which
+    /// were not loaded are overwritten. The entries which were written before a failure remain.
+    pub fn write<I>(&self, entries: I) -> Result<BatchReport, Error>
+    where
+        I: IntoIterator<Item = Entry>,
+        I::IntoIter: Send,
+    {
+        let entries = Mutex::new(entries.into_iter());
+        let written = AtomicUsize::new(0);
+        let bytes = AtomicU64::new(0);
+        let stop = AtomicBool::new(false);
+        let failure = Mutex::new(None);
+
+        let worker = || {
+            while !stop.load(Ordering::SeqCst) {
+                let next = entries
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .next();
+
+                let entry = match next {
+                    Some(entry) => entry,
+                    None => return,
+                };
+
+                match self.write_entry(entry) {
+                    Ok((id, len)) => {
+                        let count = written.fetch_add(1, Ordering::SeqCst) + 1;
+                        bytes.fetch_add(len, Ordering::SeqCst);
+                        if let Some(ref progress) = self.progress {
+                            progress(&BatchProgress {
+                                written: count,
+                                id: &id,
+                            });
+                        }
+                    }
+                    Err(why) => {
+                        stop.store(true, Ordering::SeqCst);
+                        failure
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .get_or_insert(why);
+                    }
+                }
+            }
+        };
+
+        if self.threads == 1 {
+            worker();
+        } else {
+            thread::scope(|scope| {
+                for _ in 0..self.threads {
+                    scope.spawn(worker);
+                }
+            });
+        }
+
+        if let Some(why) = failure.into_inner().unwrap_or_else(PoisonError::into_inner) {
+            return Err(why);
+        }
+
+        let report = BatchReport {
+            written: written.into_inner(),
+            bytes: bytes.into_inner(),
+        };
+
+        info!(
+            "wrote {} entries to {:?}",
+            report.written, self.conf.entries_path
+        );
+
+        Ok(report)
+    }
+
+    /// Writes an entry, returning its id and the size of its file.
+    fn write_entry(&self, mut entry: Entry) -> Result<(String, u64), Error> {
+        let conf = self.conf;
+        let path = |entry: &Entry| conf.entries_path.join(entry.conf_file_name());
+
+        entry.id = conf
+            .naming_scheme
+            .id_for(&entry)
+            .map_err(|source| Error::Entry {
+                path: path(&entry),
+                source,
+            })?;
+
+        if conf.attach_microcode && entry.kind() == EntryKind::Linux {
+            entry.attach_microcode(conf.microcode_images(&entry));
+        }
+
+        conf.check_id_unused(&entry.id)?;
+
+        let path = path(&entry);
+        if let Err(source) = entry.validate() {
+            return Err(Error::Entry { path, source });
+        }
+
+        let mut len = 0;
+        let result = conf.try_io(&path, |file| {
+            crate::format::render_entry_conf(&entry, &conf.write_style, file)?;
+            len = file.len() as u64;
+            Ok(())
+        });
+
+        result.map_err(|source| Error::EntryWrite { path, source }.classify())?;
+
+        conf.notify(ChangeEvent::EntryAdded {
+            id: entry.id.clone(),
+        });
+
+        Ok((entry.id, len))
+    }
+}
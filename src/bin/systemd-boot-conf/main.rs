@@ -0,0 +1,305 @@
+extern crate systemd_boot_conf;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+use std::env;
+use std::process::exit;
+use std::time::Duration;
+use systemd_boot_conf::efivars::EfiVars;
+use systemd_boot_conf::entry::{BootCounter, Entry};
+use systemd_boot_conf::{cmdline, reboot};
+use systemd_boot_conf::{DefaultState, Error, SystemdBootConf};
+
+const DEFAULT_ESP: &str = "/boot/efi";
+
+const USAGE: &str = "usage: systemd-boot-conf [--esp PATH] COMMAND
+
+commands:
+  list                              list the loader entries
+  get-default                       print the id of the default entry
+  set-default ID                    make an entry, or a UKI in EFI/Linux, the default
+  set-timeout SECONDS               set the menu timeout
+  add-entry ID TITLE LINUX [OPTIONS]
+      [--initrd PATH]...            add an entry with a kernel and optional initrds
+  remove-entry ID                   remove an entry and its entry file
+  lint                              report likely mistakes in the configuration
+  reboot-to-menu SECONDS            show the boot menu for a while on the next boot
+  select                            choose the default or boot once entry from a menu
+  help                              print this message
+
+The ESP is assumed to be mounted at /boot/efi unless --esp is given.";
+
+pub fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+
+    let esp = match take_flag(&mut args, "--esp") {
+        Ok(esp) => esp.unwrap_or_else(|| DEFAULT_ESP.into()),
+        Err(why) => usage_error(&why),
+    };
+
+    let command = match args.first() {
+        Some(command) => command.clone(),
+        None => usage_error("no command given"),
+    };
+
+    let args = &mut args[1..].to_vec();
+
+    let result = match command.as_str() {
+        "list" => no_args(args).and_then(|_| list(&esp)),
+        "get-default" => no_args(args).and_then(|_| get_default(&esp)),
+        "set-default" => one_arg(args).and_then(|id| set_default(&esp, &id)),
+        "set-timeout" => one_arg(args).and_then(|timeout| set_timeout(&esp, &timeout)),
+        "add-entry" => add_entry(&esp, args),
+        "remove-entry" => one_arg(args).and_then(|id| remove_entry(&esp, &id)),
+        "lint" => no_args(args).and_then(|_| lint(&esp)),
+        "reboot-to-menu" => one_arg(args).and_then(|timeout| reboot_to_menu(&timeout)),
+        "select" => no_args(args).and_then(|_| select(&esp)),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => usage_error(&format!("unknown command: {}", other)),
+    };
+
+    if let Err(why) = result {
+        eprintln!("systemd-boot-conf: {}", why);
+        exit(1);
+    }
+}
+
+fn list(esp: &str) -> Result<(), String> {
+    let manager = load(esp)?;
+
+    for entry in &manager.entries {
+        let mut markers = String::new();
+        if manager.is_default(entry) {
+            markers.push_str(" (default)");
+        }
+
+        if entry.is_current() {
+            markers.push_str(" (current)");
+        }
+
+        if entry.is_foreign() {
+            markers.push_str(" (other architecture)");
+        }
+
+        if entry.protection().is_some() {
+            markers.push_str(" (protected)");
+        }
+
+        println!("{}{}\n  title: {}", entry.id, markers, entry.title);
+
+        if let Some(ref version) = entry.version {
+            println!("  version: {}", version);
+        }
+
+        if !entry.linux.is_empty() {
+            println!("  linux: {}", entry.linux);
+        }
+
+        if let Some(ref efi) = entry.efi {
+            println!("  efi: {}", efi);
+        }
+
+        for initrd in &entry.initrd {
+            println!("  initrd: {}", initrd);
+        }
+
+        if let Some(ref devicetree) = entry.devicetree {
+            println!("  devicetree: {}", devicetree);
+        }
+
+        if !entry.options.is_empty() {
+            println!("  options: {}", cmdline::join(&entry.options));
+        }
+    }
+
+    Ok(())
+}
+
+fn get_default(esp: &str) -> Result<(), String> {
+    let manager = load(esp)?;
+
+    match manager.default_entry_exists() {
+        DefaultState::Exists => {
+            println!("{}", manager.loader_conf.default.unwrap_or_default());
+            Ok(())
+        }
+        DefaultState::DoesNotExist => Err(format!(
+            "default entry {} does not exist",
+            manager.loader_conf.default.unwrap_or_default()
+        )),
+        DefaultState::NotDefined => Err("no default entry is defined".into()),
+    }
+}
+
+fn set_default(esp: &str, id: &str) -> Result<(), String> {
+    let mut manager = load(esp)?;
+
+    // systemd-boot matches the default without the boot counter, which changes on every boot.
+    let id = BootCounter::parse(id).map_or(id, |(base, _)| base);
+    manager.loader_conf.default = Some(id.into());
+
+    // The default may also be the file name of a UKI in EFI/Linux.
+    if manager.resolve_default().is_none() {
+        return Err(format!("entry {} does not exist", id));
+    }
+
+    manager.overwrite_loader_conf().map_err(report)
+}
+
+fn set_timeout(esp: &str, timeout: &str) -> Result<(), String> {
+    let timeout = timeout
+        .parse::<u32>()
+        .map_err(|_| format!("timeout is not a number of seconds: {}", timeout))?;
+
+    let mut manager = load(esp)?;
+    manager.loader_conf.timeout = Some(timeout);
+    manager.loader_conf.menu = None;
+    manager.overwrite_loader_conf().map_err(report)
+}
+
+fn reboot_to_menu(timeout: &str) -> Result<(), String> {
+    let timeout = timeout
+        .parse::<u64>()
+        .map_err(|_| format!("timeout is not a number of seconds: {}", timeout))?;
+
+    reboot::request_boot_menu(Duration::from_secs(timeout), &EfiVars::new())
+        .map(|_| println!("the boot menu will be shown on the next boot"))
+        .map_err(|why| format!("failed to request the boot menu: {}", why))
+}
+
+fn add_entry(esp: &str, args: &mut Vec<String>) -> Result<(), String> {
+    let mut initrds = Vec::new();
+    while let Some(initrd) = take_flag(args, "--initrd")? {
+        initrds.push(initrd);
+    }
+
+    let (id, title, linux, options) = match args.as_slice() {
+        [id, title, linux] => (id, title, linux, None),
+        [id, title, linux, options] => (id, title, linux, Some(options)),
+        _ => usage_error("add-entry requires an id, title, linux path, and optional options"),
+    };
+
+    let mut entry = Entry::new(id.as_str(), title.as_str(), linux.as_str())
+        .map_err(|why| format!("invalid entry: {}", why))?;
+
+    entry
+        .set_initrds(initrds)
+        .map_err(|why| format!("invalid entry: {}", why))?;
+
+    if let Some(options) = options {
+        entry
+            .set_options(cmdline::split(options))
+            .map_err(|why| format!("invalid entry: {}", why))?;
+    }
+
+    let mut manager = load(esp)?;
+    let entry = manager.add_entry(entry).map_err(report)?.clone();
+    let id = entry
+        .entry_id()
+        .map_err(|why| format!("invalid entry: {}", why))?;
+    manager.overwrite_entry_conf(&id).map_err(report)?;
+
+    if let Ok(Some(false)) = manager.is_efi_stub(&entry) {
+        eprintln!(
+            "systemd-boot-conf: warning: {} is not an EFI-stub kernel, which systemd-boot cannot boot",
+            entry.linux
+        );
+    }
+
+    Ok(())
+}
+
+fn remove_entry(esp: &str, id: &str) -> Result<(), String> {
+    let mut manager = load(esp)?;
+    manager.delete_entry(id).map(|_| ()).map_err(report)
+}
+
+#[cfg(feature = "tui")]
+fn select(esp: &str) -> Result<(), String> {
+    tui::run(esp)
+}
+
+#[cfg(not(feature = "tui"))]
+fn select(_esp: &str) -> Result<(), String> {
+    Err("select requires systemd-boot-conf to be built with the tui feature".into())
+}
+
+fn lint(esp: &str) -> Result<(), String> {
+    for lint in load(esp)?.lint() {
+        println!("{}", lint);
+    }
+
+    Ok(())
+}
+
+fn load(esp: &str) -> Result<SystemdBootConf, String> {
+    SystemdBootConf::new(esp).map_err(report)
+}
+
+/// Formats an error of the library, followed by a hint on how to resolve it.
+fn report(error: Error) -> String {
+    let hint = error.hint();
+    let mut message = describe(error);
+
+    if let Some(hint) = hint {
+        message.push_str("\nhint: ");
+        message.push_str(hint);
+    }
+
+    message
+}
+
+/// Formats an error along with each of its causes.
+fn describe<E: std::error::Error>(error: E) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+
+    while let Some(why) = source {
+        message.push_str(": ");
+        message.push_str(&why.to_string());
+        source = why.source();
+    }
+
+    message
+}
+
+/// Removes a `--flag VALUE` pair from the arguments, returning the value.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let pos = match args.iter().position(|arg| arg == flag) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+
+    if pos + 1 >= args.len() {
+        return Err(format!("{} requires a value", flag));
+    }
+
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+fn no_args(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        usage_error(&format!("unexpected argument: {}", args[0]))
+    }
+}
+
+fn one_arg(args: &[String]) -> Result<String, String> {
+    match args {
+        [arg] => Ok(arg.clone()),
+        [] => usage_error("missing argument"),
+        _ => usage_error(&format!("unexpected argument: {}", args[1])),
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("systemd-boot-conf: {}\n\n{}", message, USAGE);
+    exit(2);
+}
@@ -0,0 +1,178 @@
+//! An interactive menu for choosing the default entry, or an entry to boot once.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use systemd_boot_conf::efivars::EfiVars;
+use systemd_boot_conf::{DefaultTarget, SystemdBootConf};
+
+const HELP: &str = "up/down: move  enter: set default  o: boot once  c: clear boot once  q: quit";
+
+enum Key {
+    Up,
+    Down,
+    Enter,
+    Char(char),
+    Escape,
+    Other,
+}
+
+/// Puts the terminal in raw mode, and restores it when dropped.
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn new() -> io::Result<Self> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return Err(io::Error::other("standard input is not a terminal"));
+            }
+
+            let mut original = mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Switch to the alternate screen, and hide the cursor.
+            print!("\x1b[?1049h\x1b[?25l");
+            io::stdout().flush()?;
+
+            Ok(RawTerminal { original })
+        }
+    }
+
+    fn read_key(&self) -> io::Result<Key> {
+        // Escape sequences arrive together, so a lone escape byte is the escape key.
+        let mut buffer = [0u8; 8];
+        let read = io::stdin().read(&mut buffer)?;
+
+        Ok(match &buffer[..read] {
+            b"\x1b[A" | b"\x1bOA" | b"k" => Key::Up,
+            b"\x1b[B" | b"\x1bOB" | b"j" => Key::Down,
+            b"\r" | b"\n" => Key::Enter,
+            b"\x1b" | b"\x03" => Key::Escape,
+            [byte] if byte.is_ascii() => Key::Char(*byte as char),
+            _ => Key::Other,
+        })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+pub fn run(esp: &str) -> Result<(), String> {
+    let mut manager = SystemdBootConf::new(esp).map_err(crate::report)?;
+    let efivars = EfiVars::new();
+
+    let entries = manager
+        .entries_in_boot_order()
+        .into_iter()
+        .map(|entry| {
+            (
+                entry.id.clone(),
+                entry.base_id().to_owned(),
+                entry.title.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        return Err("there are no entries to choose from".into());
+    }
+
+    // systemd-boot reports the booted entry, which is more reliable than matching the cmdline.
+    let selected = efivars.selected_entry().ok().flatten();
+    let current = manager.current_entry().map(|entry| entry.id.clone());
+    let is_current = |id: &str, base_id: &str| match selected {
+        Some(ref selected) => selected == base_id,
+        None => current.as_deref() == Some(id),
+    };
+
+    let is_default = |manager: &SystemdBootConf, id: &str| matches!(manager.resolve_default(), Some(DefaultTarget::Entry(entry)) if entry.id == id);
+
+    let mut oneshot = efivars.oneshot_entry().ok().flatten();
+    let mut cursor = entries
+        .iter()
+        .position(|(id, _, _)| is_default(&manager, id))
+        .unwrap_or(0);
+    let mut status = String::new();
+
+    let terminal = RawTerminal::new().map_err(|why| format!("cannot start menu: {}", why))?;
+
+    loop {
+        let mut screen = String::from("\x1b[2J\x1b[Hsystemd-boot entries\r\n\r\n");
+
+        for (pos, (id, base_id, title)) in entries.iter().enumerate() {
+            let pointer = if pos == cursor { ">" } else { " " };
+            let mut markers = String::new();
+
+            if is_default(&manager, id) {
+                markers.push_str(" [default]");
+            }
+
+            if oneshot.as_deref() == Some(base_id.as_str()) {
+                markers.push_str(" [boot once]");
+            }
+
+            if is_current(id, base_id) {
+                markers.push_str(" [current]");
+            }
+
+            screen.push_str(&format!("{} {} ({}){}\r\n", pointer, title, id, markers));
+        }
+
+        screen.push_str(&format!("\r\n{}\r\n{}\r\n", HELP, status));
+        print!("{}", screen);
+        io::stdout().flush().map_err(|why| why.to_string())?;
+
+        let key = terminal.read_key().map_err(|why| why.to_string())?;
+        let (id, base_id, _) = &entries[cursor];
+
+        status = match key {
+            Key::Up => {
+                cursor = cursor.checked_sub(1).unwrap_or(entries.len() - 1);
+                continue;
+            }
+            Key::Down => {
+                cursor = (cursor + 1) % entries.len();
+                continue;
+            }
+            Key::Enter => {
+                manager.loader_conf.default = Some(base_id.clone());
+                match manager.overwrite_loader_conf() {
+                    Ok(()) => format!("{} is now the default", id),
+                    Err(why) => format!("failed to set default: {}", crate::describe(why)),
+                }
+            }
+            Key::Char('o') => match efivars.set_oneshot_entry(Some(base_id)) {
+                Ok(()) => {
+                    oneshot = Some(base_id.clone());
+                    format!("{} will be booted once on the next boot", id)
+                }
+                Err(why) => format!("failed to set boot once entry: {}", crate::describe(why)),
+            },
+            Key::Char('c') => match efivars.set_oneshot_entry(None) {
+                Ok(()) => {
+                    oneshot = None;
+                    "cleared the boot once entry".into()
+                }
+                Err(why) => format!("failed to clear boot once entry: {}", crate::describe(why)),
+            },
+            Key::Char('q') | Key::Escape => return Ok(()),
+            Key::Char(_) | Key::Other => continue,
+        };
+    }
+}
@@ -0,0 +1,289 @@
+//! A C interface to the loader configuration, for installers and firmware tools written in C or
+//! C++. The declarations are in `include/systemd-boot-conf.h`, which cbindgen generates from this
+//! module with `cbindgen.toml`.
+//!
+//! Functions which may fail return `0` on success and `-1` on failure, or a null pointer, after
+//! which `sbc_last_error` and `sbc_last_error_code` describe the failure on the same thread.
+//! Strings which are returned are owned by the caller, and freed with `sbc_string_free`.
+//!
+//! Every pointer which is passed must be valid, and strings must be NUL-terminated UTF-8. A
+//! configuration must not be used from several threads at once.
+
+#![allow(clippy::missing_safety_doc)]
+
+use crate::cmdline;
+use crate::entry::Entry;
+use crate::{Error, SystemdBootConf};
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(CString, CString)>> = const { RefCell::new(None) };
+}
+
+/// Load the configuration of the ESP mounted at `efi_mount`, or return null on failure.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_new(efi_mount: *const c_char) -> *mut SystemdBootConf {
+    guard(ptr::null_mut(), || {
+        let efi_mount = string(efi_mount)?;
+        let conf = SystemdBootConf::new(efi_mount).map_err(|why| report(&why, why.code()))?;
+        Ok(Box::into_raw(Box::new(conf)))
+    })
+}
+
+/// Free a configuration, which may be null.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_free(conf: *mut SystemdBootConf) {
+    if !conf.is_null() {
+        drop(Box::from_raw(conf));
+    }
+}
+
+/// The number of entries.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_entry_count(conf: *const SystemdBootConf) -> usize {
+    let conf = &*conf;
+    conf.entries.len()
+}
+
+/// The id of the entry at `index`, or null if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_entry_id(
+    conf: *const SystemdBootConf,
+    index: usize,
+) -> *mut c_char {
+    let conf = &*conf;
+    match conf.entries.get(index) {
+        Some(entry) => to_c(&entry.id),
+        None => ptr::null_mut(),
+    }
+}
+
+/// The title of the entry at `index`, or null if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_entry_title(
+    conf: *const SystemdBootConf,
+    index: usize,
+) -> *mut c_char {
+    let conf = &*conf;
+    match conf.entries.get(index) {
+        Some(entry) => to_c(&entry.title),
+        None => ptr::null_mut(),
+    }
+}
+
+/// The kernel options of the entry at `index` as a command line, separated by spaces and quoted
+/// where needed, or null if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_entry_options(
+    conf: *const SystemdBootConf,
+    index: usize,
+) -> *mut c_char {
+    entry_string(conf, index, |entry| Some(cmdline::join(&entry.options)))
+}
+
+/// The default entry of the loader conf, or null if it has none.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_default(conf: *const SystemdBootConf) -> *mut c_char {
+    let conf = &*conf;
+    match conf.loader_conf.default {
+        Some(ref default) => to_c(default),
+        None => ptr::null_mut(),
+    }
+}
+
+/// The timeout of the loader conf in seconds, or `-1` if it has none.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_timeout(conf: *const SystemdBootConf) -> i64 {
+    let conf = &*conf;
+    conf.loader_conf.timeout.map_or(-1, i64::from)
+}
+
+/// Boot the entry or UKI with this id by default, which takes effect once the loader conf is
+/// written with `sbc_conf_write_loader`.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_set_default(
+    conf: *mut SystemdBootConf,
+    id: *const c_char,
+) -> c_int {
+    let conf = &mut *conf;
+    status(|| {
+        let id = string(id)?;
+        let previous = conf.loader_conf.default.replace(id);
+
+        if conf.resolve_default().is_none() {
+            conf.loader_conf.default = previous;
+            return Err(report(&Error::NotFound, Error::NotFound.code()));
+        }
+
+        Ok(())
+    })
+}
+
+/// Show the menu for this many seconds, which takes effect once the loader conf is written with
+/// `sbc_conf_write_loader`.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_set_timeout(conf: *mut SystemdBootConf, seconds: u32) {
+    let conf = &mut *conf;
+    conf.loader_conf.timeout = Some(seconds);
+    conf.loader_conf.menu = None;
+}
+
+/// Write the loader conf.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_write_loader(conf: *const SystemdBootConf) -> c_int {
+    let conf = &*conf;
+    status(|| {
+        conf.overwrite_loader_conf()
+            .map_err(|why| report(&why, why.code()))
+    })
+}
+
+/// Write the loader conf and every entry into the ESP tree at `target`.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_conf_persist_to(
+    conf: *const SystemdBootConf,
+    target: *const c_char,
+) -> c_int {
+    let conf = &*conf;
+    status(|| {
+        let target = string(target)?;
+        conf.persist_to(target)
+            .map(drop)
+            .map_err(|why| report(&why, why.code()))
+    })
+}
+
+/// The message of the last failure on this thread, with its causes, or null if nothing failed.
+///
+/// The string is valid until the next call which fails on this thread, and is not freed.
+#[no_mangle]
+pub extern "C" fn sbc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |(message, _)| message.as_ptr())
+    })
+}
+
+/// The stable code of the last failure on this thread, such as `entry-not-found`, or null if
+/// nothing failed.
+///
+/// The string is valid until the next call which fails on this thread, and is not freed.
+#[no_mangle]
+pub extern "C" fn sbc_last_error_code() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |(_, code)| code.as_ptr())
+    })
+}
+
+/// Free a string which was returned by this library, which may be null.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// A failure which has been recorded as the last error.
+struct Reported;
+
+/// Record an error and its causes as the last error of this thread.
+fn report(error: &dyn StdError, code: &str) -> Reported {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(why) = source {
+        message.push_str(": ");
+        message.push_str(&why.to_string());
+        source = why.source();
+    }
+
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    let code = CString::new(code).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((message, code)));
+    Reported
+}
+
+/// Run a function, recording panics as errors rather than unwinding into C.
+fn guard<T, F: FnOnce() -> Result<T, Reported>>(failed: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(Reported)) => failed,
+        Err(_) => {
+            let why = std::io::Error::other("a panic occurred in systemd-boot-conf");
+            report(&why, "panic");
+            failed
+        }
+    }
+}
+
+fn status<F: FnOnce() -> Result<(), Reported>>(f: F) -> c_int {
+    guard(-1, || f().map(|()| 0))
+}
+
+unsafe fn string(string: *const c_char) -> Result<String, Reported> {
+    if string.is_null() {
+        let why = std::io::Error::new(std::io::ErrorKind::InvalidInput, "a string is null");
+        return Err(report(&why, "invalid-data"));
+    }
+
+    CStr::from_ptr(string)
+        .to_str()
+        .map(str::to_owned)
+        .map_err(|why| report(&why, "invalid-data"))
+}
+
+unsafe fn entry_string<F: FnOnce(&Entry) -> Option<String>>(
+    conf: *const SystemdBootConf,
+    index: usize,
+    field: F,
+) -> *mut c_char {
+    let conf = &*conf;
+    match conf.entries.get(index).and_then(field) {
+        Some(value) => to_c(&value),
+        None => ptr::null_mut(),
+    }
+}
+
+fn to_c(value: &str) -> *mut c_char {
+    CString::new(value.replace('\0', " "))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::{Filesystem, MemoryFilesystem};
+    use std::path::Path;
+
+    #[test]
+    fn entry_options_are_quoted() {
+        let fs = MemoryFilesystem::new();
+        fs.create_dir_all(Path::new("/efi/loader/entries")).unwrap();
+        let contents = "title Pop!_OS\nlinux /vmlinuz\noptions ro dyndbg=\"file foo.c +p\"\n";
+        fs.write(
+            Path::new("/efi/loader/entries/pop.conf"),
+            contents.as_bytes(),
+        )
+        .unwrap();
+
+        let conf = SystemdBootConf::builder("/efi")
+            .filesystem(fs)
+            .build()
+            .unwrap();
+
+        unsafe {
+            let options = sbc_conf_entry_options(&conf, 0);
+            let value = CStr::from_ptr(options).to_str().unwrap().to_owned();
+            sbc_string_free(options);
+            assert_eq!(value, "ro dyndbg=\"file foo.c +p\"");
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Splitting and quoting of kernel command lines, such as the `options` of an entry.
+
+use crate::luks::LuksVolume;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where kernel-install reads the command line for new entries, in order of precedence,
+/// relative to the root of the system.
+const KERNEL_CMDLINE_PATHS: &[&str] = &["etc/kernel/cmdline", "usr/lib/kernel/cmdline"];
+
+/// Split a command line into its arguments, as the kernel's `next_arg` does.
+///
+/// Arguments are separated by whitespace, except where it is enclosed in double quotes. A quote
+/// which opens an argument or its value is removed along with the quote which ends the argument;
+/// other quotes are kept, and single quotes are not special. An unterminated quote extends to the
+/// end of the line. Backslashes are not escapes, as they are common in EFI paths such as
+/// `initrd=\EFI\a`.
+pub fn split(cmdline: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rest = cmdline.trim_start_matches(is_space);
+
+    while !rest.is_empty() {
+        let (arg, next) = next_arg(rest);
+        args.push(arg);
+        rest = next.trim_start_matches(is_space);
+    }
+
+    args
+}
+
+/// Take the first argument from a command line which does not start with whitespace.
+fn next_arg(args: &str) -> (String, &str) {
+    let quoted = args.starts_with('"');
+    let args = if quoted { &args[1..] } else { args };
+
+    let mut in_quote = quoted;
+    let mut equals = None;
+    let mut end = args.len();
+    for (pos, c) in args.char_indices() {
+        if is_space(c) && !in_quote {
+            end = pos;
+            break;
+        }
+
+        // An `=` at the start does not separate a value, as the kernel uses 0 for none.
+        if equals.is_none() && c == '=' && pos != 0 {
+            equals = Some(pos);
+        }
+
+        if c == '"' {
+            in_quote = !in_quote;
+        }
+    }
+
+    let (arg, rest) = args.split_at(end);
+    let arg = match equals {
+        Some(pos) => {
+            let (key, value) = arg.split_at(pos + 1);
+            let value = match value.strip_prefix('"') {
+                Some(value) => strip_closing_quote(value),
+                None if quoted => strip_closing_quote(value),
+                None => value,
+            };
+
+            [key, value].concat()
+        }
+        None if quoted => strip_closing_quote(arg).to_owned(),
+        None => arg.to_owned(),
+    };
+
+    (arg, rest)
+}
+
+fn strip_closing_quote(value: &str) -> &str {
+    value.strip_suffix('"').unwrap_or(value)
+}
+
+/// Whitespace, as the kernel's `isspace` defines it.
+fn is_space(c: char) -> bool {
+    c == ' ' || ('\t'..='\r').contains(&c)
+}
+
+/// Quote an argument which contains whitespace or quotes, so that `split` returns it unchanged.
+///
+/// The value of a `key=value` argument is quoted on its own, which is the form the kernel
+/// documents. Only double quotes are understood by the kernel, so some arguments, such as those
+/// which start with a quote and contain whitespace, cannot be represented; check them with
+/// `is_representable`, as `Entry::validate` does.
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    if !arg.is_empty() && splits_to(arg, arg) {
+        return Cow::Borrowed(arg);
+    }
+
+    if let Some(pos) = arg.find('=').filter(|&pos| pos != 0) {
+        let (key, value) = arg.split_at(pos + 1);
+        let quoted = [key, "\"", value, "\""].concat();
+        if splits_to(&quoted, arg) {
+            return Cow::Owned(quoted);
+        }
+    }
+
+    Cow::Owned(["\"", arg, "\""].concat())
+}
+
+/// Whether `quote` gives a command line which the kernel parses back into this argument.
+pub fn is_representable(arg: &str) -> bool {
+    splits_to(&quote(arg), arg)
+}
+
+fn splits_to(cmdline: &str, arg: &str) -> bool {
+    let mut args = split(cmdline).into_iter();
+    args.next().as_deref() == Some(arg) && args.next().is_none()
+}
+
+/// Join arguments into a command line, quoting them where needed.
+pub fn join<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut cmdline = String::new();
+    for arg in args {
+        if !cmdline.is_empty() {
+            cmdline.push(' ');
+        }
+
+        cmdline.push_str(&quote(arg.as_ref()));
+    }
+
+    cmdline
+}
+
+/// A command line composed from typed options, which is given to `Entry::set_options`, or
+/// displayed as a quoted command line.
+///
+/// Options which take a single value, such as `root=`, replace any earlier value.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Cmdline {
+    args: Vec<String>,
+}
+
+impl Cmdline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from the arguments of an existing command line, such as `/etc/kernel/cmdline`.
+    pub fn parse(cmdline: &str) -> Self {
+        Self {
+            args: split(cmdline),
+        }
+    }
+
+    /// Mount the filesystem with this UUID as the root, as `root=UUID=<uuid>`.
+    pub fn root_uuid(self, uuid: &str) -> Self {
+        self.set("root", format!("UUID={}", uuid))
+    }
+
+    /// Mount this device as the root, such as `/dev/mapper/root`.
+    pub fn root(self, device: &str) -> Self {
+        self.set("root", device)
+    }
+
+    /// Mount the root read-write, in place of `ro`.
+    pub fn rw(self) -> Self {
+        self.remove("ro").flag("rw")
+    }
+
+    /// Mount the root read-only, in place of `rw`.
+    pub fn ro(self) -> Self {
+        self.remove("rw").flag("ro")
+    }
+
+    pub fn quiet(self) -> Self {
+        self.flag("quiet")
+    }
+
+    /// Boot quietly behind a splash screen, as desktop installations do.
+    pub fn quiet_splash(self) -> Self {
+        self.quiet().flag("splash")
+    }
+
+    /// Print kernel messages of this level and more severe to the console.
+    pub fn loglevel(self, level: u8) -> Self {
+        self.set("loglevel", level)
+    }
+
+    /// Unlock a LUKS volume in a systemd initrd, with `rd.luks.uuid=` and its name and options.
+    pub fn luks(mut self, volume: &LuksVolume) -> Self {
+        for arg in volume.args() {
+            if !self.args.contains(&arg) {
+                self.args.push(arg);
+            }
+        }
+
+        self
+    }
+
+    /// Unlock a LUKS volume in an initrd which predates systemd, with `cryptdevice=`.
+    pub fn cryptdevice(self, volume: &LuksVolume) -> Self {
+        self.remove("cryptdevice").flag(&volume.cryptdevice_arg())
+    }
+
+    /// Add an argument without a value, such as `nomodeset`, unless it is present.
+    pub fn flag(mut self, name: &str) -> Self {
+        if !self.args.iter().any(|arg| arg == name) {
+            self.args.push(name.to_owned());
+        }
+
+        self
+    }
+
+    /// Set an option to a value, in place of its first value, removing any others.
+    pub fn set<V: ToString>(mut self, key: &str, value: V) -> Self {
+        let arg = [key, "=", &value.to_string()].concat();
+
+        match self.args.iter().position(|arg| arg_key(arg) == key) {
+            Some(pos) => {
+                self.args[pos] = arg;
+                let mut first = true;
+                self.args.retain(|arg| {
+                    let keep = first || arg_key(arg) != key;
+                    first &= arg_key(arg) != key;
+                    keep
+                });
+            }
+            None => self.args.push(arg),
+        }
+
+        self
+    }
+
+    /// Add another value of an option which may be repeated, such as `console=`.
+    pub fn push<V: ToString>(mut self, key: &str, value: V) -> Self {
+        self.args.push([key, "=", &value.to_string()].concat());
+        self
+    }
+
+    /// Remove an option, with or without a value.
+    pub fn remove(mut self, key: &str) -> Self {
+        self.args.retain(|arg| arg_key(arg) != key);
+        self
+    }
+
+    /// The values of an option, in order.
+    pub fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.args
+            .iter()
+            .filter(move |arg| arg_key(arg) == key)
+            .filter_map(|arg| arg.split_once('=').map(|(_, value)| value))
+    }
+
+    /// The arguments, unquoted, as they are stored in `Entry::options`.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn into_args(self) -> Vec<String> {
+        self.args
+    }
+}
+
+impl fmt::Display for Cmdline {
+    /// The arguments joined into a command line, quoted where needed.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&join(&self.args))
+    }
+}
+
+impl IntoIterator for Cmdline {
+    type Item = String;
+    type IntoIter = std::vec::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.args.into_iter()
+    }
+}
+
+impl From<Cmdline> for Vec<String> {
+    fn from(cmdline: Cmdline) -> Self {
+        cmdline.args
+    }
+}
+
+/// The name of an argument, which is the part before its value.
+fn arg_key(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(key, _)| key)
+}
+
+/// Read the command line which kernel-install gives to new entries, from `/etc/kernel/cmdline`.
+pub fn load_kernel_cmdline() -> io::Result<Vec<String>> {
+    load_kernel_cmdline_from_root("/")
+}
+
+/// Read the command line which kernel-install gives to new entries of the system installed at
+/// `root`, from `etc/kernel/cmdline` or `usr/lib/kernel/cmdline`.
+pub fn load_kernel_cmdline_from_root<P: AsRef<Path>>(root: P) -> io::Result<Vec<String>> {
+    let root = root.as_ref();
+    let mut last_error = None;
+
+    for path in KERNEL_CMDLINE_PATHS {
+        match fs::read_to_string(root.join(path)) {
+            Ok(contents) => {
+                let lines = contents
+                    .lines()
+                    .filter(|line| !line.trim_start().starts_with('#'));
+                return Ok(lines.flat_map(split).collect());
+            }
+            Err(why) => last_error = Some(why),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotFound)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Command lines and the arguments that the kernel's `next_arg` splits them into.
+    const KERNEL_SPLITS: &[(&str, &[&str])] = &[
+        (
+            "root=UUID=1234 ro quiet",
+            &["root=UUID=1234", "ro", "quiet"],
+        ),
+        ("  quiet \t splash\n", &["quiet", "splash"]),
+        (
+            "param=\"spaces in here\" quiet",
+            &["param=spaces in here", "quiet"],
+        ),
+        ("\"param=spaces in here\"", &["param=spaces in here"]),
+        ("dyndbg=\"file foo.c +p\"", &["dyndbg=file foo.c +p"]),
+        ("\"quoted flag\" rw", &["quoted flag", "rw"]),
+        ("a\"b c\"d", &["a\"b c\"d"]),
+        ("foo=a\"b c\"", &["foo=a\"b c\""]),
+        ("foo=\"bar", &["foo=bar"]),
+        ("foo=\"bar baz", &["foo=bar baz"]),
+        ("foo=\"\" bar", &["foo=", "bar"]),
+        ("'single quoted'", &["'single", "quoted'"]),
+        ("=a=b", &["=a=b"]),
+        (
+            "initrd=\\EFI\\pop\\initrd.img",
+            &["initrd=\\EFI\\pop\\initrd.img"],
+        ),
+        ("\"\"", &[""]),
+    ];
+
+    #[test]
+    fn split_as_kernel() {
+        for (cmdline, args) in KERNEL_SPLITS {
+            assert_eq!(split(cmdline), *args, "splitting {:?}", cmdline);
+        }
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        let args = [
+            "quiet",
+            "root=UUID=1234",
+            "param=spaces in here",
+            "quoted flag",
+            "a\"b",
+            "foo=a\"b c\"",
+            "it's",
+            "=leading equals",
+            "a b\"",
+            "\"quoted\"",
+            "",
+        ];
+
+        for arg in args {
+            assert!(is_representable(arg), "quoting {:?}", arg);
+            assert_eq!(split(&quote(arg)), [arg], "quoting {:?}", arg);
+        }
+
+        assert_eq!(quote("quiet"), "quiet");
+        assert_eq!(quote("param=spaces in here"), "param=\"spaces in here\"");
+        assert_eq!(quote("quoted flag"), "\"quoted flag\"");
+        assert_eq!(join(["ro", "a b", "c=d e"]), "ro \"a b\" c=\"d e\"");
+    }
+
+    #[test]
+    fn unrepresentable() {
+        for arg in ["\"a b", "foo=\"a b"] {
+            assert!(!is_representable(arg), "quoting {:?}", arg);
+        }
+    }
+}
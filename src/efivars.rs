@@ -0,0 +1,574 @@
+//! Access to the EFI variables through which systemd-boot communicates with the running system.
+
+mod memory;
+
+pub use self::memory::MemoryEfiVarStore;
+
+use crate::esp_path::EspPath;
+use crate::ErrorKind;
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, str};
+
+/// Where the kernel exposes EFI variables.
+pub const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+
+/// The vendor GUID of the variables defined by the boot loader interface.
+pub const LOADER_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+
+/// The default entry, set with `bootctl set-default`, which takes precedence over `loader.conf`.
+pub const LOADER_ENTRY_DEFAULT: &str = "LoaderEntryDefault";
+
+/// The entry to boot once on the next boot, after which it is removed by systemd-boot.
+pub const LOADER_ENTRY_ONESHOT: &str = "LoaderEntryOneShot";
+
+/// Show the boot menu for this many seconds on the next boot, after which it is removed by
+/// systemd-boot.
+pub const LOADER_CONFIG_TIMEOUT_ONESHOT: &str = "LoaderConfigTimeoutOneShot";
+
+/// The entry which was booted, set by systemd-boot.
+pub const LOADER_ENTRY_SELECTED: &str = "LoaderEntrySelected";
+
+/// The partition UUID of the ESP that the boot loader was executed from.
+pub const LOADER_DEVICE_PART_UUID: &str = "LoaderDevicePartUUID";
+
+/// The name and version of the boot loader, such as `systemd-boot 255`.
+pub const LOADER_INFO: &str = "LoaderInfo";
+
+/// The vendor and version of the firmware, such as `EDK II 1.00`.
+pub const LOADER_FIRMWARE_INFO: &str = "LoaderFirmwareInfo";
+
+/// The path on the ESP of the boot loader, or of the UKI when booted without a boot loader.
+pub const LOADER_IMAGE_IDENTIFIER: &str = "LoaderImageIdentifier";
+
+/// The path on the ESP of the UKI that systemd-stub was executed from.
+pub const STUB_IMAGE_IDENTIFIER: &str = "StubImageIdentifier";
+
+/// The PCR into which systemd-stub measured the UKI's sections.
+pub const STUB_PCR_KERNEL_IMAGE: &str = "StubPcrKernelImage";
+
+/// The PCR into which systemd-stub measured the kernel command line.
+pub const STUB_PCR_KERNEL_PARAMETERS: &str = "StubPcrKernelParameters";
+
+/// The PCR into which systemd-stub measured the system extension images for the initrd.
+pub const STUB_PCR_INITRD_SYSEXTS: &str = "StubPcrInitRDSysExts";
+
+/// The PCR into which systemd-stub measured the configuration extension images for the initrd.
+pub const STUB_PCR_INITRD_CONFEXTS: &str = "StubPcrInitRDConfExts";
+
+/// The TPM2 PCR banks which were active while booting, as a bitmask of `EFI_TCG2_BOOT_HASH_ALG_*`.
+pub const LOADER_TPM2_ACTIVE_PCR_BANKS: &str = "LoaderTpm2ActivePcrBanks";
+
+/// A random token which systemd-boot mixes into the random seed that it passes to the kernel, so
+/// that systems booting from identical images receive different seeds.
+pub const LOADER_SYSTEM_TOKEN: &str = "LoaderSystemToken";
+
+/// The size of the system token and of the random seed file, as bootctl writes them.
+pub const RANDOM_SEED_SIZE: usize = 32;
+
+const NON_VOLATILE: u32 = 0x1;
+const BOOTSERVICE_ACCESS: u32 = 0x2;
+const RUNTIME_ACCESS: u32 = 0x4;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum EfiVarError {
+    #[error("EFI variable {} is not a valid number", _0)]
+    InvalidNumber(String),
+    #[error("EFI variable {} is not a valid UTF-16 string", _0)]
+    InvalidString(String),
+    #[error("error reading EFI variable {}", name)]
+    Read { name: String, source: io::Error },
+    #[error("error removing EFI variable {}", name)]
+    Remove { name: String, source: io::Error },
+    #[error("EFI variable {} is too short to have attributes", _0)]
+    TooShort(String),
+    #[error("EFI variables of the running system require the efivarfs feature")]
+    Unsupported,
+    #[error("error writing EFI variable {}", name)]
+    Write { name: String, source: io::Error },
+}
+
+impl EfiVarError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            EfiVarError::Read { source, .. }
+            | EfiVarError::Remove { source, .. }
+            | EfiVarError::Write { source, .. } => ErrorKind::from_io(source),
+            EfiVarError::InvalidNumber(_)
+            | EfiVarError::InvalidString(_)
+            | EfiVarError::TooShort(_) => ErrorKind::InvalidData,
+            EfiVarError::Unsupported => ErrorKind::Unsupported,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// Whether `EfiVars::ensure_system_token` found a token, or wrote one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SystemToken {
+    /// A token of sufficient size was already set, and was kept.
+    Present,
+    /// A new token was generated and written.
+    Written,
+}
+
+/// The PCRs into which systemd-stub measured the parts of the boot, as reported by its
+/// variables. A PCR is `None` if that part was not measured, such as when there is no TPM.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StubMeasurements {
+    pub kernel_image: Option<u32>,
+    pub kernel_parameters: Option<u32>,
+    pub initrd_sysexts: Option<u32>,
+    pub initrd_confexts: Option<u32>,
+}
+
+impl StubMeasurements {
+    /// Whether both the kernel and its command line were measured, so that policies bound to
+    /// those PCRs are meaningful.
+    pub fn kernel_measured(&self) -> bool {
+        self.kernel_image.is_some() && self.kernel_parameters.is_some()
+    }
+}
+
+/// Where EFI variables are stored, so that the logic of `EfiVars` may be tested without
+/// firmware.
+pub trait EfiVarStore: Debug + Send + Sync {
+    /// Whether any variables may be accessed, which they may when the system was booted with EFI.
+    fn is_available(&self) -> bool;
+
+    /// The attributes and data of a variable, if it is defined.
+    fn read(&self, name: &str, guid: &str) -> Result<Option<(u32, Vec<u8>)>, EfiVarError>;
+
+    /// Define a variable, replacing any existing value.
+    fn write(
+        &self,
+        name: &str,
+        guid: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<(), EfiVarError>;
+
+    /// Remove a variable, if it is defined.
+    fn remove(&self, name: &str, guid: &str) -> Result<(), EfiVarError>;
+}
+
+/// The variables in an efivarfs mount, used by default.
+#[derive(Debug, Clone)]
+pub struct EfivarfsStore {
+    /// The efivarfs mount, which is unknown without the `efivarfs` feature.
+    path: Option<PathBuf>,
+}
+
+impl EfivarfsStore {
+    /// The variables of the running system.
+    ///
+    /// Without the `efivarfs` feature, there are none, and every access fails with
+    /// `EfiVarError::Unsupported`.
+    pub fn new() -> Self {
+        let path = if cfg!(feature = "efivarfs") {
+            Some(PathBuf::from(EFIVARS_PATH))
+        } else {
+            None
+        };
+
+        Self { path }
+    }
+
+    /// The variables in another efivarfs mount, or a directory laid out like one.
+    pub fn with_path<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    fn var_path(&self, name: &str, guid: &str) -> Result<PathBuf, EfiVarError> {
+        match self.path {
+            Some(ref path) => Ok(path.join([name, "-", guid].concat())),
+            None => Err(EfiVarError::Unsupported),
+        }
+    }
+}
+
+impl Default for EfivarfsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EfiVarStore for EfivarfsStore {
+    fn is_available(&self) -> bool {
+        self.path.as_ref().is_some_and(|path| path.is_dir())
+    }
+
+    fn read(&self, name: &str, guid: &str) -> Result<Option<(u32, Vec<u8>)>, EfiVarError> {
+        let data = match fs::read(self.var_path(name, guid)?) {
+            Ok(data) => data,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(EfiVarError::Read {
+                    name: name.into(),
+                    source,
+                })
+            }
+        };
+
+        if data.len() < 4 {
+            return Err(EfiVarError::TooShort(name.into()));
+        }
+
+        let attributes = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        Ok(Some((attributes, data[4..].to_vec())))
+    }
+
+    fn write(
+        &self,
+        name: &str,
+        guid: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<(), EfiVarError> {
+        let path = self.var_path(name, guid)?;
+        let error = |source| EfiVarError::Write {
+            name: name.into(),
+            source,
+        };
+
+        // The whole variable must be written at once, as efivarfs treats each write as a call to
+        // SetVariable.
+        let mut contents = attributes.to_le_bytes().to_vec();
+        contents.extend_from_slice(data);
+
+        if path.exists() {
+            make_mutable(&path).map_err(error)?;
+        }
+
+        fs::write(&path, &contents).map_err(error)?;
+        info!("wrote EFI variable {}", name);
+        Ok(())
+    }
+
+    fn remove(&self, name: &str, guid: &str) -> Result<(), EfiVarError> {
+        let path = self.var_path(name, guid)?;
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let error = |source| EfiVarError::Remove {
+            name: name.into(),
+            source,
+        };
+
+        make_mutable(&path).map_err(error)?;
+        fs::remove_file(&path).map_err(error)?;
+        info!("removed EFI variable {}", name);
+        Ok(())
+    }
+}
+
+/// Reads and writes the EFI variables of the boot loader interface, through efivarfs by default.
+#[derive(Debug, Clone)]
+pub struct EfiVars {
+    store: Arc<dyn EfiVarStore>,
+}
+
+impl Default for EfiVars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EfiVars {
+    /// Access the variables of the running system.
+    ///
+    /// Without the `efivarfs` feature, there are none, and every access fails with
+    /// `EfiVarError::Unsupported`.
+    pub fn new() -> Self {
+        Self::with_store(EfivarfsStore::new())
+    }
+
+    /// Access variables in another efivarfs mount, or a directory laid out like one.
+    pub fn with_path<P: Into<PathBuf>>(path: P) -> Self {
+        Self::with_store(EfivarfsStore::with_path(path))
+    }
+
+    /// Access variables in another store, such as a `MemoryEfiVarStore` in tests.
+    pub fn with_store<S: EfiVarStore + 'static>(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// The store which variables are accessed through.
+    pub fn store(&self) -> &dyn EfiVarStore {
+        &*self.store
+    }
+
+    /// Whether the system was booted with EFI, which is when efivarfs is available.
+    pub fn is_available(&self) -> bool {
+        self.store.is_available()
+    }
+
+    /// The attributes and data of a variable, if it is defined.
+    pub fn read(&self, name: &str, guid: &str) -> Result<Option<(u32, Vec<u8>)>, EfiVarError> {
+        self.store.read(name, guid)
+    }
+
+    /// Define a variable, replacing any existing value.
+    pub fn write(
+        &self,
+        name: &str,
+        guid: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<(), EfiVarError> {
+        self.store.write(name, guid, attributes, data)
+    }
+
+    /// Remove a variable, if it is defined.
+    pub fn remove(&self, name: &str, guid: &str) -> Result<(), EfiVarError> {
+        self.store.remove(name, guid)
+    }
+
+    /// A string variable of the boot loader interface, which are NUL-terminated UTF-16.
+    pub fn loader_string(&self, name: &str) -> Result<Option<String>, EfiVarError> {
+        let data = match self.read(name, LOADER_GUID)? {
+            Some((_, data)) => data,
+            None => return Ok(None),
+        };
+
+        let units = data
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect::<Vec<_>>();
+
+        String::from_utf16(&units)
+            .map(Some)
+            .map_err(|_| EfiVarError::InvalidString(name.into()))
+    }
+
+    /// Define a string variable of the boot loader interface.
+    pub fn set_loader_string(&self, name: &str, value: &str) -> Result<(), EfiVarError> {
+        let data = value
+            .encode_utf16()
+            .chain(Some(0))
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+
+        let attributes = NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS;
+        self.write(name, LOADER_GUID, attributes, &data)
+    }
+
+    /// The default entry set in EFI variables, which takes precedence over `loader.conf`.
+    pub fn default_entry(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_ENTRY_DEFAULT)
+    }
+
+    /// The entry that will be booted once on the next boot, if one was chosen.
+    pub fn oneshot_entry(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_ENTRY_ONESHOT)
+    }
+
+    /// Boot an entry once on the next boot, or clear the choice with `None`.
+    pub fn set_oneshot_entry(&self, entry: Option<&str>) -> Result<(), EfiVarError> {
+        match entry {
+            Some(entry) => self.set_loader_string(LOADER_ENTRY_ONESHOT, entry),
+            None => self.remove(LOADER_ENTRY_ONESHOT, LOADER_GUID),
+        }
+    }
+
+    /// The seconds for which the boot menu will be shown on the next boot, if it was requested.
+    pub fn menu_timeout_oneshot(&self) -> Result<Option<u32>, EfiVarError> {
+        self.loader_number(LOADER_CONFIG_TIMEOUT_ONESHOT)
+    }
+
+    /// Show the boot menu for this many seconds on the next boot, or withdraw the request with
+    /// `None`.
+    pub fn set_menu_timeout_oneshot(&self, secs: Option<u32>) -> Result<(), EfiVarError> {
+        match secs {
+            Some(secs) => self.set_loader_string(LOADER_CONFIG_TIMEOUT_ONESHOT, &secs.to_string()),
+            None => self.remove(LOADER_CONFIG_TIMEOUT_ONESHOT, LOADER_GUID),
+        }
+    }
+
+    /// The id of the entry that systemd-boot booted, without its boot counter.
+    pub fn selected_entry(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_ENTRY_SELECTED)
+    }
+
+    /// The name and version of the boot loader that booted the system.
+    pub fn loader_info(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_INFO)
+    }
+
+    /// The vendor and version of the firmware, as reported by the boot loader.
+    pub fn firmware_info(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_FIRMWARE_INFO)
+    }
+
+    /// The path on the ESP of the image which the firmware executed, which is the boot loader,
+    /// or a UKI that was booted directly.
+    ///
+    /// Use `EspPath::to_path` to find the image where the ESP is mounted.
+    pub fn loader_image(&self) -> Result<Option<EspPath>, EfiVarError> {
+        self.loader_string(LOADER_IMAGE_IDENTIFIER)
+            .map(|image| image.map(|image| EspPath::new(&image)))
+    }
+
+    /// The path on the ESP of the UKI that was booted, if systemd-stub was used.
+    pub fn stub_image(&self) -> Result<Option<EspPath>, EfiVarError> {
+        self.loader_string(STUB_IMAGE_IDENTIFIER)
+            .map(|image| image.map(|image| EspPath::new(&image)))
+    }
+
+    /// The partition UUID of the ESP that the system was booted from, in lowercase.
+    pub fn loader_partition_uuid(&self) -> Result<Option<String>, EfiVarError> {
+        self.loader_string(LOADER_DEVICE_PART_UUID)
+            .map(|uuid| uuid.map(|uuid| uuid.to_ascii_lowercase()))
+    }
+
+    /// The PCRs that systemd-stub measured into on this boot.
+    pub fn stub_measurements(&self) -> Result<StubMeasurements, EfiVarError> {
+        Ok(StubMeasurements {
+            kernel_image: self.loader_number(STUB_PCR_KERNEL_IMAGE)?,
+            kernel_parameters: self.loader_number(STUB_PCR_KERNEL_PARAMETERS)?,
+            initrd_sysexts: self.loader_number(STUB_PCR_INITRD_SYSEXTS)?,
+            initrd_confexts: self.loader_number(STUB_PCR_INITRD_CONFEXTS)?,
+        })
+    }
+
+    /// The TPM2 PCR banks which were active while booting, if a TPM2 was used.
+    pub fn tpm2_active_pcr_banks(&self) -> Result<Option<u32>, EfiVarError> {
+        self.loader_number(LOADER_TPM2_ACTIVE_PCR_BANKS)
+    }
+
+    /// The system token, if it is set.
+    pub fn system_token(&self) -> Result<Option<Vec<u8>>, EfiVarError> {
+        self.read(LOADER_SYSTEM_TOKEN, LOADER_GUID)
+            .map(|token| token.map(|(_, data)| data))
+    }
+
+    /// Generate and write a system token, unless one of at least `RANDOM_SEED_SIZE` bytes is set.
+    ///
+    /// Like bootctl, this should be skipped in virtual machines whose images may be cloned, as the
+    /// clones would share the token; see `should_write_system_token`.
+    pub fn ensure_system_token(&self) -> Result<SystemToken, EfiVarError> {
+        if let Some(token) = self.system_token()? {
+            if token.len() >= RANDOM_SEED_SIZE {
+                return Ok(SystemToken::Present);
+            }
+        }
+
+        let token = random_bytes(RANDOM_SEED_SIZE).map_err(|source| EfiVarError::Write {
+            name: LOADER_SYSTEM_TOKEN.into(),
+            source,
+        })?;
+
+        let attributes = NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS;
+        self.write(LOADER_SYSTEM_TOKEN, LOADER_GUID, attributes, &token)?;
+        Ok(SystemToken::Written)
+    }
+
+    /// A number in a string variable, which is decimal or hexadecimal with a `0x` prefix.
+    fn loader_number(&self, name: &str) -> Result<Option<u32>, EfiVarError> {
+        let value = match self.loader_string(name)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let value = value.trim();
+        let number = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse::<u32>(),
+        };
+
+        number
+            .map(Some)
+            .map_err(|_| EfiVarError::InvalidNumber(name.into()))
+    }
+}
+
+/// Whether a system token should be written, as bootctl decides: by the boolean value of
+/// `$SYSTEMD_WRITE_SYSTEM_TOKEN` if it is set, or otherwise only outside of virtual machines.
+pub fn should_write_system_token() -> bool {
+    if let Ok(value) = std::env::var("SYSTEMD_WRITE_SYSTEM_TOKEN") {
+        match value.to_ascii_lowercase().as_str() {
+            "1" | "yes" | "y" | "true" | "t" | "on" => return true,
+            "0" | "no" | "n" | "false" | "f" | "off" => return false,
+            _ => warn!(
+                "ignoring invalid value of $SYSTEMD_WRITE_SYSTEM_TOKEN: {}",
+                value
+            ),
+        }
+    }
+
+    !is_virtualized()
+}
+
+/// Whether the system runs in a virtual machine, as reported by the CPU or by Xen.
+fn is_virtualized() -> bool {
+    let hypervisor_flag = fs::read_to_string("/proc/cpuinfo").is_ok_and(|cpuinfo| {
+        cpuinfo
+            .lines()
+            .filter(|line| line.starts_with("flags"))
+            .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+    });
+
+    hypervisor_flag || Path::new("/sys/hypervisor/type").exists()
+}
+
+/// Random bytes from the kernel's random number generator.
+pub(crate) fn random_bytes(len: usize) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut bytes = vec![0; len];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// efivarfs marks most variables as immutable, to guard against accidental removal.
+#[cfg(target_os = "linux")]
+fn make_mutable(path: &Path) -> io::Result<()> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+    // _IOR('f', 1, long) and _IOW('f', 2, long)
+    let size = (mem::size_of::<libc::c_long>() as libc::c_ulong) << 16;
+    let get_flags = (2 << 30) | size | (b'f' as libc::c_ulong) << 8 | 1;
+    let set_flags = (1 << 30) | size | (b'f' as libc::c_ulong) << 8 | 2;
+
+    let file = fs::File::open(path)?;
+    let fd = file.as_raw_fd();
+    let mut flags: libc::c_int = 0;
+
+    // Filesystems without inode flags have nothing to clear.
+    if unsafe { libc::ioctl(fd, get_flags as _, &mut flags) } < 0 {
+        return Ok(());
+    }
+
+    if flags & FS_IMMUTABLE_FL == 0 {
+        return Ok(());
+    }
+
+    flags &= !FS_IMMUTABLE_FL;
+    if unsafe { libc::ioctl(fd, set_flags as _, &flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn make_mutable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use super::{EfiVarError, EfiVarStore};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The attributes and data of each variable, by its name and vendor GUID.
+type Variables = BTreeMap<(String, String), (u32, Vec<u8>)>;
+
+/// EFI variables which only exist in memory.
+///
+/// Clones share the same variables, so a clone may be handed to `EfiVars` while another is kept to
+/// set up the variables that the loader would have set, and to inspect those which were written.
+#[derive(Debug, Clone)]
+pub struct MemoryEfiVarStore {
+    variables: Arc<Mutex<Variables>>,
+    available: bool,
+}
+
+impl Default for MemoryEfiVarStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryEfiVarStore {
+    /// A store without any variables, as on a system booted with EFI by a loader which does not
+    /// implement the boot loader interface.
+    pub fn new() -> Self {
+        Self {
+            variables: Arc::default(),
+            available: true,
+        }
+    }
+
+    /// A store which is not available, as on a system which was not booted with EFI, so that
+    /// every variable is undefined and changing them fails.
+    pub fn unavailable() -> Self {
+        Self {
+            available: false,
+            ..Self::new()
+        }
+    }
+
+    /// The names and vendor GUIDs of the variables which are defined.
+    pub fn names(&self) -> Vec<(String, String)> {
+        self.variables().keys().cloned().collect()
+    }
+
+    fn variables(&self) -> MutexGuard<'_, Variables> {
+        self.variables
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn key(name: &str, guid: &str) -> (String, String) {
+        (name.to_owned(), guid.to_ascii_lowercase())
+    }
+}
+
+impl EfiVarStore for MemoryEfiVarStore {
+    fn is_available(&self) -> bool {
+        self.available
+    }
+
+    fn read(&self, name: &str, guid: &str) -> Result<Option<(u32, Vec<u8>)>, EfiVarError> {
+        Ok(self.variables().get(&Self::key(name, guid)).cloned())
+    }
+
+    fn write(
+        &self,
+        name: &str,
+        guid: &str,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<(), EfiVarError> {
+        if !self.available {
+            let source = unavailable();
+            let name = name.to_owned();
+            return Err(EfiVarError::Write { name, source });
+        }
+
+        let value = (attributes, data.to_vec());
+        self.variables().insert(Self::key(name, guid), value);
+        Ok(())
+    }
+
+    fn remove(&self, name: &str, guid: &str) -> Result<(), EfiVarError> {
+        if !self.available {
+            let source = unavailable();
+            let name = name.to_owned();
+            return Err(EfiVarError::Remove { name, source });
+        }
+
+        self.variables().remove(&Self::key(name, guid));
+        Ok(())
+    }
+}
+
+/// The error of efivarfs where the system was not booted with EFI, and it is not mounted.
+fn unavailable() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "EFI variables are not available")
+}
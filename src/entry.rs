@@ -1,98 +1,928 @@
+use crate::arch::EfiArch;
+use crate::esp_path::EspPath;
+use crate::fat::{self, FatNameError};
+use crate::grubenv::GrubEnv;
+use crate::id::EntryId;
+use crate::parse::{tolerate, ParseMode, ParseWarning, MAX_CONF_SIZE};
+use crate::version::{self, Version};
+use crate::{cmdline, ErrorKind};
 use itertools::Itertools;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str;
+
+/// Keys which may only be defined once in an entry.
+const SINGLE_KEYS: &[&str] = &[
+    "title",
+    "version",
+    "machine-id",
+    "sort-key",
+    "linux",
+    "efi",
+    "devicetree",
+    "architecture",
+];
+
+/// The EFI name of the architecture this crate was compiled for, as used by the `architecture` key.
+pub const HOST_ARCHITECTURE: Option<&str> = if cfg!(target_arch = "x86_64") {
+    Some("x64")
+} else if cfg!(target_arch = "x86") {
+    Some("ia32")
+} else if cfg!(target_arch = "aarch64") {
+    Some("aa64")
+} else if cfg!(target_arch = "arm") {
+    Some("arm")
+} else if cfg!(target_arch = "riscv64") {
+    Some("riscv64")
+} else if cfg!(target_arch = "riscv32") {
+    Some("riscv32")
+} else if cfg!(target_arch = "loongarch64") {
+    Some("loongarch64")
+} else {
+    None
+};
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum EntryError {
+    #[error("{} was defined more than once", _0)]
+    DuplicateKey(String),
+    #[error(
+        "extension key {:?} does not start with x- or contains invalid characters",
+        _0
+    )]
+    InvalidExtensionKey(String),
+    #[error(
+        "extension value {:?} is empty, has surrounding whitespace, or contains a line break",
+        _0
+    )]
+    InvalidExtensionValue(String),
+    #[error("{:?} cannot be created on the FAT filesystem of the ESP", _0)]
+    InvalidFatName(String, #[source] FatNameError),
+    #[error("{} value {:?} contains a line break", _0, _1)]
+    InvalidGrubValue(&'static str, String),
+    #[error("entry id {:?} is not a valid file name", _0)]
+    InvalidId(String),
+    #[error("machine-id {:?} is not 32 lowercase hexadecimal characters", _0)]
+    InvalidMachineId(String),
+    #[error(
+        "option {:?} is empty, or contains a control character or quotes which the kernel would not parse back",
+        _0
+    )]
+    InvalidOption(String),
+    #[error("path {:?} is empty or contains whitespace", _0)]
+    InvalidPath(String),
+    #[error("title {:?} contains a line break", _0)]
+    InvalidTitle(String),
     #[error("error reading line in entry file")]
     Line(#[source] io::Error),
+    #[error("neither linux nor efi was defined")]
+    MissingLinux,
+    #[error("machine-id is required to name the entry")]
+    MissingMachineId,
+    #[error("version is required to name the entry")]
+    MissingVersion,
     #[error("title field is missing")]
     MisisngTitle,
     #[error("entry is not a file")]
     NotAFile,
     #[error("entry does not have a file name")]
     NoFilename,
+    #[error("devicetree was defined without a value")]
+    NoValueForDevicetree,
+    #[error("efi was defined without a value")]
+    NoValueForEfi,
     #[error("initrd was defined without a value")]
     NoValueForInitrd,
     #[error("linux was defined without a value")]
     NoValueForLinux,
     #[error("error opening entry file")]
     Open(#[source] io::Error),
+    #[error("entry is protected: {}", _0)]
+    Protected(String),
+    #[error("entry file is {} bytes, which is larger than an entry may be", _0)]
+    TooLarge(usize),
     #[error("entry has a file name that is not UTF-8")]
     Utf8Filename,
 }
 
-#[derive(Debug, Default, Clone)]
+impl EntryError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            EntryError::Line(why) | EntryError::Open(why) => ErrorKind::from_io(why),
+            _ => ErrorKind::InvalidData,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct Entry {
-    pub id: Box<str>,
-    pub initrd: Option<Box<str>>,
-    pub linux: Box<str>,
-    pub options: Vec<Box<str>>,
-    pub title: Box<str>,
+    /// The EFI architecture the entry is for, such as `x64` or `aa64`.
+    pub architecture: Option<String>,
+    pub devicetree: Option<String>,
+    pub efi: Option<String>,
+    /// Vendor extension keys, which start with `x-`, and their values, in the order they appear.
+    pub extensions: Vec<(String, String)>,
+    /// The name of the file that the entry was loaded from, such as `pop.CONF`, which it is written
+    /// back to for as long as the id matches it.
+    pub file_name: Option<String>,
+    /// Arguments to GRUB's `menuentry` command, from entries generated by Fedora and RHEL.
+    pub grub_arg: Option<String>,
+    /// The GRUB menu class of the entry, from entries generated by Fedora and RHEL.
+    pub grub_class: Option<String>,
+    /// The GRUB users allowed to boot the entry, from entries generated by Fedora and RHEL.
+    pub grub_users: Option<String>,
+    pub id: String,
+    /// The initrds, which are loaded in order, so that microcode must come first.
+    pub initrd: Vec<String>,
+    pub linux: String,
+    pub machine_id: Option<String>,
+    pub options: Vec<String>,
+    /// The original text following the key of each `options` line, which is written back
+    /// verbatim for as long as it still matches `options`.
+    pub options_raw: Vec<String>,
+    /// Keys which are not modelled above, such as `uki` and `profile`, with their values, in the
+    /// order they appear. They are written back after the others, before the vendor extensions.
+    pub other_keys: Vec<(String, String)>,
+    pub sort_key: Option<String>,
+    pub title: String,
+    pub version: Option<String>,
+}
+
+/// The locations of the files that an entry references, where the partition it was read from is
+/// mounted.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ResolvedPaths {
+    pub devicetree: Option<PathBuf>,
+    pub efi: Option<PathBuf>,
+    pub initrd: Vec<PathBuf>,
+    pub linux: Option<PathBuf>,
+}
+
+/// Whether an entry boots a Linux kernel, or some other EFI program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Linux,
+    Efi,
+}
+
+/// Differences which `Entry::equivalent_with` may ignore, in addition to formatting.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Equivalence {
+    /// Compare options regardless of their order, and of how many times each is repeated.
+    ///
+    /// The order is significant to the kernel when an option is given conflicting values, so it
+    /// is compared by default.
+    pub ignore_option_order: bool,
+}
+
+/// The boot counter encoded in an entry's file name, as in `id+LEFT-DONE.conf`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BootCounter {
+    pub tries_left: u32,
+    pub tries_done: u32,
+}
+
+impl BootCounter {
+    /// Splits the boot counter suffix from an entry id, if it has one.
+    pub fn parse(id: &str) -> Option<(&str, BootCounter)> {
+        fn number(value: &str) -> Option<u32> {
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+
+            value.parse::<u32>().ok()
+        }
+
+        let pos = id.rfind('+')?;
+        let (base, counter) = (&id[..pos], &id[pos + 1..]);
+
+        let mut fields = counter.splitn(2, '-');
+        let tries_left = number(fields.next()?)?;
+        let tries_done = match fields.next() {
+            Some(done) => number(done)?,
+            None => 0,
+        };
+
+        Some((
+            base,
+            BootCounter {
+                tries_left,
+                tries_done,
+            },
+        ))
+    }
+
+    /// An entry which has no tries left is considered bad by systemd-boot.
+    pub fn is_bad(self) -> bool {
+        self.tries_left == 0
+    }
+}
+
+impl fmt::Display for BootCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.tries_done == 0 {
+            write!(f, "+{}", self.tries_left)
+        } else {
+            write!(f, "+{}-{}", self.tries_left, self.tries_done)
+        }
+    }
 }
 
 impl Entry {
+    /// Create a new entry, validating that it could be booted by systemd-boot.
+    pub fn new<I, T, L>(id: I, title: T, linux: L) -> Result<Self, EntryError>
+    where
+        I: Into<String>,
+        T: Into<String>,
+        L: Into<String>,
+    {
+        let entry = Entry {
+            id: id.into(),
+            title: title.into(),
+            linux: linux.into(),
+            ..Entry::default()
+        };
+
+        entry.validate()?;
+        Ok(entry)
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, EntryError> {
+        Self::from_path_with(path, ParseMode::Strict, &mut Vec::new())
+    }
+
+    /// Parse an entry with the given parse mode, appending any tolerated problems to `warnings`.
+    pub fn from_path_with<P: AsRef<Path>>(
+        path: P,
+        mode: ParseMode,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, EntryError> {
         let path = path.as_ref();
+        let id = id_from_path(path)?;
+        Self::from_path_with_id(path, id, mode, warnings)
+    }
 
-        if !path.is_file() {
-            return Err(EntryError::NotAFile);
-        }
+    /// Parse an entry whose file name may not be UTF-8.
+    ///
+    /// Invalid sequences in the file name are replaced in the id, so the id of such an entry will
+    /// not refer back to its file. Use `SystemdBootConf::rename_non_utf8_entry` to fix the file name.
+    pub fn from_path_lossy<P: AsRef<Path>>(path: P) -> Result<Self, EntryError> {
+        let path = path.as_ref();
 
         let file_name = match path.file_stem() {
-            Some(file_name) => match file_name.to_str() {
-                Some(file_name) => file_name.to_owned(),
-                None => return Err(EntryError::Utf8Filename),
-            },
+            Some(file_name) => file_name.to_string_lossy().into_owned(),
             None => return Err(EntryError::NoFilename),
         };
 
-        let file = File::open(path).map_err(EntryError::Open)?;
+        Self::from_path_with_id(path, file_name, ParseMode::Strict, &mut Vec::new())
+    }
 
-        let mut entry = Entry::default();
-        entry.id = file_name.into();
+    fn from_path_with_id(
+        path: &Path,
+        id: String,
+        mode: ParseMode,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, EntryError> {
+        if !path.is_file() {
+            return Err(EntryError::NotAFile);
+        }
+
+        let contents = fs::read(path).map_err(EntryError::Open)?;
+        Self::parse_with_id(path, id, &contents, mode, warnings)
+    }
 
-        for line in BufReader::new(file).lines() {
-            let line = line.map_err(EntryError::Line)?;
+    /// Parse the contents of an entry file strictly, such as one received over the network.
+    ///
+    /// The entry has no id, which is otherwise taken from the file name; give it one with
+    /// `set_id`. Malformed input of any kind is reported as an error, rather than causing a
+    /// panic, and input larger than `parse::MAX_CONF_SIZE` is refused before it is parsed.
+    pub fn parse(contents: &[u8]) -> Result<Self, EntryError> {
+        let mut warnings = Vec::new();
+        Self::parse_with_id(
+            Path::new(""),
+            String::new(),
+            contents,
+            ParseMode::Strict,
+            &mut warnings,
+        )
+    }
+
+    /// Parse the contents of an entry file, where `path` is only used to report warnings.
+    pub(crate) fn parse_with_id(
+        path: &Path,
+        id: String,
+        contents: &[u8],
+        mode: ParseMode,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, EntryError> {
+        if contents.len() > MAX_CONF_SIZE {
+            return Err(EntryError::TooLarge(contents.len()));
+        }
+
+        let contents = str::from_utf8(contents)
+            .map_err(|why| EntryError::Line(io::Error::new(io::ErrorKind::InvalidData, why)))?;
+
+        let mut entry = Entry {
+            id,
+            file_name: path.file_name().and_then(OsStr::to_str).map(str::to_owned),
+            ..Entry::default()
+        };
+
+        let mut issue =
+            |line: Option<usize>, error: EntryError| tolerate(mode, warnings, path, line, error);
+
+        let mut seen = HashSet::new();
+        for (number, line) in contents.lines().enumerate() {
+            let number = Some(number + 1);
+            let line = crate::format::clean_line(line).trim_start();
             let mut fields = line.split_whitespace();
-            match fields.next() {
-                Some("title") => entry.title = fields.join(" ").into(),
+            // Keys are matched case-insensitively, as systemd-boot does.
+            let key = fields.next().map(str::to_ascii_lowercase);
+
+            if let Some(key) = key.as_deref() {
+                if SINGLE_KEYS.contains(&key) && !seen.insert(key.to_owned()) {
+                    issue(number, EntryError::DuplicateKey(key.to_owned()))?;
+                }
+            }
+
+            match key.as_deref() {
+                Some("title") => entry.title = fields.join(" "),
                 Some("linux") => match fields.next() {
-                    Some(value) => entry.linux = value.into(),
-                    None => return Err(EntryError::NoValueForLinux),
+                    Some(value) => entry.linux = value.to_owned(),
+                    None => issue(number, EntryError::NoValueForLinux)?,
                 },
                 Some("initrd") => match fields.next() {
-                    Some(value) => entry.initrd = Some(value.into()),
-                    None => return Err(EntryError::NoValueForInitrd),
+                    Some(value) => entry.initrd.push(value.to_owned()),
+                    None => issue(number, EntryError::NoValueForInitrd)?,
+                },
+                Some("options") => {
+                    // Multiple options lines are concatenated.
+                    let value = &line[line.find(char::is_whitespace).unwrap_or(line.len())..];
+                    entry.options.extend(cmdline::split(value));
+                    entry.options_raw.push(value.to_owned());
+                }
+                Some("architecture") => entry.architecture = fields.next().map(String::from),
+                Some("devicetree") => match fields.next() {
+                    Some(value) => entry.devicetree = Some(value.to_owned()),
+                    None => issue(number, EntryError::NoValueForDevicetree)?,
                 },
-                Some("options") => entry.options = fields.map(Box::from).collect(),
-                _ => (),
+                Some("efi") => match fields.next() {
+                    Some(value) => entry.efi = Some(value.to_owned()),
+                    None => issue(number, EntryError::NoValueForEfi)?,
+                },
+                // GRUB's extensions are preserved for the distributions which boot with GRUB.
+                Some("grub_arg") => entry.grub_arg = Some(fields.join(" ")),
+                Some("grub_class") => entry.grub_class = Some(fields.join(" ")),
+                Some("grub_users") => entry.grub_users = Some(fields.join(" ")),
+                Some(key) if key.starts_with("x-") => {
+                    let value = line[key.len()..].trim();
+                    if !value.is_empty() {
+                        entry.insert_extension(key, value);
+                    }
+                }
+                Some("machine-id") => entry.machine_id = fields.next().map(String::from),
+                Some("sort-key") => entry.sort_key = fields.next().map(String::from),
+                Some("version") => entry.version = fields.next().map(String::from),
+                Some(key) if key.starts_with('#') => (),
+                Some(key) => {
+                    let value = line[key.len()..].trim();
+                    entry.other_keys.push((key.to_owned(), value.to_owned()));
+                }
+                None => (),
             }
         }
 
         if entry.title.is_empty() {
-            return Err(EntryError::MisisngTitle);
+            issue(None, EntryError::MisisngTitle)?;
+            // systemd-boot displays the id of entries which lack a title.
+            entry.title = entry.id.clone();
+        }
+
+        if entry.linux.is_empty() && entry.efi.is_none() {
+            issue(None, EntryError::MissingLinux)?;
         }
 
         Ok(entry)
     }
 
+    /// Change the id of the entry, which is also the file name of its conf file.
+    pub fn set_id<S: Into<String>>(&mut self, id: S) -> Result<(), EntryError> {
+        let id = id.into();
+        validate_id(&id)?;
+        self.id = id;
+        Ok(())
+    }
+
+    /// Change the title of the entry.
+    pub fn set_title<S: Into<String>>(&mut self, title: S) -> Result<(), EntryError> {
+        let title = title.into();
+        validate_title(&title)?;
+        self.title = title;
+        Ok(())
+    }
+
+    /// Change the path to the kernel image, relative to the EFI mount.
+    pub fn set_linux<S: Into<String>>(&mut self, linux: S) -> Result<(), EntryError> {
+        let linux = linux.into();
+        validate_path(&linux)?;
+        self.linux = linux;
+        Ok(())
+    }
+
+    /// Change or remove the path to the initrd, relative to the EFI mount.
+    pub fn set_initrd<S: Into<String>>(&mut self, initrd: Option<S>) -> Result<(), EntryError> {
+        self.set_initrds(initrd)
+    }
+
+    /// Replace the initrds of the entry, which are relative to the EFI mount.
+    pub fn set_initrds<I, S>(&mut self, initrds: I) -> Result<(), EntryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let initrds = initrds.into_iter().map(Into::into).collect::<Vec<String>>();
+        for initrd in &initrds {
+            validate_path(initrd)?;
+        }
+
+        self.initrd = initrds;
+        Ok(())
+    }
+
+    /// Add microcode images which the entry does not already load, before its other initrds.
+    ///
+    /// Returns whether the initrds were changed.
+    pub fn attach_microcode<I, S>(&mut self, images: I) -> bool
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut missing = images
+            .into_iter()
+            .map(Into::into)
+            .filter(|image| {
+                let image = EspPath::new(image);
+                !self
+                    .initrd
+                    .iter()
+                    .any(|initrd| EspPath::new(initrd) == image)
+            })
+            .collect::<Vec<String>>();
+
+        let reordered = self.order_microcode_first();
+        if missing.is_empty() {
+            return reordered;
+        }
+
+        missing.append(&mut self.initrd);
+        self.initrd = missing;
+        self.order_microcode_first();
+        true
+    }
+
+    /// Whether a CPU microcode image is loaded after another initrd, where the kernel would not
+    /// find it in time to apply it early.
+    pub fn microcode_misordered(&self) -> bool {
+        self.initrd
+            .iter()
+            .skip_while(|initrd| is_microcode(initrd))
+            .any(|initrd| is_microcode(initrd))
+    }
+
+    /// Move CPU microcode images before the other initrds, returning whether any were moved.
+    ///
+    /// The initrds otherwise keep their order.
+    pub fn order_microcode_first(&mut self) -> bool {
+        if !self.microcode_misordered() {
+            return false;
+        }
+
+        let (mut initrds, others): (Vec<_>, Vec<_>) = self
+            .initrd
+            .drain(..)
+            .partition(|initrd| is_microcode(initrd));
+
+        initrds.extend(others);
+        self.initrd = initrds;
+        true
+    }
+
+    /// The version of the kernel that the entry boots, for comparing how old entries are.
+    ///
+    /// This is the `version` field if it is defined, or otherwise the version in the file name of
+    /// the kernel, such as `6.9.3-76060903-generic` in `vmlinuz-6.9.3-76060903-generic`.
+    pub fn kernel_version(&self) -> Option<Version> {
+        if let Some(ref version) = self.version {
+            return Some(Version::new(version.as_str()));
+        }
+
+        let image = match self.efi {
+            Some(ref efi) if self.linux.is_empty() => efi,
+            _ => &self.linux,
+        };
+
+        let name = image.rsplit(['/', '\\']).next()?;
+        let name = match name.len().checked_sub(4) {
+            Some(end)
+                if name
+                    .get(end..)
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(".efi")) =>
+            {
+                &name[..end]
+            }
+            _ => name,
+        };
+
+        // The version follows the first dash which precedes a digit.
+        let bytes = name.as_bytes();
+        let start =
+            (1..bytes.len()).find(|&pos| bytes[pos - 1] == b'-' && bytes[pos].is_ascii_digit())?;
+
+        Some(Version::new(&name[start..]))
+    }
+
+    /// The value of a vendor extension key, such as `x-pop-channel`.
+    pub fn extension(&self, key: &str) -> Option<&str> {
+        self.extensions
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parse the value of a vendor extension key, if it is defined.
+    pub fn extension_as<T: str::FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.extension(key).map(str::parse)
+    }
+
+    /// Define a vendor extension key, which must start with `x-`, replacing its value if it is
+    /// already defined.
+    pub fn set_extension<K: Into<String>, V: ToString>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<(), EntryError> {
+        let key = key.into().to_ascii_lowercase();
+        let value = value.to_string();
+        validate_extension(&key, &value)?;
+        self.insert_extension(&key, &value);
+        Ok(())
+    }
+
+    /// Remove a vendor extension key, returning its value if it was defined.
+    pub fn remove_extension(&mut self, key: &str) -> Option<String> {
+        let pos = self
+            .extensions
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        Some(self.extensions.remove(pos).1)
+    }
+
+    fn insert_extension(&mut self, key: &str, value: &str) {
+        match self.extensions.iter_mut().find(|(k, _)| k == key) {
+            Some(extension) => extension.1 = value.to_owned(),
+            None => self.extensions.push((key.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Why the entry is protected from removal and from having its options edited, if it is.
+    pub fn protection(&self) -> Option<&str> {
+        self.extension(PROTECTED_KEY)
+    }
+
+    /// Protect the entry from removal and from having its options edited, such as a recovery
+    /// entry. The reason is kept in the entry file, and reported to whoever tries to change it.
+    pub fn protect<S: ToString>(&mut self, reason: S) -> Result<(), EntryError> {
+        self.set_extension(PROTECTED_KEY, reason)
+    }
+
+    /// Remove the protection of the entry, returning the reason it was protected.
+    pub fn unprotect(&mut self) -> Option<String> {
+        self.remove_extension(PROTECTED_KEY)
+    }
+
+    /// Change or remove the path to the devicetree, relative to the EFI mount.
+    pub fn set_devicetree<S: Into<String>>(
+        &mut self,
+        devicetree: Option<S>,
+    ) -> Result<(), EntryError> {
+        let devicetree = devicetree.map(Into::into);
+        if let Some(ref devicetree) = devicetree {
+            validate_path(devicetree)?;
+        }
+
+        self.devicetree = devicetree;
+        Ok(())
+    }
+
+    /// Replace the kernel options of the entry, such as with those of a `Cmdline`, unless it is
+    /// protected.
+    pub fn set_options<I, S>(&mut self, options: I) -> Result<(), EntryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if let Some(reason) = self.protection() {
+            return Err(EntryError::Protected(reason.to_owned()));
+        }
+
+        let options = options.into_iter().map(Into::into).collect::<Vec<String>>();
+        for option in &options {
+            validate_option(option)?;
+        }
+
+        self.options = options;
+        Ok(())
+    }
+
+    /// Remove options which repeat an earlier option exactly, returning the removed options.
+    ///
+    /// Options which conflict, such as `ro` and `rw`, are kept, as only the user can tell which
+    /// was intended. The options of a protected entry are left as they are.
+    pub fn dedup_options(&mut self) -> Vec<String> {
+        if self.protection().is_some() {
+            return Vec::new();
+        }
+
+        let mut seen = HashSet::new();
+        let mut removed = Vec::new();
+
+        self.options.retain(|option| {
+            if seen.insert(option.clone()) {
+                true
+            } else {
+                removed.push(option.clone());
+                false
+            }
+        });
+
+        removed
+    }
+
+    /// The id of the entry, if it is valid.
+    pub fn entry_id(&self) -> Result<EntryId, EntryError> {
+        EntryId::new(self.id.as_str())
+    }
+
+    /// Checks that the entry can be written and would be bootable by systemd-boot.
+    ///
+    /// The public fields may be modified freely, so this is checked again before an entry is
+    /// written to the disk.
+    pub fn validate(&self) -> Result<(), EntryError> {
+        validate_id(&self.id)?;
+        validate_title(&self.title)?;
+
+        if let Some(ref machine_id) = self.machine_id {
+            if !crate::machine_id::is_valid(machine_id) {
+                return Err(EntryError::InvalidMachineId(machine_id.clone()));
+            }
+        }
+
+        match self.efi {
+            Some(ref efi) if self.linux.is_empty() => validate_path(efi)?,
+            _ => {
+                if self.linux.is_empty() {
+                    return Err(EntryError::NoValueForLinux);
+                }
+
+                validate_path(&self.linux)?;
+            }
+        }
+
+        for initrd in &self.initrd {
+            if initrd.is_empty() {
+                return Err(EntryError::NoValueForInitrd);
+            }
+
+            validate_path(initrd)?;
+        }
+
+        if let Some(ref devicetree) = self.devicetree {
+            if devicetree.is_empty() {
+                return Err(EntryError::NoValueForDevicetree);
+            }
+
+            validate_path(devicetree)?;
+        }
+
+        for option in &self.options {
+            validate_option(option)?;
+        }
+
+        for (key, value) in &self.extensions {
+            validate_extension(key, value)?;
+        }
+
+        let grub = [
+            ("grub_arg", &self.grub_arg),
+            ("grub_class", &self.grub_class),
+            ("grub_users", &self.grub_users),
+        ];
+
+        for (key, value) in grub.iter() {
+            if let Some(value) = value {
+                if value.contains(['\n', '\r']) {
+                    return Err(EntryError::InvalidGrubValue(key, value.clone()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where the files that the entry references are found, relative to the root of the
+    /// partition that `conf` reads entries from.
+    pub fn resolved_paths(&self, conf: &crate::SystemdBootConf) -> ResolvedPaths {
+        let root = conf.entry_root();
+        let resolve = |path: &String| EspPath::new(path).to_path(root);
+
+        ResolvedPaths {
+            devicetree: self.devicetree.as_ref().map(resolve),
+            efi: self.efi.as_ref().map(resolve),
+            initrd: self.initrd.iter().map(resolve).collect(),
+            linux: Some(&self.linux)
+                .filter(|linux| !linux.is_empty())
+                .map(resolve),
+        }
+    }
+
+    /// The original options lines, if they have not been modified since the entry was parsed.
+    pub fn unmodified_options_raw(&self) -> Option<&[String]> {
+        let raw = self.options_raw.iter().flat_map(|raw| cmdline::split(raw));
+
+        if !self.options_raw.is_empty() && raw.eq(self.options.iter().cloned()) {
+            Some(&self.options_raw)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the entry boots a Linux kernel, or some other EFI program.
+    pub fn kind(&self) -> EntryKind {
+        if self.linux.is_empty() && self.efi.is_some() {
+            EntryKind::Efi
+        } else {
+            EntryKind::Linux
+        }
+    }
+
+    /// The name of the conf file of the entry: the file it was loaded from, or `ID.conf` if it was
+    /// not loaded from a file or has since been given another id.
+    pub fn conf_file_name(&self) -> String {
+        match self.file_name {
+            Some(ref name) if id_from_file_name(name) == Some(&self.id) => name.clone(),
+            _ => format!("{}.conf", self.id),
+        }
+    }
+
+    /// The id of this entry without its boot counter suffix, which is how systemd-boot refers to it.
+    pub fn base_id(&self) -> &str {
+        BootCounter::parse(&self.id).map_or(&self.id, |(base, _)| base)
+    }
+
+    /// The boot counter of this entry, if boot counting is enabled for it.
+    pub fn boot_counter(&self) -> Option<BootCounter> {
+        BootCounter::parse(&self.id).map(|(_, counter)| counter)
+    }
+
+    /// Orders entries as they appear in the systemd-boot menu.
+    ///
+    /// Entries with no tries left are placed last, and entries with a sort key are placed before
+    /// those without. Entries with sort keys are sorted by their sort key and machine id, and then
+    /// by their version in descending order. Remaining ties are sorted by their id in descending
+    /// version order, so that newer kernels come first. Entries which differ only in their boot
+    /// counters are ordered by the tries they have left, and then by the tries done.
+    pub fn boot_order(&self, other: &Entry) -> Ordering {
+        let (a, b) = (self.boot_counter(), other.boot_counter());
+        let bad = |counter: Option<BootCounter>| counter.is_some_and(BootCounter::is_bad);
+
+        let by_sort_key = || match (&self.sort_key, &other.sort_key) {
+            (Some(a), Some(b)) => a
+                .cmp(b)
+                .then_with(|| self.machine_id.cmp(&other.machine_id))
+                .then_with(|| match (&self.version, &other.version) {
+                    (Some(a), Some(b)) => version::compare(b, a),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                }),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        };
+
+        bad(a)
+            .cmp(&bad(b))
+            .then_with(by_sort_key)
+            .then_with(|| version::compare(other.base_id(), self.base_id()))
+            .then_with(|| match (a, b) {
+                (Some(a), Some(b)) => b
+                    .tries_left
+                    .cmp(&a.tries_left)
+                    .then(a.tries_done.cmp(&b.tries_done)),
+                _ => Ordering::Equal,
+            })
+    }
+
+    /// Compares two entries while ignoring differences that only affect formatting.
+    ///
+    /// Whitespace in titles is not significant, and paths are compared regardless of
+    /// their separators or the presence of a leading slash. Boot counters are ignored, so the
+    /// same entry is recognized on another ESP after it has been booted a different number of
+    /// times.
+    pub fn equivalent(&self, other: &Entry) -> bool {
+        self.equivalent_with(other, Equivalence::default())
+    }
+
+    /// Compares two entries like `equivalent`, optionally ignoring further differences.
+    pub fn equivalent_with(&self, other: &Entry, equivalence: Equivalence) -> bool {
+        let same_title = self
+            .title
+            .split_whitespace()
+            .eq(other.title.split_whitespace());
+
+        let same_optional_path = |a: &Option<String>, b: &Option<String>| {
+            a.as_ref().map(EspPath::from) == b.as_ref().map(EspPath::from)
+        };
+
+        let same_options = if equivalence.ignore_option_order {
+            let set = |options: &[String]| options.iter().cloned().collect::<BTreeSet<_>>();
+            set(&self.options) == set(&other.options)
+        } else {
+            self.options == other.options
+        };
+
+        self.base_id() == other.base_id()
+            && same_title
+            && self.version == other.version
+            && self.machine_id == other.machine_id
+            && self.sort_key == other.sort_key
+            && self.architecture == other.architecture
+            && EspPath::new(&self.linux) == EspPath::new(&other.linux)
+            && same_optional_path(&self.efi, &other.efi)
+            && self
+                .initrd
+                .iter()
+                .map(EspPath::from)
+                .eq(other.initrd.iter().map(EspPath::from))
+            && same_optional_path(&self.devicetree, &other.devicetree)
+            && same_options
+    }
+
+    /// Whether systemd-boot would show this entry on a machine of the given EFI architecture.
+    ///
+    /// Entries without an architecture are shown on every machine.
+    pub fn bootable_on(&self, architecture: &str) -> bool {
+        self.architecture
+            .as_deref()
+            .is_none_or(|arch| arch.eq_ignore_ascii_case(architecture))
+    }
+
+    /// Whether the entry is for an architecture other than that of the firmware of this machine,
+    /// such that systemd-boot would hide it.
+    pub fn is_foreign(&self) -> bool {
+        EfiArch::firmware().is_some_and(|arch| !self.bootable_on(arch.as_str()))
+    }
+
     /// Determines if this boot entry is the current boot entry
     ///
     /// # Implementation
     ///
     /// This is determined by a matching the entry's initd and options to `/proc/cmdline`.
     pub fn is_current(&self) -> bool {
-        let initrd = self
+        self.matches_cmdline(&self.options)
+    }
+
+    /// Determines if this is the current boot entry, after expanding the GRUB variables in its
+    /// options, for entries which were booted by GRUB.
+    pub fn is_current_with(&self, env: &GrubEnv) -> bool {
+        self.matches_cmdline(&self.expanded_options(env))
+    }
+
+    /// The options with their GRUB variables, such as `$kernelopts`, expanded.
+    pub fn expanded_options(&self, env: &GrubEnv) -> Vec<String> {
+        env.expand(&self.options)
+    }
+
+    fn matches_cmdline(&self, options: &[String]) -> bool {
+        // systemd-boot passes an `initrd=` option for each initrd.
+        let initrds = self
             .initrd
-            .as_ref()
-            .map(|x| ["initrd=", &x.replace('/', "\\")].concat());
+            .iter()
+            .map(|x| ["initrd=", &EspPath::new(x).to_efi_string()].concat())
+            .collect::<Vec<_>>();
 
-        let initrd = initrd.as_ref().map(String::as_str);
-        let options = self.options.iter().map(Box::as_ref);
+        let initrds = initrds.iter().map(String::as_str);
+        let options = options.iter().map(String::as_str);
 
-        let expected_cmdline = initrd.iter().cloned().chain(options);
+        let expected_cmdline = initrds.chain(options);
 
         crate::kernel_cmdline()
             .iter()
@@ -101,3 +931,238 @@ impl Entry {
             .all(|(a, b)| a == b)
     }
 }
+
+/// The id of an entry is the file name of its conf file, without the extension.
+pub(crate) fn id_from_path(path: &Path) -> Result<String, EntryError> {
+    match path.file_stem() {
+        Some(file_name) => match file_name.to_str() {
+            Some(file_name) => Ok(file_name.to_owned()),
+            None => Err(EntryError::Utf8Filename),
+        },
+        None => Err(EntryError::NoFilename),
+    }
+}
+
+/// The id that an entry file name gives, where the `.conf` extension may be in any case.
+fn id_from_file_name(name: &str) -> Option<&str> {
+    let split = name.len().checked_sub(5)?;
+    match (name.get(..split), name.get(split..)) {
+        (Some(id), Some(extension)) if extension.eq_ignore_ascii_case(".conf") => Some(id),
+        _ => None,
+    }
+}
+
+/// The vendor extension key which marks an entry as protected, with the reason as its value.
+pub const PROTECTED_KEY: &str = "x-protected";
+
+/// The file names of CPU microcode images, as installed by distributions.
+pub(crate) const MICROCODE: &[&str] = &["amd-ucode.img", "intel-ucode.img", "early_ucode.cpio"];
+
+/// Whether an initrd is a CPU microcode image, by its file name.
+pub fn is_microcode(initrd: &str) -> bool {
+    let name = initrd.rsplit(['/', '\\']).next().unwrap_or(initrd);
+    MICROCODE
+        .iter()
+        .any(|microcode| name.eq_ignore_ascii_case(microcode))
+}
+
+pub(crate) fn validate_id(id: &str) -> Result<(), EntryError> {
+    let invalid = id.is_empty()
+        || id.starts_with('.')
+        || id.chars().any(|c| c == '/' || c == '\\' || c.is_control());
+
+    if invalid {
+        return Err(EntryError::InvalidId(id.to_owned()));
+    }
+
+    let file_name = format!("{}.conf", id);
+    fat::validate_name(&file_name).map_err(|why| EntryError::InvalidFatName(file_name, why))
+}
+
+fn validate_extension(key: &str, value: &str) -> Result<(), EntryError> {
+    let valid_key = key.len() > 2
+        && key.starts_with("x-")
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+
+    if !valid_key {
+        return Err(EntryError::InvalidExtensionKey(key.to_owned()));
+    }
+
+    if value.trim().is_empty() || value.contains(['\n', '\r']) || value.trim() != value {
+        return Err(EntryError::InvalidExtensionValue(value.to_owned()));
+    }
+
+    Ok(())
+}
+
+fn validate_title(title: &str) -> Result<(), EntryError> {
+    if title.trim().is_empty() {
+        return Err(EntryError::MisisngTitle);
+    }
+
+    if title.contains(['\n', '\r']) {
+        return Err(EntryError::InvalidTitle(title.to_owned()));
+    }
+
+    Ok(())
+}
+
+fn validate_path(path: &str) -> Result<(), EntryError> {
+    if path.is_empty() || path.contains(char::is_whitespace) {
+        return Err(EntryError::InvalidPath(path.to_owned()));
+    }
+
+    fat::validate_path(path).map_err(|why| EntryError::InvalidFatName(path.to_owned(), why))
+}
+
+/// Options may contain whitespace, which is quoted when written, unless the quotes that it would
+/// need cannot be told apart from quotes within the option.
+fn validate_option(option: &str) -> Result<(), EntryError> {
+    let invalid = option.is_empty()
+        || option.contains(char::is_control)
+        || !cmdline::is_representable(option);
+
+    if invalid {
+        return Err(EntryError::InvalidOption(option.to_owned()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use crate::style::WriteStyle;
+
+    fn parse(contents: &str) -> Entry {
+        format::parse_entry(
+            "pop",
+            contents.as_bytes(),
+            ParseMode::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap()
+    }
+
+    fn render(entry: &Entry) -> String {
+        String::from_utf8(format::entry_conf(entry, &WriteStyle::default())).unwrap()
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        let oversized = vec![b'#'; MAX_CONF_SIZE + 1];
+        assert!(matches!(
+            Entry::parse(&oversized),
+            Err(EntryError::TooLarge(_))
+        ));
+
+        let corpus: &[&[u8]] = &[
+            b"title \xff\xfe\nlinux /vmlinuz\n",
+            b"\xef\xbb",
+            b"linux\n",
+            b"title a\n",
+            b"title a\nlinux /vmlinuz\ntitle b\n",
+            b"title a\nlinux /vmlinuz\ninitrd\n",
+            b"\r\r\r\n\t \x0b",
+        ];
+
+        for contents in corpus {
+            assert!(Entry::parse(contents).is_err(), "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn options_round_trip_byte_identical() {
+        let contents = "title Pop!_OS\nlinux /vmlinuz\n\
+            options  root=UUID=1234   ro quiet\n\
+            options dyndbg=\"file foo.c +p\"  splash\n";
+        let entry = parse(contents);
+        assert_eq!(
+            entry.options,
+            [
+                "root=UUID=1234",
+                "ro",
+                "quiet",
+                "dyndbg=file foo.c +p",
+                "splash"
+            ]
+        );
+        assert_eq!(render(&entry), contents);
+
+        // Once the options change, they are joined into one line and quoted as needed.
+        let mut entry = entry;
+        entry.options.push("rw".into());
+        assert!(render(&entry)
+            .ends_with("options root=UUID=1234 ro quiet dyndbg=\"file foo.c +p\" splash rw\n"));
+    }
+
+    #[test]
+    fn crlf_and_bom() {
+        let entry = parse("\u{feff}title Pop!_OS\r\nlinux /vmlinuz\r\noptions quiet\r\n");
+        assert_eq!(entry.title, "Pop!_OS");
+        assert_eq!(entry.linux, "/vmlinuz");
+        assert_eq!(entry.options, ["quiet"]);
+        assert_eq!(
+            render(&entry),
+            "title Pop!_OS\nlinux /vmlinuz\noptions quiet\n"
+        );
+    }
+
+    #[test]
+    fn keys_ignore_case() {
+        let entry = parse("Title Pop!_OS\nLINUX /vmlinuz\nInitrd /initrd.img\nOptions quiet\n");
+        assert_eq!(entry.title, "Pop!_OS");
+        assert_eq!(entry.linux, "/vmlinuz");
+        assert_eq!(entry.initrd, ["/initrd.img"]);
+        assert_eq!(entry.options, ["quiet"]);
+
+        let duplicate = format::parse_entry(
+            "pop",
+            b"title a\nTITLE b\nlinux /vmlinuz\n",
+            ParseMode::Strict,
+            &mut Vec::new(),
+        );
+        assert!(matches!(duplicate, Err(EntryError::DuplicateKey(ref key)) if key == "title"));
+    }
+
+    #[test]
+    fn multiple_initrds_keep_their_order() {
+        let entry = parse(
+            "title Pop!_OS\nlinux /vmlinuz\ninitrd /intel-ucode.img\ninitrd /initrd.img\n\
+             initrd /extra.img\n",
+        );
+        assert_eq!(
+            entry.initrd,
+            ["/intel-ucode.img", "/initrd.img", "/extra.img"]
+        );
+        assert!(!entry.microcode_misordered());
+        assert!(render(&entry)
+            .ends_with("initrd /intel-ucode.img\ninitrd /initrd.img\ninitrd /extra.img\n"));
+
+        let misordered =
+            parse("title Pop!_OS\nlinux /vmlinuz\ninitrd /initrd.img\ninitrd /amd-ucode.img\n");
+        assert!(misordered.microcode_misordered());
+    }
+
+    #[test]
+    fn unmodelled_keys_are_kept() {
+        let entry = parse(
+            "title Pop!_OS\nuki /EFI/Linux/pop.efi\n# comment\nprofile 1\nx-vendor yes\n\
+             linux /vmlinuz\n",
+        );
+        assert_eq!(
+            entry.other_keys,
+            vec![
+                ("uki".to_owned(), "/EFI/Linux/pop.efi".to_owned()),
+                ("profile".to_owned(), "1".to_owned()),
+            ]
+        );
+        assert_eq!(
+            render(&entry),
+            "title Pop!_OS\nlinux /vmlinuz\nuki /EFI/Linux/pop.efi\nprofile 1\nx-vendor yes\n"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Paths relative to the root of the ESP, as used by the `linux`, `initrd`, `efi`, and
+//! `devicetree` keys of entries.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A path on the ESP in a canonical form, with forward slashes and a single leading slash.
+///
+/// systemd-boot accepts either kind of separator, with or without a leading slash, so
+/// `\EFI\Pop_OS\vmlinuz.efi` and `EFI/Pop_OS/vmlinuz.efi` refer to the same file. Repeated
+/// separators and `.` components are removed, while `..` components are kept as they are.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EspPath(String);
+
+impl EspPath {
+    pub fn new(path: &str) -> Self {
+        let mut normalized = String::with_capacity(path.len() + 1);
+
+        for component in path.split(['/', '\\']) {
+            if component.is_empty() || component == "." {
+                continue;
+            }
+
+            normalized.push('/');
+            normalized.push_str(component);
+        }
+
+        if normalized.is_empty() {
+            normalized.push('/');
+        }
+
+        EspPath(normalized)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The path with backslashes, as the firmware and the kernel's `initrd=` option see it.
+    pub fn to_efi_string(&self) -> String {
+        self.0.replace('/', "\\")
+    }
+
+    /// The location of the file when the ESP is mounted at `esp`.
+    pub fn to_path<P: AsRef<Path>>(&self, esp: P) -> PathBuf {
+        esp.as_ref().join(self.0.trim_start_matches('/'))
+    }
+}
+
+impl fmt::Display for EspPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for EspPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for EspPath {
+    fn from(path: &str) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&String> for EspPath {
+    fn from(path: &String) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<String> for EspPath {
+    fn from(path: String) -> Self {
+        Self::new(&path)
+    }
+}
@@ -0,0 +1,138 @@
+//! Notifications of the changes which `SystemdBootConf` makes to the configuration on disk.
+
+use crate::entry::Entry;
+use crate::loader::LoaderConf;
+use crate::style::WriteStyle;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+/// A change to the loader configuration or its entries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChangeEvent {
+    /// An entry file was written which did not exist before.
+    EntryAdded { id: String },
+    /// An entry file was rewritten with different values.
+    EntryModified {
+        id: String,
+        changes: Vec<FieldChange>,
+    },
+    /// An entry file was removed.
+    EntryRemoved { id: String },
+    /// An entry file was renamed, such as when boot counting was enabled.
+    EntryRenamed { from: String, to: String },
+    /// The default entry in `loader.conf` changed.
+    DefaultChanged {
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// The menu timeout in `loader.conf` changed.
+    TimeoutChanged { old: Option<u32>, new: Option<u32> },
+}
+
+/// A key of an entry whose values changed. Keys such as `initrd` may have several values.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldChange {
+    pub key: String,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// The differences between the configuration in memory and on disk, as found by
+/// `SystemdBootConf::reload`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ChangeSet {
+    /// Entries which were found on disk, which were not loaded before.
+    pub added: Vec<String>,
+    /// Entries which were loaded before, which are no longer on disk.
+    pub removed: Vec<String>,
+    /// Entries whose values differ on disk, with the keys which changed.
+    pub modified: Vec<(String, Vec<FieldChange>)>,
+    /// The keys of `loader.conf` whose values differ on disk.
+    pub loader: Vec<FieldChange>,
+}
+
+impl ChangeSet {
+    /// Whether the configuration on disk matched the configuration in memory.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.loader.is_empty()
+    }
+}
+
+/// An observer of the changes which are made to the configuration.
+pub trait ChangeListener: Debug + Send + Sync {
+    fn changed(&self, event: &ChangeEvent);
+}
+
+/// The keys whose values differ between two versions of an entry, in the order they are written.
+pub(crate) fn entry_changes(old: &Entry, new: &Entry) -> Vec<FieldChange> {
+    diff(entry_fields(old), entry_fields(new))
+}
+
+/// The keys whose values differ between two versions of the loader conf.
+pub(crate) fn loader_changes(old: &LoaderConf, new: &LoaderConf) -> Vec<FieldChange> {
+    diff(loader_fields(old), loader_fields(new))
+}
+
+fn diff(
+    old: BTreeMap<String, Vec<String>>,
+    new: BTreeMap<String, Vec<String>>,
+) -> Vec<FieldChange> {
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let old = old.get(key).cloned().unwrap_or_default();
+            let new = new.get(key).cloned().unwrap_or_default();
+            if old == new {
+                None
+            } else {
+                Some(FieldChange {
+                    key: key.clone(),
+                    old,
+                    new,
+                })
+            }
+        })
+        .collect()
+}
+
+/// The values of each key, as the entry is written.
+fn entry_fields(entry: &Entry) -> BTreeMap<String, Vec<String>> {
+    let mut entry = entry.clone();
+    // Compare options by their values, rather than how they were split across lines.
+    entry.options_raw.clear();
+
+    let mut contents = Vec::new();
+    let _ = crate::format::render_entry_conf(&entry, &WriteStyle::default(), &mut contents);
+    fields(&contents)
+}
+
+/// The values of each key, as the loader conf is written.
+fn loader_fields(conf: &LoaderConf) -> BTreeMap<String, Vec<String>> {
+    let mut contents = Vec::new();
+    let _ = crate::format::render_loader_conf(conf, &WriteStyle::default(), &mut contents);
+    fields(&contents)
+}
+
+fn fields(contents: &[u8]) -> BTreeMap<String, Vec<String>> {
+    let mut fields: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for line in String::from_utf8_lossy(contents).lines() {
+        let (key, value) = match line.find(' ') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => (line, ""),
+        };
+
+        fields
+            .entry(key.to_owned())
+            .or_default()
+            .push(value.to_owned());
+    }
+
+    fields
+}
@@ -0,0 +1,37 @@
+//! The removable media fallback path, `EFI/BOOT/BOOT<ARCH>.EFI`, which firmware boots when it
+//! has no boot entry for the ESP, and which `bootctl install` fills with a copy of systemd-boot.
+
+use std::path::PathBuf;
+
+/// How the fallback loader compares to the installed systemd-boot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FallbackState {
+    /// The fallback loader is identical to systemd-boot.
+    Identical,
+    /// The fallback loader is another version or build of systemd-boot, which may not parse the
+    /// configuration in the same way.
+    Stale,
+    /// There is no fallback loader.
+    Missing,
+    /// The fallback loader is not systemd-boot, such as shim, and is left alone.
+    Foreign,
+}
+
+/// The fallback loader for one architecture.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FallbackLoader {
+    /// The EFI name of the architecture, such as `x64`.
+    pub architecture: String,
+    pub loader: PathBuf,
+    pub fallback: PathBuf,
+    pub loader_version: Option<String>,
+    pub fallback_version: Option<String>,
+    pub state: FallbackState,
+}
+
+impl FallbackLoader {
+    /// Whether the fallback loader should be replaced with a copy of systemd-boot.
+    pub fn needs_fix(&self) -> bool {
+        self.state == FallbackState::Stale || self.state == FallbackState::Missing
+    }
+}
@@ -0,0 +1,46 @@
+//! The constraints of the FAT filesystem of the ESP on file names, which Linux reports only
+//! as `EINVAL` when a file is created.
+
+/// Why a name cannot be given to a file on a FAT filesystem.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Error)]
+pub enum FatNameError {
+    #[error("the name is empty")]
+    Empty,
+    #[error("the name contains {:?}, which FAT does not allow", _0)]
+    ForbiddenCharacter(char),
+    #[error("the name is longer than 255 characters")]
+    TooLong,
+    #[error("the name ends with a dot or a space, which FAT drops")]
+    TrailingDotOrSpace,
+}
+
+/// Checks that a file name can be created on a FAT filesystem with long file names.
+pub fn validate_name(name: &str) -> Result<(), FatNameError> {
+    if name.is_empty() {
+        return Err(FatNameError::Empty);
+    }
+
+    if let Some(c) = name
+        .chars()
+        .find(|&c| c.is_control() || "\"*/:<>?\\|".contains(c))
+    {
+        return Err(FatNameError::ForbiddenCharacter(c));
+    }
+
+    if name.ends_with(['.', ' ']) && name != "." && name != ".." {
+        return Err(FatNameError::TrailingDotOrSpace);
+    }
+
+    if name.encode_utf16().count() > 255 {
+        return Err(FatNameError::TooLong);
+    }
+
+    Ok(())
+}
+
+/// Checks each component of a path on the ESP, which may be separated by `/` or `\`.
+pub fn validate_path(path: &str) -> Result<(), FatNameError> {
+    path.split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .try_for_each(validate_name)
+}
@@ -0,0 +1,256 @@
+//! An abstraction over the filesystem operations used by `SystemdBootConf`, so that boot
+//! configuration logic may be tested without a real ESP.
+
+mod memory;
+mod retry;
+
+pub use self::memory::MemoryFilesystem;
+pub use self::retry::{is_transient, RetryPolicy, RetryingFilesystem};
+
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The kind of a file, as reported by `Filesystem::metadata`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// Information about a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Metadata {
+    pub kind: FileKind,
+    pub len: u64,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Other
+        };
+
+        Metadata {
+            kind,
+            len: metadata.len(),
+        }
+    }
+}
+
+/// The filesystem operations that `SystemdBootConf` performs.
+pub trait Filesystem: Debug + Send + Sync {
+    /// The paths of the files in a directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Read the contents of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Create or truncate a file, and write the contents to it.
+    ///
+    /// A symlink is written through to the file it points to, rather than replaced.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Write a file as `write` does, but readable and writable only by its owner, for secrets
+    /// such as the random seed. Filesystems without permissions write it as `write` does.
+    fn write_private(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.write(path, contents)
+    }
+
+    /// Flush the contents of a file to the device. Does nothing by default.
+    fn sync(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The space available to new files on the filesystem containing a path, in bytes, or `None`
+    /// if it is unknown, as it is by default.
+    fn available_space(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Whether the filesystem containing a path is mounted read-only, which it is not by default.
+    fn is_read_only(&self, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove a file.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove an empty directory.
+    ///
+    /// This fails with `io::ErrorKind::Unsupported` by default.
+    fn remove_dir(&self, _path: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this filesystem cannot remove directories",
+        ))
+    }
+
+    /// Create a directory and all of its missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Information about a file, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Information about a file, without following symlinks.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// The absolute path of a file, with all symlinks resolved.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+}
+
+/// The filesystem of the running system, used by default.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RealFilesystem;
+
+impl Filesystem for RealFilesystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    #[cfg(unix)]
+    fn write_private(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+
+        // The mode only applies to new files. FAT may refuse to change it on an existing file,
+        // where the mount options decide the permissions instead.
+        match file.set_permissions(fs::Permissions::from_mode(0o600)) {
+            Err(why) if why.kind() != io::ErrorKind::PermissionDenied => return Err(why),
+            _ => (),
+        }
+
+        file.write_all(contents)
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        fs::File::open(path)?.sync_all()
+    }
+
+    #[cfg(unix)]
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        let stat = statvfs(path)?;
+
+        // The widths of these fields differ between platforms.
+        #[allow(clippy::unnecessary_cast)]
+        Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+    }
+
+    #[cfg(unix)]
+    fn is_read_only(&self, path: &Path) -> io::Result<bool> {
+        Ok(statvfs(path)?.f_flag & libc::ST_RDONLY != 0)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Metadata::from)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+/// The statistics of the filesystem containing a path.
+#[cfg(unix)]
+pub(crate) fn statvfs(path: &Path) -> io::Result<libc::statvfs> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { stat.assume_init() })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn write_private_is_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("random-seed");
+
+        RealFilesystem.write_private(&path, b"seed").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"seed");
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        RealFilesystem.write_private(&path, b"new").unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+    }
+}
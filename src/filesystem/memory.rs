@@ -0,0 +1,479 @@
+use super::{FileKind, Filesystem, Metadata};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Symlinks nested deeper than this are treated as a loop, as Linux does.
+const MAX_SYMLINK_DEPTH: u32 = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    File(Vec<u8>),
+    Directory,
+    Symlink(PathBuf),
+}
+
+/// A filesystem which only exists in memory.
+///
+/// Clones share the same file tree, so a clone may be handed to `SystemdBootConf` while another is
+/// kept to inspect the files that were written. Paths are normalized lexically, so `.` and `..`
+/// components are resolved without consulting symlinks.
+#[derive(Debug, Clone)]
+pub struct MemoryFilesystem {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, Node>>>,
+}
+
+impl Default for MemoryFilesystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFilesystem {
+    /// An empty filesystem, containing only the root directory.
+    pub fn new() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), Node::Directory);
+
+        Self {
+            nodes: Arc::new(Mutex::new(nodes)),
+        }
+    }
+
+    /// Copy a directory tree from the real filesystem into memory, such as to simulate changes
+    /// to an ESP without modifying it.
+    pub fn snapshot<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        fn copy(memory: &MemoryFilesystem, path: &Path) -> io::Result<()> {
+            let metadata = fs::symlink_metadata(path)?;
+            if metadata.file_type().is_symlink() {
+                memory.add_symlink(path, fs::read_link(path)?);
+            } else if metadata.is_dir() {
+                memory.create_dir_all(path)?;
+                for entry in fs::read_dir(path)? {
+                    copy(memory, &entry?.path())?;
+                }
+            } else if metadata.is_file() {
+                memory.add_file(path, fs::read(path)?);
+            }
+
+            Ok(())
+        }
+
+        let memory = Self::new();
+        copy(&memory, root.as_ref())?;
+        Ok(memory)
+    }
+
+    /// Add a file, creating its parent directories as needed.
+    pub fn add_file<P: AsRef<Path>, C: Into<Vec<u8>>>(&self, path: P, contents: C) {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.nodes();
+        create_parents(&mut nodes, &path);
+        nodes.insert(path, Node::File(contents.into()));
+    }
+
+    /// Add a symlink pointing to `target`, creating its parent directories as needed.
+    pub fn add_symlink<P: AsRef<Path>, T: Into<PathBuf>>(&self, path: P, target: T) {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.nodes();
+        create_parents(&mut nodes, &path);
+        nodes.insert(path, Node::Symlink(target.into()));
+    }
+
+    /// The contents of every file in the tree.
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.nodes()
+            .iter()
+            .filter_map(|(path, node)| match node {
+                Node::File(contents) => Some((path.clone(), contents.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The contents of a file as a string, if it exists and is UTF-8.
+    pub fn read_to_string<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        let contents = self.read(path.as_ref()).ok()?;
+        String::from_utf8(contents).ok()
+    }
+
+    fn nodes(&self) -> MutexGuard<'_, BTreeMap<PathBuf, Node>> {
+        self.nodes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Resolve the symlinks in a path, optionally leaving a symlink in the last component.
+    fn resolve(&self, path: &Path, follow_last: bool) -> io::Result<PathBuf> {
+        resolve(&self.nodes(), &normalize(path), follow_last, 0)
+    }
+}
+
+impl Filesystem for MemoryFilesystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = self.resolve(path, true)?;
+        let nodes = self.nodes();
+
+        match nodes.get(&dir) {
+            Some(Node::Directory) => (),
+            Some(_) => return Err(not_a_directory()),
+            None => return Err(not_found()),
+        }
+
+        // Children are listed under the requested path, rather than the resolved one.
+        let base = normalize(path);
+        Ok(nodes
+            .keys()
+            .filter(|child| child.parent() == Some(&dir))
+            .filter_map(|child| child.file_name().map(|name| base.join(name)))
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = self.resolve(path, true)?;
+        match self.nodes().get(&path) {
+            Some(Node::File(contents)) => Ok(contents.clone()),
+            Some(_) => Err(io::Error::other("not a file")),
+            None => Err(not_found()),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = self.resolve(path, true)?;
+        let mut nodes = self.nodes();
+        require_parent(&nodes, &path)?;
+
+        match nodes.get(&path) {
+            Some(Node::Directory) => Err(is_a_directory()),
+            _ => {
+                nodes.insert(path, Node::File(contents.to_vec()));
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = self.resolve(from, false)?;
+        let to = self.resolve(to, false)?;
+        let mut nodes = self.nodes();
+
+        if from == to {
+            return Ok(());
+        }
+
+        require_parent(&nodes, &to)?;
+
+        // As with rename(2), a directory may only replace an empty directory, and a file may not
+        // replace a directory.
+        match (nodes.get(&from), nodes.get(&to)) {
+            (None, _) => return Err(not_found()),
+            (Some(Node::Directory), _) if to.starts_with(&from) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot move a directory into itself",
+                ))
+            }
+            (Some(Node::Directory), Some(Node::Directory))
+                if nodes.keys().any(|child| child.parent() == Some(&to)) =>
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    "directory not empty",
+                ))
+            }
+            (Some(Node::Directory), Some(Node::Directory)) => (),
+            (Some(Node::Directory), Some(_)) => return Err(not_a_directory()),
+            (Some(_), Some(Node::Directory)) => return Err(is_a_directory()),
+            _ => (),
+        }
+
+        nodes.remove(&to);
+
+        // Directories are moved along with everything beneath them.
+        let moved = nodes
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for path in moved {
+            if let Some(node) = nodes.remove(&path) {
+                let relative = path.strip_prefix(&from).unwrap_or_else(|_| Path::new(""));
+                let target = if relative.as_os_str().is_empty() {
+                    to.clone()
+                } else {
+                    to.join(relative)
+                };
+
+                nodes.insert(target, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let path = self.resolve(path, false)?;
+        let mut nodes = self.nodes();
+
+        match nodes.get(&path) {
+            Some(Node::Directory) => Err(is_a_directory()),
+            Some(_) => {
+                nodes.remove(&path);
+                Ok(())
+            }
+            None => Err(not_found()),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let path = self.resolve(path, false)?;
+        let mut nodes = self.nodes();
+
+        match nodes.get(&path) {
+            Some(Node::Directory) if path.parent().is_none() => {
+                Err(io::Error::other("cannot remove the root directory"))
+            }
+            Some(Node::Directory) => {
+                if nodes.keys().any(|child| child.parent() == Some(&path)) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::DirectoryNotEmpty,
+                        "directory not empty",
+                    ));
+                }
+
+                nodes.remove(&path);
+                Ok(())
+            }
+            Some(_) => Err(not_a_directory()),
+            None => Err(not_found()),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = self.resolve(path, true)?;
+        let mut nodes = self.nodes();
+
+        match nodes.get(&path) {
+            Some(Node::Directory) => Ok(()),
+            Some(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists")),
+            None => {
+                create_parents(&mut nodes, &path);
+                nodes.insert(path, Node::Directory);
+                Ok(())
+            }
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = self.resolve(path, true)?;
+        self.nodes().get(&path).map(metadata).ok_or_else(not_found)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = self.resolve(path, false)?;
+        self.nodes().get(&path).map(metadata).ok_or_else(not_found)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = self.resolve(path, true)?;
+        if self.nodes().contains_key(&path) {
+            Ok(path)
+        } else {
+            Err(not_found())
+        }
+    }
+}
+
+fn metadata(node: &Node) -> Metadata {
+    match node {
+        Node::File(contents) => Metadata {
+            kind: FileKind::File,
+            len: contents.len() as u64,
+        },
+        Node::Directory => Metadata {
+            kind: FileKind::Directory,
+            len: 0,
+        },
+        Node::Symlink(target) => Metadata {
+            kind: FileKind::Symlink,
+            len: target.as_os_str().len() as u64,
+        },
+    }
+}
+
+/// Resolves `.` and `..` components lexically.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+fn resolve(
+    nodes: &BTreeMap<PathBuf, Node>,
+    path: &Path,
+    follow_last: bool,
+    depth: u32,
+) -> io::Result<PathBuf> {
+    if depth > MAX_SYMLINK_DEPTH {
+        return Err(io::Error::other("too many levels of symbolic links"));
+    }
+
+    let components = path.components().collect::<Vec<_>>();
+    let mut resolved = PathBuf::new();
+
+    for (pos, component) in components.iter().enumerate() {
+        resolved.push(component);
+        let last = pos + 1 == components.len();
+
+        if let Some(Node::Symlink(target)) = nodes.get(&resolved) {
+            if !last || follow_last {
+                let parent = resolved.parent().map(Path::to_path_buf).unwrap_or_default();
+                resolved = resolve(nodes, &normalize(&parent.join(target)), true, depth + 1)?;
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+fn create_parents(nodes: &mut BTreeMap<PathBuf, Node>, path: &Path) {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor.as_os_str().is_empty() {
+            continue;
+        }
+
+        nodes
+            .entry(ancestor.to_path_buf())
+            .or_insert(Node::Directory);
+    }
+}
+
+fn require_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => match nodes.get(parent) {
+            Some(Node::Directory) => Ok(()),
+            Some(_) => Err(not_a_directory()),
+            None => Err(not_found()),
+        },
+        _ => Ok(()),
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+fn not_a_directory() -> io::Error {
+    io::Error::other("not a directory")
+}
+
+fn is_a_directory() -> io::Error {
+    io::Error::other("is a directory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_directory_replaces_empty_directory() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/EFI/new/grubx64.efi", "new");
+        fs.create_dir_all(Path::new("/efi/EFI/old")).unwrap();
+
+        fs.rename(Path::new("/efi/EFI/new"), Path::new("/efi/EFI/old"))
+            .unwrap();
+
+        let files = fs.files();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec![Path::new("/efi/EFI/old/grubx64.efi")]
+        );
+        assert!(fs.metadata(Path::new("/efi/EFI/new")).is_err());
+    }
+
+    #[test]
+    fn rename_directory_refuses_non_empty_directory() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/EFI/new/grubx64.efi", "new");
+        fs.add_file("/efi/EFI/old/shimx64.efi", "old");
+
+        let why = fs
+            .rename(Path::new("/efi/EFI/new"), Path::new("/efi/EFI/old"))
+            .unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::DirectoryNotEmpty);
+        assert_eq!(fs.files().len(), 2);
+
+        let why = fs
+            .rename(Path::new("/efi/EFI"), Path::new("/efi/EFI/new/EFI"))
+            .unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rename_file() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/a.conf", "a");
+        fs.add_file("/efi/b.conf", "b");
+        fs.create_dir_all(Path::new("/efi/dir")).unwrap();
+
+        fs.rename(Path::new("/efi/a.conf"), Path::new("/efi/b.conf"))
+            .unwrap();
+        assert_eq!(fs.read_to_string("/efi/b.conf").as_deref(), Some("a"));
+        assert!(fs.read_to_string("/efi/a.conf").is_none());
+
+        assert!(fs
+            .rename(Path::new("/efi/b.conf"), Path::new("/efi/dir"))
+            .is_err());
+        assert!(fs
+            .rename(Path::new("/efi/dir"), Path::new("/efi/b.conf"))
+            .is_err());
+        assert!(fs
+            .rename(Path::new("/efi/missing"), Path::new("/efi/c.conf"))
+            .is_err());
+    }
+
+    #[test]
+    fn write_follows_symlinks() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/loader/real.conf", "timeout 3\n");
+        fs.add_symlink("/efi/loader/loader.conf", "real.conf");
+
+        fs.write(Path::new("/efi/loader/loader.conf"), b"timeout 5\n")
+            .unwrap();
+
+        let link = fs
+            .symlink_metadata(Path::new("/efi/loader/loader.conf"))
+            .unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(
+            fs.read_to_string("/efi/loader/real.conf").as_deref(),
+            Some("timeout 5\n")
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/loader/entries/a.conf", "a");
+
+        let entries = Path::new("/efi/loader/entries");
+        assert!(fs.remove(entries).is_err());
+        assert!(fs.remove_dir(entries).is_err());
+
+        fs.remove(&entries.join("a.conf")).unwrap();
+        assert!(fs.remove(&entries.join("a.conf")).is_err());
+        fs.remove_dir(entries).unwrap();
+        assert!(fs.metadata(entries).is_err());
+    }
+}
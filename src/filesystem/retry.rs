@@ -0,0 +1,205 @@
+use super::{Filesystem, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often, and how patiently, operations which fail with transient errors are retried.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// How many times an operation is attempted in total, including the first attempt.
+    pub attempts: u32,
+    /// How long to wait before the first retry. The wait doubles after each retry.
+    pub backoff: Duration,
+    /// The longest that the wait between retries may grow to.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 50ms and then 100ms between them.
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Attempt operations this many times in total, with the default backoff.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts,
+            ..Self::default()
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Run `operation` until it succeeds, fails with an error which is not transient, or has
+    /// been attempted as often as the policy allows.
+    pub fn run<T, F: FnMut() -> io::Result<T>>(
+        &self,
+        path: &Path,
+        mut operation: F,
+    ) -> io::Result<T> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+
+        loop {
+            match operation() {
+                Err(ref why) if attempt < self.attempts && is_transient(why) => {
+                    warn!(
+                        "attempt {} of {} on {:?} failed, retrying in {:?}: {}",
+                        attempt, self.attempts, path, backoff, why
+                    );
+
+                    thread::sleep(backoff);
+                    backoff = self.next_backoff(backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The wait after `backoff`, which doubles it without overflowing, up to `max_backoff`.
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        backoff
+            .checked_mul(2)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Whether an IO error may not recur if the operation is attempted again, such as an `EIO` from
+/// marginal flash media, or an `EAGAIN`.
+///
+/// Errors such as a missing file, denied access, or a read-only filesystem are persistent.
+pub fn is_transient(error: &io::Error) -> bool {
+    match error.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => true,
+        #[cfg(unix)]
+        _ => matches!(error.raw_os_error(), Some(libc::EIO) | Some(libc::EAGAIN)),
+        #[cfg(not(unix))]
+        _ => false,
+    }
+}
+
+/// Retries the reads and writes of another filesystem which fail with transient errors.
+///
+/// Renames and removals are not retried, as they may have taken effect before failing.
+#[derive(Debug, Clone)]
+pub struct RetryingFilesystem {
+    inner: Arc<dyn Filesystem>,
+    policy: RetryPolicy,
+}
+
+impl RetryingFilesystem {
+    pub fn new<F: Filesystem + 'static>(inner: F, policy: RetryPolicy) -> Self {
+        Self::from_arc(Arc::new(inner), policy)
+    }
+
+    pub(crate) fn from_arc(inner: Arc<dyn Filesystem>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+impl Filesystem for RetryingFilesystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.policy.run(path, || self.inner.read_dir(path))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.policy.run(path, || self.inner.read(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.policy.run(path, || self.inner.write(path, contents))
+    }
+
+    fn write_private(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.policy
+            .run(path, || self.inner.write_private(path, contents))
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(path, || self.inner.sync(path))
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<Option<u64>> {
+        self.policy.run(path, || self.inner.available_space(path))
+    }
+
+    fn is_read_only(&self, path: &Path) -> io::Result<bool> {
+        self.inner.is_read_only(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(path, || self.inner.create_dir_all(path))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.policy.run(path, || self.inner.metadata(path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.policy.run(path, || self.inner.symlink_metadata(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.policy.run(path, || self.inner.canonicalize(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::default();
+        let backoff = policy.next_backoff(Duration::from_millis(50));
+        assert_eq!(backoff, Duration::from_millis(100));
+        assert_eq!(
+            policy.next_backoff(Duration::from_millis(800)),
+            policy.max_backoff
+        );
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let policy = RetryPolicy::default().max_backoff(Duration::MAX);
+        assert_eq!(policy.next_backoff(Duration::MAX), Duration::MAX);
+
+        let policy = RetryPolicy::default().max_backoff(Duration::from_secs(u64::MAX));
+        let backoff = Duration::from_secs(u64::MAX / 2 + 1);
+        assert_eq!(policy.next_backoff(backoff), policy.max_backoff);
+    }
+}
@@ -0,0 +1,168 @@
+//! Builders for realistic ESP layouts, for use in the tests of crates which depend on this one.
+
+use crate::filesystem::{Filesystem, MemoryFilesystem, RealFilesystem};
+use crate::{Error, SystemdBootConf};
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// The machine id given to generated entries.
+pub const MACHINE_ID: &str = "0123456789abcdef0123456789abcdef";
+
+/// The root filesystem UUID used in the options of generated entries.
+pub const ROOT_UUID: &str = "ed646eba-b8a3-4c79-8f93-5ee1a25c6ec3";
+
+/// Enough of a PE header for payloads to be recognized as EFI binaries: the DOS header, which
+/// points to the PE signature at 0x40, an empty COFF header, and the PE32+ magic.
+const PE_STUB: &[u8] = b"MZ\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\0\0\0\0\0\0\0\0\0\0\x40\0\0\0\
+    PE\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\
+    \0\0\0\0\x0b\x02";
+
+/// An ESP layout in a temporary directory, which is removed when the fixture is dropped.
+#[derive(Debug)]
+pub struct EspFixture {
+    dir: TempDir,
+}
+
+impl EspFixture {
+    pub fn builder() -> EspFixtureBuilder {
+        EspFixtureBuilder::default()
+    }
+
+    /// The root of the ESP.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Load the configuration of the ESP.
+    pub fn load(&self) -> Result<SystemdBootConf, Error> {
+        SystemdBootConf::new(self.path())
+    }
+}
+
+/// Describes the files of an ESP layout.
+#[derive(Debug, Default, Clone)]
+pub struct EspFixtureBuilder {
+    loader_conf: Option<String>,
+    default: Option<String>,
+    timeout: Option<u32>,
+    files: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl EspFixtureBuilder {
+    /// Set the `default` key of the loader conf.
+    pub fn default_entry<S: Into<String>>(mut self, id: S) -> Self {
+        self.default = Some(id.into());
+        self
+    }
+
+    /// Set the `timeout` key of the loader conf.
+    pub fn timeout(mut self, timeout: u32) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Use this text as the loader conf, instead of generating it from `default_entry` and
+    /// `timeout`.
+    pub fn loader_conf<S: Into<String>>(mut self, contents: S) -> Self {
+        self.loader_conf = Some(contents.into());
+        self
+    }
+
+    /// Add an entry for a kernel version, along with its kernel and initrd.
+    pub fn entry(mut self, id: &str, version: &str) -> Self {
+        let linux = format!("/EFI/fixture/vmlinuz-{}.efi", version);
+        let initrd = format!("/EFI/fixture/initrd.img-{}", version);
+
+        let contents = format!(
+            "title Fixture OS\nversion {}\nmachine-id {}\nlinux {}\ninitrd {}\noptions root=UUID={} ro quiet splash\n",
+            version, MACHINE_ID, linux, initrd, ROOT_UUID
+        );
+
+        self.files.push((linux[1..].into(), PE_STUB.to_vec()));
+        self.files.push((initrd[1..].into(), b"initrd".to_vec()));
+        self.raw_entry(id, contents)
+    }
+
+    /// Add `count` entries with ascending kernel versions, named `fixture-0` onwards.
+    pub fn entries(mut self, count: usize) -> Self {
+        for pos in 0..count {
+            self = self.entry(&format!("fixture-{}", pos), &format!("6.{}.0", pos));
+        }
+
+        self
+    }
+
+    /// Add an entry file with the given contents.
+    pub fn raw_entry<S: Into<String>>(self, id: &str, contents: S) -> Self {
+        let path = format!("loader/entries/{}.conf", id);
+        self.file(path, contents.into())
+    }
+
+    /// Add an entry which fails to parse, as it lacks a title and a value for `linux`.
+    pub fn broken_entry(self, id: &str) -> Self {
+        self.raw_entry(id, "linux\noptions quiet\n")
+    }
+
+    /// Add a unified kernel image to `EFI/Linux`.
+    pub fn uki(self, name: &str) -> Self {
+        self.file(format!("EFI/Linux/{}.efi", name), PE_STUB)
+    }
+
+    /// Add a file at a path relative to the root of the ESP.
+    pub fn file<P: Into<PathBuf>, C: Into<Vec<u8>>>(mut self, path: P, contents: C) -> Self {
+        self.files.push((path.into(), contents.into()));
+        self
+    }
+
+    /// Create the layout in a new temporary directory.
+    pub fn build(self) -> io::Result<EspFixture> {
+        let dir = tempfile::Builder::new().prefix("esp-fixture").tempdir()?;
+        self.write_to(&RealFilesystem, dir.path())?;
+        Ok(EspFixture { dir })
+    }
+
+    /// Create the layout in memory, rooted at `root`.
+    pub fn build_in_memory<P: AsRef<Path>>(self, root: P) -> io::Result<MemoryFilesystem> {
+        let memory = MemoryFilesystem::new();
+        self.write_to(&memory, root.as_ref())?;
+        Ok(memory)
+    }
+
+    /// Create the layout in any filesystem, rooted at `root`.
+    pub fn write_to(&self, fs: &dyn Filesystem, root: &Path) -> io::Result<()> {
+        fs.create_dir_all(&root.join("loader/entries"))?;
+
+        let loader_conf = match self.loader_conf {
+            Some(ref contents) => contents.clone(),
+            None => {
+                let mut contents = String::new();
+                if let Some(ref default) = self.default {
+                    contents.push_str(&format!("default {}\n", default));
+                }
+
+                if let Some(timeout) = self.timeout {
+                    contents.push_str(&format!("timeout {}\n", timeout));
+                }
+
+                contents
+            }
+        };
+
+        fs.write(&root.join("loader/loader.conf"), loader_conf.as_bytes())?;
+
+        for (path, contents) in &self.files {
+            let path = root.join(path);
+            if let Some(parent) = path.parent() {
+                fs.create_dir_all(parent)?;
+            }
+
+            fs.write(&path, contents)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! The formats of the loader conf and of entry files, parsed from and rendered to bytes without
+//! any IO, so that tools which cannot access an ESP, such as web-based inspectors, may share the
+//! parsing of this crate.
+//!
+//! This module, and the types which it parses into, do not touch the filesystem or `/proc`, and
+//! build for targets other than Linux, such as `wasm32-unknown-unknown`.
+
+use crate::cmdline;
+use crate::entry::{Entry, EntryError};
+use crate::esp_path::EspPath;
+use crate::loader::{LoaderConf, LoaderError};
+use crate::parse::{ParseMode, ParseWarning};
+use crate::style::{Line, WriteStyle};
+use std::io;
+use std::path::Path;
+
+/// Parse the contents of an entry file named `<id>.conf`, recording the problems which are
+/// tolerated in lenient mode as warnings.
+pub fn parse_entry(
+    id: &str,
+    contents: &[u8],
+    mode: ParseMode,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<Entry, EntryError> {
+    let path = [id, ".conf"].concat();
+    Entry::parse_with_id(Path::new(&path), id.to_owned(), contents, mode, warnings)
+}
+
+/// Parse the contents of `loader.conf`, recording the problems which are tolerated in lenient
+/// mode as warnings.
+pub fn parse_loader_conf(
+    contents: &[u8],
+    mode: ParseMode,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<LoaderConf, LoaderError> {
+    LoaderConf::parse_file(Path::new("loader.conf"), contents, mode, warnings)
+}
+
+/// The contents of the file of an entry, as `SystemdBootConf` writes it in this style.
+pub fn entry_conf(entry: &Entry, style: &WriteStyle) -> Vec<u8> {
+    let mut contents = Vec::new();
+    // Writing to a vector does not fail.
+    let _ = render_entry_conf(entry, style, &mut contents);
+    contents
+}
+
+/// The contents of `loader.conf`, as `SystemdBootConf` writes it in this style.
+pub fn loader_conf(conf: &LoaderConf, style: &WriteStyle) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let _ = render_loader_conf(conf, style, &mut contents);
+    contents
+}
+
+/// Writes an entry with its keys in canonical order, keeping its original options lines if
+/// they still match its options.
+pub(crate) fn render_entry_conf(
+    entry: &Entry,
+    style: &WriteStyle,
+    file: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut lines = vec![Line::new("title", &entry.title)];
+
+    if let Some(ref version) = entry.version {
+        lines.push(Line::new("version", version));
+    }
+
+    if let Some(ref machine_id) = entry.machine_id {
+        lines.push(Line::new("machine-id", machine_id));
+    }
+
+    if let Some(ref sort_key) = entry.sort_key {
+        lines.push(Line::new("sort-key", sort_key));
+    }
+
+    if !entry.linux.is_empty() {
+        lines.push(Line::new("linux", EspPath::new(&entry.linux)));
+    }
+
+    if let Some(ref efi) = entry.efi {
+        lines.push(Line::new("efi", EspPath::new(efi)));
+    }
+
+    for initrd in &entry.initrd {
+        lines.push(Line::new("initrd", EspPath::new(initrd)));
+    }
+
+    if let Some(ref devicetree) = entry.devicetree {
+        lines.push(Line::new("devicetree", EspPath::new(devicetree)));
+    }
+
+    match entry.unmodified_options_raw() {
+        Some(raw) if style.keep_options_lines => {
+            for options in raw {
+                lines.push(Line {
+                    key: "options",
+                    value: options.clone(),
+                    verbatim: true,
+                });
+            }
+        }
+        _ if !entry.options.is_empty() => {
+            lines.push(Line::new("options", cmdline::join(&entry.options)));
+        }
+        _ => (),
+    }
+
+    if let Some(ref architecture) = entry.architecture {
+        lines.push(Line::new("architecture", architecture));
+    }
+
+    if let Some(ref grub_users) = entry.grub_users {
+        lines.push(Line::new("grub_users", grub_users));
+    }
+
+    if let Some(ref grub_arg) = entry.grub_arg {
+        lines.push(Line::new("grub_arg", grub_arg));
+    }
+
+    if let Some(ref grub_class) = entry.grub_class {
+        lines.push(Line::new("grub_class", grub_class));
+    }
+
+    for (key, value) in entry.other_keys.iter().chain(&entry.extensions) {
+        lines.push(Line::new(key, value));
+    }
+
+    style.write(lines, file)
+}
+
+pub(crate) fn render_loader_conf(
+    conf: &LoaderConf,
+    style: &WriteStyle,
+    file: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut lines = Vec::new();
+
+    if let Some(ref default) = conf.default {
+        lines.push(Line::new("default", default));
+    }
+
+    if let Some(menu) = conf.menu {
+        lines.push(Line::new("timeout", menu.as_str()));
+    } else if let Some(timeout) = conf.timeout {
+        lines.push(Line::new("timeout", timeout));
+    }
+
+    if let Some(editor) = conf.editor {
+        lines.push(Line::new("editor", if editor { "yes" } else { "no" }));
+    }
+
+    if let Some(enroll) = conf.secure_boot_enroll {
+        lines.push(Line::new("secure-boot-enroll", enroll.as_str()));
+    }
+
+    for (key, value) in &conf.other_keys {
+        lines.push(Line::new(key, value));
+    }
+
+    style.write(lines, file)
+}
+
+/// Strips a UTF-8 byte order mark and carriage return from a line, which are left behind when
+/// configuration files on the ESP are edited on Windows.
+pub(crate) fn clean_line(line: &str) -> &str {
+    line.trim_start_matches('\u{feff}').trim_end_matches('\r')
+}
@@ -0,0 +1,77 @@
+//! Finding and removing the kernels, initrds, and UKIs which no entry boots anymore.
+
+use std::path::{Path, PathBuf};
+
+/// Directories whose images are found by systemd-boot itself, rather than through entries, and
+/// which are therefore never collected.
+pub(crate) const UNMANAGED_DIRS: &[&str] = &["/EFI/Linux", "/EFI/systemd", "/EFI/BOOT"];
+
+/// File name prefixes of kernels and initrds.
+const PAYLOAD_PREFIXES: &[&str] = &["vmlinuz", "vmlinux", "bzimage", "initrd", "initramfs"];
+
+/// File extensions of EFI images and devicetrees.
+const PAYLOAD_EXTENSIONS: &[&str] = &["efi", "dtb"];
+
+/// Options for `SystemdBootConf::gc`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct GcOptions {
+    /// Report the files which would be removed, without removing them.
+    pub dry_run: bool,
+    /// Files which are kept even if no entry references them.
+    ///
+    /// A path with a single component, such as `vmlinuz.old`, matches files by their name in
+    /// any directory. Other paths must match the absolute path of the file.
+    pub exclude: Vec<PathBuf>,
+}
+
+impl GcOptions {
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        self.exclude.iter().any(|exclude| {
+            if exclude.components().count() == 1 && !exclude.is_absolute() {
+                path.file_name() == Some(exclude.as_os_str())
+            } else {
+                exclude == path
+            }
+        })
+    }
+}
+
+/// A boot payload which no entry references.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OrphanedFile {
+    pub path: PathBuf,
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+/// The files which `SystemdBootConf::gc` removed, or would have removed in a dry run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub files: Vec<OrphanedFile>,
+}
+
+impl GcReport {
+    /// The space which was reclaimed, or would be reclaimed in a dry run, in bytes.
+    pub fn reclaimed(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+/// Whether a file name is that of a kernel, initrd, UKI, or devicetree.
+///
+/// Other files in a kernel's directory, such as `config-*` and `System.map-*` in `/boot`, are
+/// never considered orphans. Neither are CPU microcode images, which are shared by entries.
+pub(crate) fn is_payload_name(name: &str) -> bool {
+    if crate::entry::is_microcode(name) {
+        return false;
+    }
+
+    let name = name.to_ascii_lowercase();
+    let extension = Path::new(&name).extension().and_then(|ext| ext.to_str());
+
+    PAYLOAD_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || extension.is_some_and(|extension| PAYLOAD_EXTENSIONS.contains(&extension))
+}
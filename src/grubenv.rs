@@ -0,0 +1,115 @@
+//! The GRUB environment block, whose variables Fedora and RHEL reference from entry options,
+//! such as `options $kernelopts`.
+//!
+//! systemd-boot does not expand these variables, but GRUB does when it boots the same entries.
+
+use crate::cmdline;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// The variables of a GRUB environment block, such as `/boot/grub2/grubenv`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrubEnv {
+    pub vars: BTreeMap<String, String>,
+}
+
+impl GrubEnv {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = std::fs::read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&contents)))
+    }
+
+    /// Parses the `key=value` lines of an environment block.
+    ///
+    /// Comments, and the `#` padding which fills the block to its fixed size, are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut vars = BTreeMap::new();
+
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(pos) => (&line[..pos], &line[pos + 1..]),
+                None => continue,
+            };
+
+            // grub-editenv escapes line breaks and backslashes in values.
+            let mut unescaped = String::with_capacity(value.len());
+            let mut chars = value.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some(c) => unescaped.push(c),
+                        None => unescaped.push('\\'),
+                    },
+                    c => unescaped.push(c),
+                }
+            }
+
+            vars.insert(key.to_owned(), unescaped);
+        }
+
+        GrubEnv { vars }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    /// Expands the `$name` and `${name}` variables in kernel options, as GRUB does when booting.
+    ///
+    /// A variable may expand to several options, and undefined variables expand to nothing.
+    pub fn expand<S: AsRef<str>>(&self, options: &[S]) -> Vec<String> {
+        options
+            .iter()
+            .flat_map(|option| {
+                let option = option.as_ref();
+                if option.contains('$') {
+                    cmdline::split(&self.expand_str(option))
+                } else {
+                    vec![option.to_owned()]
+                }
+            })
+            .collect()
+    }
+
+    fn expand_str(&self, text: &str) -> String {
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(pos) = rest.find('$') {
+            expanded.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+
+            let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+                match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => {
+                        expanded.push('$');
+                        continue;
+                    }
+                }
+            } else {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+
+            if name.is_empty() {
+                expanded.push('$');
+            } else if let Some(value) = self.get(name) {
+                expanded.push_str(value);
+            }
+
+            rest = after;
+        }
+
+        expanded.push_str(rest);
+        expanded
+    }
+}
@@ -0,0 +1,21 @@
+//! Hooks which run around every file that `SystemdBootConf` writes.
+
+use std::fmt::Debug;
+use std::io;
+use std::path::Path;
+
+/// External steps to perform before and after a file is written, such as signing an image,
+/// updating checksums, or notifying an audit daemon.
+///
+/// Both methods receive the path of the file, and the contents which are written to it.
+pub trait WriteHook: Debug + Send + Sync {
+    /// Called before the file is written. An error aborts the write.
+    fn before_write(&self, _path: &Path, _contents: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the file was written. An error is reported as a failure to write the file.
+    fn after_write(&self, _path: &Path, _contents: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
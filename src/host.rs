@@ -0,0 +1,93 @@
+//! Lookups of the system that entries are managed for, which may be installed at another root,
+//! such as the target of an installer or an image being built.
+
+use crate::cmdline;
+use crate::kernel::{self, InstalledKernel};
+use crate::kernel_install::InstallConf;
+use crate::luks::{self, CrypttabEntry};
+use crate::machine_id;
+use crate::os_release::OsRelease;
+use crate::rootfs::RootFs;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The root of the system whose machine id, os-release, kernel command line, and kernels are
+/// read, which is `/` for the running system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Host {
+    root: PathBuf,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::running()
+    }
+}
+
+impl Host {
+    /// The running system.
+    pub fn running() -> Self {
+        Self::at("/")
+    }
+
+    /// The system installed at `root`, which is managed from outside of it.
+    pub fn at<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether this is the running system, rather than one at an alternate root.
+    pub fn is_running(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Resolves an absolute path of the system, such as `/etc/fstab`, under its root.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// The machine id of the system, from `/etc/machine-id`.
+    pub fn machine_id(&self) -> io::Result<String> {
+        machine_id::load_from_root(&self.root)
+    }
+
+    /// The token that kernel-install names entries with, from `/etc/kernel/entry-token`.
+    pub fn entry_token(&self) -> io::Result<String> {
+        let token = fs::read_to_string(self.resolve("/etc/kernel/entry-token"))?;
+        Ok(token.trim().to_owned())
+    }
+
+    pub fn os_release(&self) -> io::Result<OsRelease> {
+        OsRelease::load_from_root(&self.root)
+    }
+
+    /// The command line which kernel-install gives to new entries, from `/etc/kernel/cmdline`.
+    pub fn kernel_cmdline(&self) -> io::Result<Vec<String>> {
+        cmdline::load_kernel_cmdline_from_root(&self.root)
+    }
+
+    /// The configuration of kernel-install, from `/etc/kernel/install.conf`.
+    pub fn install_conf(&self) -> io::Result<InstallConf> {
+        InstallConf::load_from_root(&self.root)
+    }
+
+    /// The encrypted volumes of the system, from `/etc/crypttab`.
+    pub fn crypttab(&self) -> io::Result<Vec<CrypttabEntry>> {
+        luks::load_crypttab_from_root(&self.root)
+    }
+
+    /// The kernels installed on the system, newest first.
+    pub fn installed_kernels(&self) -> io::Result<Vec<InstalledKernel>> {
+        kernel::installed_in_root(&self.root)
+    }
+
+    /// The filesystem mounted at the root of the system.
+    pub fn root_fs(&self) -> io::Result<RootFs> {
+        RootFs::detect_at(&self.root)
+    }
+}
@@ -0,0 +1,133 @@
+//! Validated entry ids, which compare as the case-insensitive FAT filesystem of the ESP compares
+//! their file names.
+
+use crate::entry::{validate_id, BootCounter, EntryError};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The id of an entry, which is the name of its file without the `.conf` extension.
+///
+/// An id is valid as the file name of an entry, both under the Boot Loader Specification and on
+/// FAT. Ids which differ only by case are equal, as they name the same file on the ESP, though
+/// the case that an id was given in is kept.
+#[derive(Debug, Clone)]
+pub struct EntryId(String);
+
+impl EntryId {
+    pub fn new<S: Into<String>>(id: S) -> Result<Self, EntryError> {
+        let id = id.into();
+        validate_id(&id)?;
+        Ok(EntryId(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// The id without its boot counter suffix, such as `pop` for `pop+3-1`.
+    pub fn base(&self) -> &str {
+        BootCounter::parse(&self.0).map_or(&self.0, |(base, _)| base)
+    }
+
+    /// Whether this id is the same as `id`, ignoring case.
+    pub fn matches(&self, id: &str) -> bool {
+        self.0 == id || self.folded() == id.to_lowercase()
+    }
+
+    fn folded(&self) -> String {
+        self.0.to_lowercase()
+    }
+}
+
+impl PartialEq for EntryId {
+    fn eq(&self, other: &Self) -> bool {
+        self.matches(&other.0)
+    }
+}
+
+impl Eq for EntryId {}
+
+impl PartialEq<str> for EntryId {
+    fn eq(&self, other: &str) -> bool {
+        self.matches(other)
+    }
+}
+
+impl PartialEq<&str> for EntryId {
+    fn eq(&self, other: &&str) -> bool {
+        self.matches(other)
+    }
+}
+
+impl PartialEq<String> for EntryId {
+    fn eq(&self, other: &String) -> bool {
+        self.matches(other)
+    }
+}
+
+impl Hash for EntryId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded().hash(state);
+    }
+}
+
+impl PartialOrd for EntryId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EntryId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.folded().cmp(&other.folded())
+    }
+}
+
+impl fmt::Display for EntryId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for EntryId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for EntryId {
+    type Err = EntryError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        EntryId::new(id)
+    }
+}
+
+impl TryFrom<&str> for EntryId {
+    type Error = EntryError;
+
+    fn try_from(id: &str) -> Result<Self, Self::Error> {
+        EntryId::new(id)
+    }
+}
+
+impl TryFrom<String> for EntryId {
+    type Error = EntryError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        EntryId::new(id)
+    }
+}
+
+impl From<EntryId> for String {
+    fn from(id: EntryId) -> Self {
+        id.0
+    }
+}
@@ -0,0 +1,23 @@
+//! Grouping the entries on a shared ESP by the OS installation which they belong to.
+
+use crate::entry::Entry;
+
+/// The entries of one OS installation on a shared ESP, as told apart by their machine id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Installation<'a> {
+    /// The machine id of the installation, which is `None` for the entries which have none.
+    pub machine_id: Option<&'a str>,
+    /// Whether this is the installation that the configuration is managed from, whose machine id
+    /// is that of the host.
+    pub local: bool,
+    pub entries: Vec<&'a Entry>,
+}
+
+impl<'a> Installation<'a> {
+    /// The sort key of the installation's entries, which is usually the `ID` of its os-release.
+    pub fn sort_key(&self) -> Option<&'a str> {
+        self.entries
+            .iter()
+            .find_map(|entry| entry.sort_key.as_deref())
+    }
+}
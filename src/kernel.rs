@@ -0,0 +1,158 @@
+//! Inspecting Linux kernel images, such as to find the version of the kernel an entry boots.
+
+use crate::pe::{self, PeImage};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The magic number of the x86 boot protocol's setup header.
+const SETUP_HEADER_MAGIC: &[u8] = b"HdrS";
+
+/// The banner which the kernel prints on boot, which uncompressed images contain.
+const BANNER: &[u8] = b"Linux version ";
+
+/// A kernel installed on a system, which entries may be generated for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstalledKernel {
+    pub version: String,
+    pub image: PathBuf,
+    /// The initrd which was generated for the kernel, if there is one.
+    pub initrd: Option<PathBuf>,
+}
+
+/// The kernels installed on the running system.
+pub fn installed() -> io::Result<Vec<InstalledKernel>> {
+    installed_in_root("/")
+}
+
+/// The kernels installed on the system at `root`, newest first.
+///
+/// Kernels are found in `usr/lib/modules/<version>/vmlinuz`, as kernel-install expects, and in
+/// `boot/vmlinuz-<version>`, as Debian and Fedora install them. Their initrds are found beside
+/// them, or in `boot` as `initrd.img-<version>` or `initramfs-<version>.img`.
+pub fn installed_in_root<P: AsRef<Path>>(root: P) -> io::Result<Vec<InstalledKernel>> {
+    let root = root.as_ref();
+    let boot = root.join("boot");
+    let mut kernels = BTreeMap::new();
+
+    let boot_initrd = |version: &str| {
+        [
+            format!("initrd.img-{}", version),
+            format!("initramfs-{}.img", version),
+        ]
+        .iter()
+        .map(|name| boot.join(name))
+        .find(|path| path.is_file())
+    };
+
+    for (name, path) in read_dir(&root.join("usr/lib/modules"))? {
+        let image = path.join("vmlinuz");
+        if image.is_file() {
+            let initrd = Some(path.join("initrd"))
+                .filter(|initrd| initrd.is_file())
+                .or_else(|| boot_initrd(&name));
+            kernels.insert(name.clone(), (image, initrd));
+        }
+    }
+
+    for (name, image) in read_dir(&boot)? {
+        if let Some(version) = name.strip_prefix("vmlinuz-") {
+            if image.is_file() && !kernels.contains_key(version) {
+                kernels.insert(version.to_owned(), (image, boot_initrd(version)));
+            }
+        }
+    }
+
+    let mut kernels: Vec<InstalledKernel> = kernels
+        .into_iter()
+        .map(|(version, (image, initrd))| InstalledKernel {
+            version,
+            image,
+            initrd,
+        })
+        .collect();
+
+    kernels.sort_by(|a, b| crate::version::compare(&b.version, &a.version));
+    Ok(kernels)
+}
+
+/// The UTF-8 names and paths in a directory, which is empty if it does not exist.
+fn read_dir(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(why),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if let Ok(name) = entry.file_name().into_string() {
+            files.push((name, entry.path()));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Whether an image is a PE executable which EFI firmware can run, as systemd-boot requires of
+/// the `linux` image of an entry.
+///
+/// Kernels built without `CONFIG_EFI_STUB`, and compressed images such as a gzipped arm64
+/// `Image.gz`, are not.
+pub fn is_efi_stub(data: &[u8]) -> bool {
+    PeImage::parse(data).is_ok()
+}
+
+/// The version of the kernel in an image, such as `6.9.3-76060903-generic`.
+///
+/// Unified kernel images record the version in their `.uname` section. x86 bzImages, which
+/// include EFI-stub kernels, point to their version string from the setup header. Other images
+/// are searched for the kernel's boot banner, which is only found in uncompressed images.
+pub fn version_from_image(data: &[u8]) -> Option<String> {
+    if let Ok(image) = PeImage::parse(data) {
+        if let Some(version) = image.section_text(".uname") {
+            return first_word(version.as_bytes());
+        }
+    }
+
+    if let Some(version) = bzimage_version(data) {
+        return Some(version);
+    }
+
+    data.windows(BANNER.len())
+        .position(|window| window == BANNER)
+        .and_then(|pos| first_word(&data[pos + BANNER.len()..]))
+}
+
+/// Reads the version string that the setup header's `kernel_version` field points to.
+fn bzimage_version(data: &[u8]) -> Option<String> {
+    if data.get(0x202..0x206) != Some(SETUP_HEADER_MAGIC) {
+        return None;
+    }
+
+    // The field was added in version 2.0 of the boot protocol.
+    let protocol = pe::read_u16(data, 0x206).ok()?;
+    let offset = pe::read_u16(data, 0x20e).ok()? as usize;
+    if protocol < 0x200 || offset == 0 {
+        return None;
+    }
+
+    data.get(offset + 0x200..).and_then(first_word)
+}
+
+/// The text up to the first whitespace or NUL byte, if it is not empty.
+fn first_word(data: &[u8]) -> Option<String> {
+    let end = data
+        .iter()
+        .position(|&b| b == 0 || b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+
+    let word = std::str::from_utf8(&data[..end.min(256)]).ok()?;
+    if word.is_empty() {
+        None
+    } else {
+        Some(word.to_owned())
+    }
+}
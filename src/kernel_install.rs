@@ -0,0 +1,197 @@
+//! Reading the configuration of kernel-install, so that entries and their images are placed where
+//! its plugins, and the initrd generators which they run, expect to find them.
+
+use crate::filesystem::Filesystem;
+use crate::os_release::parse_assignments;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The locations of `install.conf` in order of precedence, relative to the root of the system.
+const PATHS: &[&str] = &["etc/kernel/install.conf", "usr/lib/kernel/install.conf"];
+
+/// How kernel-install lays out the files of the kernels that it installs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Layout {
+    /// Type #1 entries, with the kernel and initrd in `<token>/<version>/` of the boot root.
+    Bls,
+    /// Type #2 entries, with a unified kernel image in `EFI/Linux/<token>-<version>.efi`.
+    Uki,
+    /// Some other layout, which the plugins of kernel-install decide, such as for GRUB.
+    Other,
+}
+
+impl Layout {
+    /// Parse the value of `layout=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "bls" => Some(Layout::Bls),
+            "uki" => Some(Layout::Uki),
+            "other" => Some(Layout::Other),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Layout::Bls => "bls",
+            Layout::Uki => "uki",
+            Layout::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The settings of kernel-install's `install.conf`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct InstallConf {
+    /// The layout, which is detected from the boot root when it is not set.
+    pub layout: Option<Layout>,
+    /// The partition which kernels are installed to, which is found by kernel-install when it
+    /// is not set.
+    pub boot_root: Option<PathBuf>,
+    /// The machine id which entries are named and tagged with, instead of `/etc/machine-id`.
+    pub machine_id: Option<String>,
+    /// The program which generates initrds, such as `dracut` or `mkinitcpio`.
+    pub initrd_generator: Option<String>,
+    /// The program which generates unified kernel images, such as `ukify` or `dracut`.
+    pub uki_generator: Option<String>,
+}
+
+impl InstallConf {
+    /// Read the configuration of the running system.
+    pub fn load() -> io::Result<Self> {
+        Self::load_from_root("/")
+    }
+
+    /// Read the configuration of the system installed at `root`, from `etc/kernel/install.conf`
+    /// or `usr/lib/kernel/install.conf`, which is empty if neither exists.
+    pub fn load_from_root<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref();
+
+        for path in PATHS {
+            match fs::read_to_string(root.join(path)) {
+                Ok(contents) => return Ok(Self::parse(&contents)),
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => (),
+                Err(why) => return Err(why),
+            }
+        }
+
+        Ok(Self::default())
+    }
+
+    /// Parse the contents of `install.conf`, ignoring unknown settings and invalid layouts, as
+    /// kernel-install does.
+    pub fn parse(contents: &str) -> Self {
+        let fields = parse_assignments(contents);
+        let get = |key: &str| fields.get(key).filter(|value| !value.is_empty());
+
+        InstallConf {
+            layout: get("layout").and_then(|layout| Layout::parse(layout)),
+            boot_root: get("BOOT_ROOT").map(PathBuf::from),
+            machine_id: get("MACHINE_ID").cloned(),
+            initrd_generator: get("initrd_generator").cloned(),
+            uki_generator: get("uki_generator").cloned(),
+        }
+    }
+
+    /// The layout which kernel-install uses for a boot root, as it detects it when none is set.
+    ///
+    /// The layout is `bls` if `loader/entries.srel` declares `type1` entries, or if there is a
+    /// directory named after the entry token, and `other` otherwise.
+    pub fn effective_layout(
+        &self,
+        fs: &dyn Filesystem,
+        boot_root: &Path,
+        entry_token: &str,
+    ) -> Layout {
+        if let Some(layout) = self.layout {
+            return layout;
+        }
+
+        if let Ok(srel) = fs.read(&boot_root.join("loader/entries.srel")) {
+            return if String::from_utf8_lossy(&srel).trim() == "type1" {
+                Layout::Bls
+            } else {
+                Layout::Other
+            };
+        }
+
+        let token_dir = fs.metadata(&boot_root.join(entry_token));
+        if token_dir.is_ok_and(|metadata| metadata.is_dir()) {
+            Layout::Bls
+        } else {
+            Layout::Other
+        }
+    }
+}
+
+/// Where kernel-install places the files of each kernel version.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelInstallPaths {
+    pub layout: Layout,
+    pub boot_root: PathBuf,
+    pub entry_token: String,
+}
+
+impl KernelInstallPaths {
+    /// The id of the entry of a kernel, which is `<token>-<version>`.
+    pub fn entry_id(&self, version: &str) -> String {
+        [&self.entry_token, "-", version].concat()
+    }
+
+    /// The entry file of a kernel, in the `bls` layout.
+    pub fn entry(&self, version: &str) -> Option<PathBuf> {
+        self.bls(|| {
+            let file_name = [&self.entry_id(version), ".conf"].concat();
+            self.boot_root.join("loader/entries").join(file_name)
+        })
+    }
+
+    /// The directory of the kernel and initrd of a kernel, in the `bls` layout.
+    pub fn kernel_dir(&self, version: &str) -> Option<PathBuf> {
+        self.bls(|| self.boot_root.join(&self.entry_token).join(version))
+    }
+
+    /// The kernel image, in the `bls` layout.
+    pub fn linux(&self, version: &str) -> Option<PathBuf> {
+        self.kernel_dir(version).map(|dir| dir.join("linux"))
+    }
+
+    /// The initrd which the initrd generator writes, in the `bls` layout.
+    pub fn initrd(&self, version: &str) -> Option<PathBuf> {
+        self.kernel_dir(version).map(|dir| dir.join("initrd"))
+    }
+
+    /// The unified kernel image of a kernel, in the `uki` layout.
+    pub fn uki(&self, version: &str) -> Option<PathBuf> {
+        if self.layout == Layout::Uki {
+            let file_name = [&self.entry_id(version), ".efi"].concat();
+            Some(self.boot_root.join("EFI/Linux").join(file_name))
+        } else {
+            None
+        }
+    }
+
+    /// The path of a file on the boot root as an entry refers to it, such as
+    /// `/<token>/<version>/linux`.
+    pub fn entry_path(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.boot_root).ok()?;
+        Some(["/", relative.to_str()?].concat())
+    }
+
+    fn bls<F: FnOnce() -> PathBuf>(&self, path: F) -> Option<PathBuf> {
+        if self.layout == Layout::Bls {
+            Some(path())
+        } else {
+            None
+        }
+    }
+}
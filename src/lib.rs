@@ -3,65 +3,624 @@
 #[macro_use]
 extern crate thiserror;
 
+#[macro_use]
+mod macros;
+
+pub mod arch;
+pub mod audit;
+#[cfg(feature = "authenticode")]
+pub mod authenticode;
+pub mod backend;
+pub mod batch;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cmdline;
+pub mod efivars;
 pub mod entry;
+pub mod esp_path;
+pub mod events;
+pub mod fallback;
+pub mod fat;
+pub mod filesystem;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod format;
+pub mod gc;
+pub mod grubenv;
+pub mod hooks;
+pub mod host;
+pub mod id;
+pub mod installation;
+pub mod kernel;
+pub mod kernel_install;
+pub mod lint;
 pub mod loader;
+pub mod luks;
+pub mod machine_id;
+pub mod manifest;
+pub mod merge;
+pub mod mirror;
+pub mod mount;
+pub mod naming;
+pub mod os_release;
+pub mod ownership;
+pub mod parse;
+pub mod pe;
+pub mod progress;
+pub mod query;
+pub mod reboot;
+pub mod retention;
+pub mod rootfs;
+pub mod sbat;
+pub mod secure_boot;
+pub mod shared;
+pub mod slots;
+pub mod status;
+pub mod stub;
+pub mod style;
+pub mod uki;
+pub mod version;
 
+use self::arch::EfiArch;
+use self::audit::AuditLog;
+use self::batch::BatchWriter;
+use self::efivars::{EfiVarError, EfiVars};
 use self::entry::*;
+use self::esp_path::EspPath;
+use self::events::{ChangeEvent, ChangeListener, ChangeSet};
+use self::fallback::{FallbackLoader, FallbackState};
+use self::filesystem::{Filesystem, RealFilesystem, RetryPolicy, RetryingFilesystem};
+use self::format::{render_entry_conf, render_loader_conf};
+use self::gc::{GcOptions, GcReport, OrphanedFile};
+use self::grubenv::GrubEnv;
+use self::hooks::WriteHook;
+use self::host::Host;
+use self::id::EntryId;
+use self::installation::Installation;
+use self::kernel_install::KernelInstallPaths;
+use self::lint::Lint;
 use self::loader::*;
+use self::manifest::{Manifest, ManifestDiff};
+use self::merge::{MergeConflict, MergeOptions, MergeReport};
+use self::mirror::SyncReport;
+use self::mount::TempMount;
+use self::naming::NamingScheme;
+use self::ownership::Ownership;
+use self::parse::{ParseMode, ParseWarning};
+use self::pe::{PeError, PeImage};
+use self::progress::{Operation, Progress, ProgressListener};
+use self::query::EntryQuery;
+use self::retention::{BootHistory, RetentionPlan, RetentionPolicy, RetentionRule};
+use self::secure_boot::{KeyError, KeyKind, KeySet};
+use self::status::{InstalledLoader, Status};
+use self::stub::{Addon, Credential, StubScope, UkiExtras};
+use self::style::WriteStyle;
+use self::uki::UkiError;
 
 use once_cell::sync::OnceCell;
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::{self, BufWriter};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("error reading loader enrties directory")]
-    EntriesDir(#[source] io::Error),
+    #[error("error creating loader directory {:?}", path)]
+    CreateDir { path: PathBuf, source: io::Error },
+    #[error("error accessing EFI variables")]
+    EfiVar(#[source] EfiVarError),
+    #[error("error reading loader entries directory {:?}", path)]
+    EntriesDir { path: PathBuf, source: io::Error },
     #[error("error parsing entry at {:?}", path)]
     Entry { path: PathBuf, source: EntryError },
-    #[error("error writing entry file")]
-    EntryWrite(#[source] io::Error),
-    #[error("error reading entry in loader entries directory")]
-    FileEntry(#[source] io::Error),
+    #[error("an entry with the id {:?} already exists", _0)]
+    EntryExists(String),
+    #[error(
+        "the id {:?} collides with the entry {:?}, as the ESP ignores the case of file names",
+        id,
+        existing
+    )]
+    EntryExistsIgnoringCase { id: String, existing: String },
+    #[error("error removing entry file {:?}", path)]
+    EntryRemove { path: PathBuf, source: io::Error },
+    #[error("entry at {:?} is a symlink", _0)]
+    EntrySymlink(PathBuf),
+    #[error("error renaming entry file {:?} to {:?}", path, to)]
+    EntryRename {
+        path: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+    #[error("error writing entry file {:?}", path)]
+    EntryWrite { path: PathBuf, source: io::Error },
+    #[error("no EFI system partition was found")]
+    EspNotFound,
+    #[error("error writing fallback boot loader {:?}", path)]
+    FallbackWrite { path: PathBuf, source: io::Error },
+    #[error("error reading {:?} in loader entries directory", path)]
+    FileEntry { path: PathBuf, source: io::Error },
+    #[error("error reading boot image {:?}", path)]
+    ImageRead { path: PathBuf, source: io::Error },
+    #[error(
+        "{} bytes are needed on {:?}, including the reserve, but only {} are available",
+        needed,
+        path,
+        available
+    )]
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+        /// Orphaned boot images on the ESP which could be removed to make space.
+        prunable: Vec<OrphanedFile>,
+    },
+    #[error("{:?} is not an addon, as it is not a PE image", path)]
+    InvalidAddon { path: PathBuf, source: PeError },
+    #[error("{:?} is not a valid addon name", _0)]
+    InvalidAddonName(String),
+    #[error("{:?} is not a valid credential name", _0)]
+    InvalidCredentialName(String),
+    #[error("{:?} is not a unified kernel image", path)]
+    InvalidUki { path: PathBuf, source: UkiError },
+    #[error("{:?} is not a valid unified kernel image name", _0)]
+    InvalidUkiName(String),
+    #[error("error reading {:?} to find orphaned boot images", path)]
+    OrphanRead { path: PathBuf, source: io::Error },
+    #[error("error removing orphaned boot image {:?}", path)]
+    OrphanRemove { path: PathBuf, source: io::Error },
+    #[error("invalid secure boot keys at {:?}", path)]
+    Keys { path: PathBuf, source: KeyError },
+    #[error("error reading secure boot keys directory {:?}", path)]
+    KeysDir { path: PathBuf, source: io::Error },
+    #[error("error writing secure boot keys to {:?}", path)]
+    KeysWrite { path: PathBuf, source: io::Error },
     #[error("error parsing loader conf at {:?}", path)]
     Loader { path: PathBuf, source: LoaderError },
-    #[error("error writing loader file")]
-    LoaderWrite(#[source] io::Error),
+    #[error("error writing loader file {:?}", path)]
+    LoaderWrite { path: PathBuf, source: io::Error },
+    #[error("error reading {:?} for the manifest", path)]
+    ManifestRead { path: PathBuf, source: io::Error },
+    #[error("error mounting {:?}", path)]
+    Mount { path: PathBuf, source: io::Error },
     #[error("entry not found in data structure")]
     NotFound,
+    #[error("the EFI system partition is not mounted at {:?}", _0)]
+    NotMounted(PathBuf),
+    #[error("entry {} belongs to another installation", _0)]
+    NotOwned(String),
+    #[error("error copying boot image to {:?}", path)]
+    PayloadCopy { path: PathBuf, source: io::Error },
+    #[error("permission denied, as root privileges are required")]
+    PermissionDenied(#[source] Box<Error>),
+    #[error("entry {} is protected: {}", id, reason)]
+    Protected { id: String, reason: String },
+    #[error("error writing random seed to {:?}", path)]
+    RandomSeedWrite { path: PathBuf, source: io::Error },
+    #[error("the EFI system partition is mounted read-only")]
+    ReadOnly(#[source] Box<Error>),
+    #[error("error remounting {:?}", path)]
+    Remount { path: PathBuf, source: io::Error },
+    #[error("error reading systemd-stub directory {:?}", path)]
+    StubDir { path: PathBuf, source: io::Error },
+    #[error("error removing {:?}, which systemd-stub loads", path)]
+    StubRemove { path: PathBuf, source: io::Error },
+    #[error("error writing {:?}, which systemd-stub loads", path)]
+    StubWrite { path: PathBuf, source: io::Error },
+    #[error("error copying {:?} to the mirrored ESP", path)]
+    SyncCopy { path: PathBuf, source: io::Error },
+    #[error("error removing {:?} from the mirrored ESP", path)]
+    SyncRemove { path: PathBuf, source: io::Error },
+    #[error("error removing unified kernel image {:?}", path)]
+    UkiRemove { path: PathBuf, source: io::Error },
+    #[error("error writing unified kernel image {:?}", path)]
+    UkiWrite { path: PathBuf, source: io::Error },
+    #[error(
+        "refusing to look for orphaned boot images, as the entries at {:?} were not loaded",
+        _0
+    )]
+    UnloadedEntries(Vec<PathBuf>),
+    #[error("error unmounting {:?}", path)]
+    Unmount { path: PathBuf, source: io::Error },
+    #[error("{:?} is not an EFI image for {}", path, expected)]
+    WrongArchitecture { path: PathBuf, expected: EfiArch },
+}
+
+impl Error {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::EfiVar(source) => source.kind(),
+            Error::Entry { source, .. } => source.kind(),
+            Error::InvalidUki { source, .. } => source.kind(),
+            Error::Keys { source, .. } => source.kind(),
+            Error::Loader { source, .. } => source.kind(),
+            Error::CreateDir { source, .. }
+            | Error::EntriesDir { source, .. }
+            | Error::EntryRemove { source, .. }
+            | Error::EntryRename { source, .. }
+            | Error::EntryWrite { source, .. }
+            | Error::FileEntry { source, .. }
+            | Error::ImageRead { source, .. }
+            | Error::KeysDir { source, .. }
+            | Error::KeysWrite { source, .. }
+            | Error::LoaderWrite { source, .. }
+            | Error::FallbackWrite { source, .. }
+            | Error::ManifestRead { source, .. }
+            | Error::Mount { source, .. }
+            | Error::OrphanRead { source, .. }
+            | Error::OrphanRemove { source, .. }
+            | Error::PayloadCopy { source, .. }
+            | Error::RandomSeedWrite { source, .. }
+            | Error::Remount { source, .. }
+            | Error::StubDir { source, .. }
+            | Error::StubRemove { source, .. }
+            | Error::StubWrite { source, .. }
+            | Error::SyncCopy { source, .. }
+            | Error::SyncRemove { source, .. }
+            | Error::UkiRemove { source, .. }
+            | Error::UkiWrite { source, .. }
+            | Error::Unmount { source, .. } => ErrorKind::from_io(source),
+            Error::EntryExists(_) | Error::EntryExistsIgnoringCase { .. } => {
+                ErrorKind::AlreadyExists
+            }
+            Error::EntrySymlink(_)
+            | Error::InvalidAddon { .. }
+            | Error::InvalidAddonName(_)
+            | Error::InvalidCredentialName(_)
+            | Error::InvalidUkiName(_)
+            | Error::UnloadedEntries(_)
+            | Error::WrongArchitecture { .. } => ErrorKind::InvalidData,
+            Error::InsufficientSpace { .. } => ErrorKind::InsufficientSpace,
+            Error::NotFound => ErrorKind::EntryNotFound,
+            Error::EspNotFound | Error::NotMounted(_) => ErrorKind::NotMounted,
+            Error::NotOwned(_) => ErrorKind::NotOwned,
+            Error::Protected { .. } => ErrorKind::Protected,
+            Error::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            Error::ReadOnly(_) => ErrorKind::ReadOnly,
+        }
+    }
+
+    /// The file or directory that the failed operation was performed on, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::CreateDir { path, .. }
+            | Error::EntriesDir { path, .. }
+            | Error::Entry { path, .. }
+            | Error::EntryRemove { path, .. }
+            | Error::EntryRename { path, .. }
+            | Error::EntryWrite { path, .. }
+            | Error::FallbackWrite { path, .. }
+            | Error::FileEntry { path, .. }
+            | Error::ImageRead { path, .. }
+            | Error::InsufficientSpace { path, .. }
+            | Error::InvalidAddon { path, .. }
+            | Error::InvalidUki { path, .. }
+            | Error::WrongArchitecture { path, .. }
+            | Error::OrphanRead { path, .. }
+            | Error::OrphanRemove { path, .. }
+            | Error::Keys { path, .. }
+            | Error::KeysDir { path, .. }
+            | Error::KeysWrite { path, .. }
+            | Error::Loader { path, .. }
+            | Error::LoaderWrite { path, .. }
+            | Error::ManifestRead { path, .. }
+            | Error::Mount { path, .. }
+            | Error::PayloadCopy { path, .. }
+            | Error::RandomSeedWrite { path, .. }
+            | Error::Remount { path, .. }
+            | Error::StubDir { path, .. }
+            | Error::StubRemove { path, .. }
+            | Error::StubWrite { path, .. }
+            | Error::SyncCopy { path, .. }
+            | Error::SyncRemove { path, .. }
+            | Error::UkiRemove { path, .. }
+            | Error::UkiWrite { path, .. }
+            | Error::Unmount { path, .. } => Some(path),
+            Error::EntrySymlink(path) | Error::NotMounted(path) => Some(path),
+            Error::PermissionDenied(why) | Error::ReadOnly(why) => why.path(),
+            Error::EfiVar(_)
+            | Error::EntryExists(_)
+            | Error::EntryExistsIgnoringCase { .. }
+            | Error::EspNotFound
+            | Error::InvalidAddonName(_)
+            | Error::InvalidCredentialName(_)
+            | Error::InvalidUkiName(_)
+            | Error::NotFound
+            | Error::NotOwned(_)
+            | Error::Protected { .. }
+            | Error::UnloadedEntries(_) => None,
+        }
+    }
+
+    /// A suggestion for the user on how to resolve this error, if there is one.
+    pub fn hint(&self) -> Option<&'static str> {
+        self.kind().hint()
+    }
+
+    /// Whether the operation failed with an IO error which may not recur if it is attempted
+    /// again, such as an `EIO` from marginal media. Other errors are persistent.
+    pub fn is_transient(&self) -> bool {
+        let mut source = std::error::Error::source(self);
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<io::Error>() {
+                return filesystem::is_transient(error);
+            }
+
+            source = error.source();
+        }
+
+        false
+    }
+
+    /// Reports IO failures caused by missing privileges or a read-only filesystem with their
+    /// own variants, which keep the original error as their source.
+    pub(crate) fn classify(self) -> Self {
+        match self {
+            Error::NotMounted(_) | Error::PermissionDenied(_) | Error::ReadOnly(_) => self,
+            _ => match self.kind() {
+                ErrorKind::PermissionDenied => Error::PermissionDenied(Box::new(self)),
+                ErrorKind::ReadOnly => Error::ReadOnly(Box::new(self)),
+                _ => self,
+            },
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// A stable classification of errors, which frontends may map to remediation steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A file or directory is missing, which may mean that the ESP is not mounted.
+    NotFound,
+    /// Access was denied, which usually means that root privileges are required.
+    PermissionDenied,
+    /// The filesystem is mounted read-only.
+    ReadOnly,
+    /// Any other IO error.
+    Io,
+    /// The filesystem would have less free space than its configured reserve.
+    InsufficientSpace,
+    /// A configuration file or value is malformed, or would not be bootable.
+    InvalidData,
+    /// An entry with the same id already exists.
+    AlreadyExists,
+    /// The requested entry does not exist.
+    EntryNotFound,
+    /// The EFI system partition is not mounted.
+    NotMounted,
+    /// The entry belongs to another OS installation, which is protected by the ownership guard.
+    NotOwned,
+    /// The entry is protected from removal and from having its options edited.
+    Protected,
+    /// The operation requires a cargo feature which is disabled, or is not supported here.
+    Unsupported,
+}
+
+impl ErrorKind {
+    /// A stable, machine-readable code for this kind of error.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "not-found",
+            ErrorKind::PermissionDenied => "permission-denied",
+            ErrorKind::ReadOnly => "read-only",
+            ErrorKind::Io => "io",
+            ErrorKind::InsufficientSpace => "insufficient-space",
+            ErrorKind::InvalidData => "invalid-data",
+            ErrorKind::AlreadyExists => "already-exists",
+            ErrorKind::EntryNotFound => "entry-not-found",
+            ErrorKind::NotMounted => "not-mounted",
+            ErrorKind::NotOwned => "not-owned",
+            ErrorKind::Protected => "protected",
+            ErrorKind::Unsupported => "unsupported",
+        }
+    }
+
+    /// A suggestion for the user on how to resolve this kind of error, if there is one.
+    pub fn hint(self) -> Option<&'static str> {
+        match self {
+            ErrorKind::InsufficientSpace => Some(
+                "remove orphaned boot images with gc, or entries which are no longer needed, and \
+                 try again",
+            ),
+            ErrorKind::NotMounted => Some("mount the EFI system partition, and try again"),
+            ErrorKind::NotOwned => Some(
+                "leave the entry to the installation that it belongs to, or override the \
+                 ownership guard to modify it anyway",
+            ),
+            ErrorKind::PermissionDenied => Some("run the command again as root"),
+            ErrorKind::Protected => {
+                Some("remove the protection from the entry first, if it should really be changed")
+            }
+            ErrorKind::ReadOnly => Some(
+                "remount the EFI system partition read-write; if it was remounted read-only \
+                 after an error, check it with fsck.vfat first",
+            ),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::ReadOnlyFilesystem => ErrorKind::ReadOnly,
+            io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Io,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SystemdBootConf {
-    pub efi_mount: Box<Path>,
-    pub entries_path: Box<Path>,
-    pub loader_path: Box<Path>,
+    pub efi_mount: PathBuf,
+    pub entries_path: PathBuf,
+    pub loader_path: PathBuf,
     pub entries: Vec<Entry>,
     pub loader_conf: LoaderConf,
+    /// The format of the entries, as declared by `loader/entries.srel`, which is `type1` for
+    /// entries in the format of the Boot Loader Specification.
+    pub entries_srel: Option<String>,
+    /// Entry files which were not loaded because their file names are not UTF-8.
+    pub non_utf8_entries: Vec<PathBuf>,
+    /// Symlinked entry files which were not loaded, under `SymlinkPolicy::Skip`.
+    pub skipped_entries: Vec<PathBuf>,
+    /// How symlinks in the entries directory are handled when loading entries.
+    pub symlink_policy: SymlinkPolicy,
+    /// How strictly the loader conf and entries are parsed.
+    pub parse_mode: ParseMode,
+    /// Problems which were tolerated while loading in lenient mode.
+    pub warnings: Vec<ParseWarning>,
+    /// Whether `add_entry` adds the microcode images found on the ESP to new Linux entries.
+    pub attach_microcode: bool,
+    /// Where the XBOOTLDR partition is mounted, such as `/boot`, if entries are read from it.
+    pub xbootldr_mount: Option<PathBuf>,
+    /// GRUB variables which are expanded in entry options, for entries generated for GRUB.
+    pub grubenv: Option<GrubEnv>,
+    /// How the loader conf and entries are formatted when they are written.
+    pub write_style: WriteStyle,
+    /// How the entries given to `add_entry` are named.
+    pub naming_scheme: NamingScheme,
+    /// The system that entries are managed for, which may be installed at an alternate root.
+    pub host: Host,
+    /// If set, entries of other installations on a shared ESP are refused by the methods which
+    /// modify or delete entries on disk, and skipped by `normalize`.
+    pub ownership: Option<Ownership>,
+    /// The free space, in bytes, which operations that copy images must leave on the
+    /// filesystem. They fail before copying anything if they would leave less.
+    pub min_free_space: Option<u64>,
+    /// Whether every file is synced and read back after it is written, to catch media which
+    /// corrupts writes silently.
+    pub verify_writes: bool,
+    fs: Arc<dyn Filesystem>,
+    hooks: Vec<Arc<dyn WriteHook>>,
+    listeners: Vec<Arc<dyn ChangeListener>>,
+    progress: Vec<Arc<dyn ProgressListener>>,
+    index: HashMap<String, usize>,
 }
 
 impl SystemdBootConf {
     pub fn new<P: Into<PathBuf>>(efi_mount: P) -> Result<Self, Error> {
-        let efi_mount = efi_mount.into();
-        let entries_path = efi_mount.join("loader/entries").into();
-        let loader_path = efi_mount.join("loader/loader.conf").into();
+        Self::builder(efi_mount).build()
+    }
 
-        let mut manager = Self {
-            efi_mount: efi_mount.into(),
-            entries_path,
-            loader_path,
-            entries: Vec::default(),
-            loader_conf: LoaderConf::default(),
+    /// Run `f` with the configuration of the ESP that `mount::find_esp_partition` finds, such as
+    /// in rescue environments, mounting it to a temporary directory if it is not mounted and
+    /// unmounting it afterwards, even if `f` fails.
+    ///
+    /// This requires root privileges if the ESP is mounted.
+    pub fn with_mounted_esp<T, F>(f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut SystemdBootConf) -> Result<T, Error>,
+    {
+        let device = match mount::find_esp_partition() {
+            Ok(Some(device)) => device,
+            Ok(None) => return Err(Error::EspNotFound),
+            Err(why) => {
+                warn!("unable to search for the EFI system partition: {}", why);
+                return Err(Error::EspNotFound);
+            }
         };
 
-        manager.load_conf()?;
-        manager.load_entries()?;
+        if let Ok(Some(path)) = mount::mount_point(&device) {
+            return f(&mut SystemdBootConf::new(path)?);
+        }
 
-        Ok(manager)
+        let esp = TempMount::esp(&device).map_err(|source| {
+            Error::Mount {
+                path: device.clone(),
+                source,
+            }
+            .classify()
+        })?;
+
+        let path = esp.path().to_owned();
+        let result = SystemdBootConf::new(&path).and_then(|mut conf| f(&mut conf));
+        let unmounted = esp
+            .unmount()
+            .map_err(|source| Error::Unmount { path, source }.classify());
+
+        // A failure of `f` is reported over a failure to unmount the partition.
+        let value = result?;
+        unmounted.map(|()| value)
+    }
+
+    /// Initialize a fresh loader layout in the EFI mount, and load it.
+    ///
+    /// The `loader/entries` directory and a minimal `loader/loader.conf` are created if they do
+    /// not already exist, so that installers may start from a pristine ESP.
+    pub fn create<P: Into<PathBuf>>(efi_mount: P) -> Result<Self, Error> {
+        Self::builder(efi_mount).create()
+    }
+
+    /// An empty configuration which is not read from any ESP, to be populated and then written
+    /// with `persist_to`.
+    pub fn empty() -> Self {
+        Self::builder(PathBuf::new()).empty()
+    }
+
+    /// Configure the paths of the loader configuration before loading it.
+    ///
+    /// By default, entries are read from `loader/entries` and the loader configuration from
+    /// `loader/loader.conf`, relative to the EFI mount.
+    pub fn builder<P: Into<PathBuf>>(efi_mount: P) -> SystemdBootConfBuilder {
+        SystemdBootConfBuilder {
+            efi_mount: efi_mount.into(),
+            entries_path: None,
+            loader_path: None,
+            symlink_policy: SymlinkPolicy::default(),
+            parse_mode: ParseMode::default(),
+            attach_microcode: false,
+            grubenv: None,
+            xbootldr_mount: None,
+            write_style: WriteStyle::default(),
+            naming_scheme: NamingScheme::default(),
+            host: Host::default(),
+            ownership: None,
+            min_free_space: None,
+            verify_writes: false,
+            retry: None,
+            fs: Arc::new(RealFilesystem),
+            hooks: Vec::new(),
+            listeners: Vec::new(),
+            progress: Vec::new(),
+        }
+    }
+
+    /// The root of the partition that entries are read from, which the paths in entries are
+    /// relative to.
+    ///
+    /// This is the XBOOTLDR partition if the entries path is within it, or otherwise the ESP.
+    pub fn entry_root(&self) -> &Path {
+        match self.xbootldr_mount {
+            Some(ref xbootldr) if self.entries_path.starts_with(xbootldr) => xbootldr,
+            _ => &self.efi_mount,
+        }
+    }
+
+    /// The filesystem through which all file operations are performed.
+    pub fn filesystem(&self) -> &dyn Filesystem {
+        &*self.fs
+    }
+
+    /// Run this hook around every file that is written, after the hooks already added.
+    pub fn add_write_hook<H: WriteHook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    /// Notify this listener of the changes made to the configuration on disk: entry files which
+    /// are written, removed, or renamed, and changes to the default entry and timeout.
+    pub fn add_listener<L: ChangeListener + 'static>(&mut self, listener: L) {
+        self.listeners.push(Arc::new(listener));
     }
 
     /// Find the boot entry which matches the current boot
@@ -71,14 +630,167 @@ impl SystemdBootConf {
     /// The current boot option is determined by a matching the entry's initd and options
     /// to `/proc/cmdline`.
     pub fn current_entry(&self) -> Option<&Entry> {
-        self.entries.iter().find(|e| e.is_current())
+        match self.grubenv {
+            Some(ref env) => self.entries.iter().find(|e| e.is_current_with(env)),
+            None => self.entries.iter().find(|e| e.is_current()),
+        }
+    }
+
+    /// The options that an entry passes to the kernel, with GRUB variables expanded if a
+    /// GRUB environment was given.
+    pub fn effective_options(&self, entry: &Entry) -> Vec<String> {
+        match self.grubenv {
+            Some(ref env) => entry.expanded_options(env),
+            None => entry.options.clone(),
+        }
+    }
+
+    /// The entries which systemd-boot would hide on this machine, as they are for another
+    /// architecture.
+    pub fn foreign_entries(&self) -> Vec<&Entry> {
+        self.entries.iter().filter(|e| e.is_foreign()).collect()
+    }
+
+    /// The entries in the order that systemd-boot displays them in its menu.
+    ///
+    /// Entries for other architectures are excluded, as systemd-boot does not display them.
+    pub fn entries_in_boot_order(&self) -> Vec<&Entry> {
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|e| !e.is_foreign())
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.boot_order(b));
+        entries
+    }
+
+    /// Where the image that the firmware executed is found in the EFI mount, which is the boot
+    /// loader, or a UKI that was booted directly.
+    pub fn loader_image_path(&self, efivars: &EfiVars) -> Result<Option<PathBuf>, EfiVarError> {
+        Ok(efivars
+            .loader_image()?
+            .map(|image| image.to_path(&self.efi_mount)))
+    }
+
+    /// A report of the boot loader's state, combining the configuration, the boot loaders
+    /// installed on the ESP, and the EFI variables of the current boot.
+    ///
+    /// This does not fail; EFI variables which could not be read are recorded in the report.
+    pub fn status(&self, efivars: &EfiVars) -> Status {
+        let mut status = Status {
+            efi_mount: self.efi_mount.clone(),
+            efivars_available: efivars.is_available(),
+            default_entry: self.loader_conf.default.clone(),
+            default_state: self.default_entry_exists(),
+            entries: self.entries.len(),
+            non_utf8_entries: self.non_utf8_entries.clone(),
+            warnings: self.warnings.clone(),
+            ..Status::default()
+        };
+
+        status.installed_loaders = self
+            .loader_images()
+            .into_iter()
+            .map(|image| {
+                let version = self
+                    .fs
+                    .read(&image.to_path(&self.efi_mount))
+                    .ok()
+                    .and_then(|data| status::loader_version_from_image(&data));
+
+                InstalledLoader { image, version }
+            })
+            .collect();
+
+        for entry in &self.entries {
+            if entry.is_foreign() {
+                status.foreign_entries.push(entry.id.clone());
+            }
+
+            if entry.microcode_misordered() {
+                status.misordered_microcode.push(entry.id.clone());
+            }
+        }
+
+        if status.efivars_available {
+            let mut errors = Vec::new();
+            let mut read = |result: Result<Option<String>, EfiVarError>| {
+                result.unwrap_or_else(|why| {
+                    errors.push(why);
+                    None
+                })
+            };
+
+            status.loader_info = read(efivars.loader_info());
+            status.firmware_info = read(efivars.firmware_info());
+            status.loader_partition_uuid = read(efivars.loader_partition_uuid());
+            status.efivar_default_entry = read(efivars.default_entry());
+            status.oneshot_entry = read(efivars.oneshot_entry());
+            status.selected_entry = read(efivars.selected_entry());
+            status.loader_image = efivars.loader_image().unwrap_or_else(|why| {
+                errors.push(why);
+                None
+            });
+
+            status.efivar_errors = errors;
+        }
+
+        status
+    }
+
+    /// Write a stream of entries to disk without loading them, such as the thousands of entries
+    /// which are generated for filesystem snapshots.
+    pub fn batch_writer(&self) -> BatchWriter<'_> {
+        BatchWriter::new(self)
+    }
+
+    /// The entries of each machine id, with the entries which have no machine id under `None`.
+    pub fn entries_by_machine_id(&self) -> BTreeMap<Option<&str>, Vec<&Entry>> {
+        let mut groups: BTreeMap<Option<&str>, Vec<&Entry>> = BTreeMap::new();
+        for entry in &self.entries {
+            groups
+                .entry(entry.machine_id.as_deref())
+                .or_default()
+                .push(entry);
+        }
+
+        groups
+    }
+
+    /// The OS installations which share the ESP, told apart by the machine ids of their entries.
+    ///
+    /// The local installation, whose machine id is that of the host, is first, if it has
+    /// entries. The entries which have no machine id are grouped last, as they may belong to any
+    /// installation.
+    pub fn installations(&self) -> Vec<Installation<'_>> {
+        let local = self.host.machine_id().ok();
+
+        let mut installations: Vec<Installation<'_>> = self
+            .entries_by_machine_id()
+            .into_iter()
+            .map(|(machine_id, entries)| Installation {
+                machine_id,
+                local: machine_id.is_some() && machine_id == local.as_deref(),
+                entries,
+            })
+            .collect();
+
+        installations.sort_by_key(|i| (!i.local, i.machine_id.is_none()));
+        installations
+    }
+
+    /// Select entries with combinators, such as by machine id or kind.
+    pub fn query(&self) -> EntryQuery<'_> {
+        EntryQuery::new(&self.entries)
     }
 
     /// Validate that the default entry exists.
+    ///
+    /// The default may name an entry, or the file name of a UKI in `EFI/Linux`.
     pub fn default_entry_exists(&self) -> DefaultState {
         match self.loader_conf.default {
-            Some(ref default) => {
-                if self.entry_exists(default) {
+            Some(_) => {
+                if self.resolve_default().is_some() {
                     DefaultState::Exists
                 } else {
                     DefaultState::DoesNotExist
@@ -88,142 +800,3266 @@ impl SystemdBootConf {
         }
     }
 
-    /// Validates that an entry exists with this name.
-    pub fn entry_exists(&self, entry: &str) -> bool {
-        self.entries.iter().any(|e| e.id.as_ref() == entry)
+    /// The entry or UKI which the `default` of the loader conf refers to.
+    ///
+    /// As in systemd-boot, an entry with a boot counter is also matched by its id without it,
+    /// which does not change as the tries are counted down.
+    pub fn resolve_default(&self) -> Option<DefaultTarget<'_>> {
+        let default = self.loader_conf.default.as_deref()?;
+
+        let entry = self
+            .find(default)
+            .or_else(|| self.entries.iter().find(|entry| entry.base_id() == default));
+
+        if let Some(entry) = entry {
+            return Some(DefaultTarget::Entry(entry));
+        }
+
+        // File names on the ESP are case-insensitive.
+        self.ukis()
+            .into_iter()
+            .find(|uki| {
+                uki.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.eq_ignore_ascii_case(default))
+            })
+            .map(DefaultTarget::Uki)
     }
 
-    /// Get the entry that corresponds to the given name.
-    pub fn get(&self, entry: &str) -> Option<&Entry> {
-        self.entries.iter().find(|e| e.id.as_ref() == entry)
+    /// Whether this entry is the one which the `default` of the loader conf refers to.
+    pub fn is_default(&self, entry: &Entry) -> bool {
+        match self.resolve_default() {
+            Some(DefaultTarget::Entry(default)) => default.id == entry.id,
+            _ => false,
+        }
     }
 
-    /// Get a mutable entry that corresponds to the given name.
-    pub fn get_mut(&mut self, entry: &str) -> Option<&mut Entry> {
-        self.entries.iter_mut().find(|e| e.id.as_ref() == entry)
+    /// The unified kernel images in `EFI/Linux`, which systemd-boot adds to its menu without
+    /// entry files, on the ESP and the XBOOTLDR partition.
+    pub fn ukis(&self) -> Vec<PathBuf> {
+        let mut ukis = Vec::new();
+        for dir in self.uki_dirs() {
+            let mut images = self
+                .fs
+                .read_dir(&dir)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|path| {
+                    path.extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("efi"))
+                })
+                .filter(|path| self.fs.metadata(path).is_ok_and(|m| m.is_file()))
+                .collect::<Vec<_>>();
+
+            images.sort();
+            ukis.extend(images);
+        }
+
+        ukis
     }
 
-    /// Attempt to re-read the loader configuration.
-    pub fn load_conf(&mut self) -> Result<(), Error> {
-        let &mut SystemdBootConf {
-            ref mut loader_conf,
-            ref loader_path,
-            ..
-        } = self;
+    /// The `EFI/Linux` directories of the ESP and the XBOOTLDR partition.
+    fn uki_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.efi_mount.join("EFI/Linux")];
+        if let Some(ref xbootldr) = self.xbootldr_mount {
+            dirs.push(xbootldr.join("EFI/Linux"));
+        }
 
-        *loader_conf = LoaderConf::from_path(loader_path).map_err(move |source| Error::Loader {
-            path: loader_path.to_path_buf(),
-            source,
-        })?;
+        dirs
+    }
 
-        Ok(())
+    /// Validates that an entry exists with this id, which may differ from it by case.
+    pub fn entry_exists(&self, id: &EntryId) -> bool {
+        self.position_of(id).is_some()
     }
 
-    /// Attempt to load all of the available entries in the system.
-    pub fn load_entries(&mut self) -> Result<(), Error> {
-        let &mut SystemdBootConf {
-            ref mut entries,
-            ref entries_path,
-            ..
-        } = self;
-        let dir_entries = fs::read_dir(entries_path).map_err(Error::EntriesDir)?;
+    /// The id of an existing entry whose file name differs from that of `id` only by case,
+    /// which is the same file on the case-insensitive FAT filesystem of the ESP.
+    pub fn case_collision(&self, id: &str) -> Option<&str> {
+        let folded = id.to_lowercase();
+        self.entries
+            .iter()
+            .find(|e| e.id != id && e.id.to_lowercase() == folded)
+            .map(|e| e.id.as_str())
+    }
 
-        entries.clear();
-        for entry in dir_entries {
-            let entry = entry.map_err(Error::FileEntry)?;
-            let path = entry.path();
+    /// Fails if `id` is used by an existing entry, or collides with one by case.
+    fn check_id_unused(&self, id: &str) -> Result<(), Error> {
+        if self.position(id).is_some() {
+            return Err(Error::EntryExists(id.to_owned()));
+        }
 
-            // Only consider conf files in the directory.
-            if !path.is_file() || path.extension().map_or(true, |ext| ext != "conf") {
-                continue;
+        match self.case_collision(id) {
+            Some(existing) => Err(Error::EntryExistsIgnoringCase {
+                id: id.to_owned(),
+                existing: existing.to_owned(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the entry with this id, which may differ from it by case.
+    pub fn get(&self, id: &EntryId) -> Option<&Entry> {
+        self.position_of(id).map(move |pos| &self.entries[pos])
+    }
+
+    /// Get a mutable entry with this id, which may differ from it by case.
+    pub fn get_mut(&mut self, id: &EntryId) -> Option<&mut Entry> {
+        self.position_of(id).map(move |pos| &mut self.entries[pos])
+    }
+
+    /// Get the entry whose id is exactly `id`.
+    fn find(&self, id: &str) -> Option<&Entry> {
+        self.position(id).map(move |pos| &self.entries[pos])
+    }
+
+    /// Likely mistakes in the configuration, which systemd-boot would accept.
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        lint::menu(self, &mut lints);
+        lint::paths(self, &mut lints);
+        lint::options(self, &mut lints);
+        lint::machine_ids(self, &mut lints);
+        lint::versions(self, &mut lints);
+        lint::case_collisions(self, &mut lints);
+        lint::efi_stubs(self, &mut lints);
+        lints
+    }
+
+    /// Linux entries whose options differ from `cmdline`, the command line which kernel-install
+    /// gives to new entries, such as that of `cmdline::load_kernel_cmdline`.
+    ///
+    /// This reveals entries which were edited by hand, or written before the command line changed.
+    pub fn check_cmdline<S: AsRef<str>>(&self, cmdline: &[S]) -> Vec<Lint> {
+        let intended: Vec<&str> = cmdline.iter().map(AsRef::as_ref).collect();
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind() == EntryKind::Linux)
+            .filter_map(|entry| {
+                let options = self.effective_options(entry);
+                let extra: Vec<String> = options
+                    .iter()
+                    .filter(|option| !intended.contains(&option.as_str()))
+                    .cloned()
+                    .collect();
+                let missing: Vec<String> = intended
+                    .iter()
+                    .filter(|option| !options.iter().any(|o| o == *option))
+                    .map(|option| option.to_string())
+                    .collect();
+
+                if extra.is_empty() && missing.is_empty() {
+                    return None;
+                }
+
+                Some(Lint::CmdlineDrift {
+                    entry: entry.id.clone(),
+                    extra,
+                    missing,
+                })
+            })
+            .collect()
+    }
+
+    /// Linux entries whose options do not explicitly unlock the LUKS volumes with these UUIDs,
+    /// such as those of the host's crypttab, which its initrd may then fail to unlock.
+    pub fn check_luks<S: AsRef<str>>(&self, uuids: &[S]) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        for entry in self.entries.iter().filter(|e| e.kind() == EntryKind::Linux) {
+            let unlocked = luks::unlocked_uuids(&self.effective_options(entry));
+            for uuid in uuids {
+                let uuid = uuid.as_ref().to_lowercase();
+                if !unlocked.contains(&uuid) {
+                    lints.push(Lint::LuksNotUnlocked {
+                        entry: entry.id.clone(),
+                        uuid,
+                    });
+                }
             }
+        }
 
-            let entry = Entry::from_path(&path).map_err(move |source| Error::Entry {
-                path: path.to_path_buf(),
-                source,
-            })?;
+        lints
+    }
 
-            entries.push(entry);
+    /// Entries whose machine id differs from `machine_id`, such as that of `machine_id::load`.
+    ///
+    /// kernel-install only manages the entries of its own machine id, so entries which should
+    /// belong to this installation, but carry another id, are left behind on kernel updates.
+    pub fn check_machine_id(&self, machine_id: &str) -> Vec<Lint> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                let found = entry.machine_id.as_deref()?;
+                if found == machine_id {
+                    return None;
+                }
+
+                Some(Lint::MachineIdMismatch {
+                    entry: entry.id.clone(),
+                    machine_id: found.to_owned(),
+                    expected: machine_id.to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    /// Check the entries against the machine id and kernel command line of the host, skipping
+    /// either check if the host does not define it.
+    pub fn check_host(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        if let Ok(machine_id) = self.host.machine_id() {
+            lints.extend(self.check_machine_id(&machine_id));
         }
 
-        Ok(())
+        if let Ok(cmdline) = self.host.kernel_cmdline() {
+            lints.extend(self.check_cmdline(&cmdline));
+        }
+
+        lints
     }
 
-    /// Overwrite the conf file with stored values.
-    pub fn overwrite_loader_conf(&self) -> Result<(), Error> {
-        let result = Self::try_io(&self.loader_path, |file| {
-            if let Some(ref default) = self.loader_conf.default {
-                writeln!(file, "default {}", default)?;
-            }
+    /// Where kernel-install places the entries and images of the host's kernels, according to
+    /// its `install.conf`, so that entries which are generated here match those of its plugins.
+    ///
+    /// The boot root is the `BOOT_ROOT` of `install.conf`, or the partition that entries are read
+    /// from. The entry token is that of `/etc/kernel/entry-token`, or the machine id, or the
+    /// `IMAGE_ID` or `ID` of os-release.
+    pub fn kernel_install_paths(&self) -> io::Result<KernelInstallPaths> {
+        let conf = self.host.install_conf()?;
+
+        let boot_root = conf.boot_root.as_ref().map_or_else(
+            || self.entry_root().to_owned(),
+            |boot_root| self.host.resolve(boot_root),
+        );
+
+        let entry_token = match self.host.entry_token() {
+            Ok(token) if !token.is_empty() => token,
+            _ => match conf
+                .machine_id
+                .clone()
+                .map_or_else(|| self.host.machine_id(), Ok)
+            {
+                Ok(machine_id) => machine_id,
+                Err(why) => self
+                    .host
+                    .os_release()
+                    .ok()
+                    .and_then(|os| os.sort_key().map(str::to_owned))
+                    .ok_or(why)?,
+            },
+        };
+
+        Ok(KernelInstallPaths {
+            layout: conf.effective_layout(&*self.fs, &boot_root, &entry_token),
+            boot_root,
+            entry_token,
+        })
+    }
 
-            if let Some(timeout) = self.loader_conf.timeout {
-                writeln!(file, "timeout {}", timeout)?;
+    /// Add a new entry, which must not share its id with an existing entry.
+    ///
+    /// The entry is renamed according to the naming scheme. Call `overwrite_entry_conf` to write
+    /// the entry to disk.
+    pub fn add_entry(&mut self, mut entry: Entry) -> Result<&mut Entry, Error> {
+        match self.naming_scheme.id_for(&entry) {
+            Ok(id) => entry.id = id,
+            Err(source) => {
+                return Err(Error::Entry {
+                    path: self.entry_path(&entry),
+                    source,
+                })
             }
+        }
 
-            Ok(())
-        });
+        self.insert_entry(entry)
+    }
 
-        result.map_err(Error::LoaderWrite)
+    /// Add a new entry under the id it was given.
+    fn insert_entry(&mut self, mut entry: Entry) -> Result<&mut Entry, Error> {
+        if self.attach_microcode && entry.kind() == EntryKind::Linux {
+            entry.attach_microcode(self.microcode_images(&entry));
+        }
+
+        self.check_id_unused(&entry.id)?;
+
+        if let Err(source) = entry.validate() {
+            return Err(Error::Entry {
+                path: self.entry_path(&entry),
+                source,
+            });
+        }
+
+        let pos = self.entries.len();
+        self.index.insert(entry.id.clone(), pos);
+        self.entries.push(entry);
+
+        Ok(&mut self.entries[pos])
     }
 
-    /// Overwrite the entry conf for the given entry.
-    pub fn overwrite_entry_conf(&self, entry: &str) -> Result<(), Error> {
-        let entry = match self.get(entry) {
-            Some(entry) => entry,
-            None => return Err(Error::NotFound),
-        };
+    /// Combine the entries of another loader configuration with these, such as when migrating
+    /// from an old ESP.
+    ///
+    /// Entries are merged in memory; write them with `overwrite_entry_conf`. Only the images
+    /// which merged entries boot are written immediately, if `copy_payloads` is set.
+    pub fn merge(
+        &mut self,
+        from: &SystemdBootConf,
+        options: &MergeOptions,
+    ) -> Result<MergeReport, Error> {
+        if options.conflict == MergeConflict::Fail {
+            for entry in &from.entries {
+                self.check_id_unused(&entry.id)?;
+            }
+        }
+
+        let mut report = MergeReport::default();
+        let mut bytes = 0;
+        let mut total_bytes = 0;
+        if options.copy_payloads {
+            let mut targets = HashSet::new();
+            total_bytes = from
+                .entries
+                .iter()
+                .flat_map(|entry| self.payload_copies(from, entry))
+                .filter(|(_, target)| {
+                    self.fs.metadata(target).is_err() && targets.insert(target.clone())
+                })
+                .filter_map(|(source, _)| from.fs.metadata(&source).ok())
+                .map(|metadata| metadata.len)
+                .sum();
+
+            self.check_space(self.entry_root(), total_bytes, true)?;
+        }
 
-        let result = Self::try_io(
-            &self.entries_path.join(format!("{}.conf", entry.id)),
-            move |file| {
-                writeln!(file, "title {}", entry.title)?;
-                writeln!(file, "linux {}", entry.linux)?;
+        for entry in &from.entries {
+            let mut entry = entry.clone();
 
-                if let Some(ref initrd) = entry.initrd {
-                    writeln!(file, "initrd {}", initrd)?;
+            if let Some(ref machine_id) = options.machine_id {
+                if let Some(old) = entry.machine_id.replace(machine_id.clone()) {
+                    if old != *machine_id && entry.id.starts_with(&old) {
+                        entry.id = [machine_id, &entry.id[old.len()..]].concat();
+                    }
                 }
+            }
+
+            let existing = match self.case_collision(&entry.id) {
+                _ if self.position(&entry.id).is_some() => Some(entry.id.clone()),
+                collision => collision.map(str::to_owned),
+            };
 
-                if !entry.options.is_empty() {
-                    writeln!(file, "options: {}", entry.options.join(" "))?;
+            if let Some(existing) = existing {
+                match options.conflict {
+                    MergeConflict::Keep | MergeConflict::Fail => {
+                        report.skipped.push(entry.id);
+                        continue;
+                    }
+                    MergeConflict::Replace => {
+                        if let Some(existing) = self.find(&existing) {
+                            self.check_owned(existing)?;
+                        }
+
+                        self.remove_entry(&existing)?;
+                        report.replaced.push(existing);
+                    }
+                    MergeConflict::Rename => {
+                        let id = self.merged_id(&entry.id);
+                        report.renamed.push((entry.id.clone(), id.clone()));
+                        entry.id = id;
+                    }
                 }
+            }
 
-                Ok(())
-            },
-        );
+            if options.copy_payloads {
+                for (source, target) in self.payload_copies(from, &entry) {
+                    if self.fs.metadata(&target).is_ok() {
+                        continue;
+                    }
+
+                    let file_bytes = from.fs.metadata(&source).map_or(0, |metadata| metadata.len);
+                    self.report_progress(&Progress {
+                        operation: Operation::MergePayloads,
+                        path: &target,
+                        file_bytes,
+                        bytes,
+                        total_bytes,
+                    });
 
-        result.map_err(Error::EntryWrite)
+                    bytes += file_bytes;
+
+                    let copy = |path: &Path| {
+                        if let Some(parent) = path.parent() {
+                            self.fs.create_dir_all(parent)?;
+                        }
+
+                        self.write_file(path, &from.fs.read(&source)?)
+                    };
+
+                    if let Err(source) = copy(&target) {
+                        return Err(Error::PayloadCopy {
+                            path: target,
+                            source,
+                        }
+                        .classify());
+                    }
+
+                    info!("copied {:?} to {:?}", source, target);
+                    report.copied.push(target);
+                }
+            }
+
+            let id = entry.id.clone();
+            self.insert_entry(entry)?;
+            report.merged.push(id);
+        }
+
+        Ok(report)
     }
 
-    fn try_io<F: FnMut(&mut BufWriter<File>) -> io::Result<()>>(
-        path: &Path,
-        mut instructions: F,
-    ) -> io::Result<()> {
-        instructions(&mut BufWriter::new(File::create(path)?))
+    /// The images which an entry of `from` boots, paired with where they belong on this ESP,
+    /// if that differs.
+    fn payload_copies(&self, from: &SystemdBootConf, entry: &Entry) -> Vec<(PathBuf, PathBuf)> {
+        let source = entry.resolved_paths(from);
+        let target = entry.resolved_paths(self);
+        let source = source
+            .linux
+            .into_iter()
+            .chain(source.efi)
+            .chain(source.initrd)
+            .chain(source.devicetree);
+        let target = target
+            .linux
+            .into_iter()
+            .chain(target.efi)
+            .chain(target.initrd)
+            .chain(target.devicetree);
+
+        source
+            .zip(target)
+            .filter(|(source, target)| source != target)
+            .collect()
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-pub enum DefaultState {
-    NotDefined,
-    Exists,
-    DoesNotExist,
-}
+    /// An unused id for an entry merged under the id of an existing entry, which keeps the
+    /// boot counter of the id.
+    fn merged_id(&self, id: &str) -> String {
+        let (base, counter) = match BootCounter::parse(id) {
+            Some((base, counter)) => (base, counter.to_string()),
+            None => (id, String::new()),
+        };
 
-/// Fetches the kernel command line, and lazily initialize it if it has not been fetched.
-pub fn kernel_cmdline() -> &'static [&'static str] {
-    static CMDLINE_BUF: OnceCell<Box<str>> = OnceCell::new();
-    static CMDLINE: OnceCell<Box<[&'static str]>> = OnceCell::new();
+        (1..)
+            .map(|n| match n {
+                1 => format!("{}-merged{}", base, counter),
+                n => format!("{}-merged-{}{}", base, n, counter),
+            })
+            .find(|id| self.check_id_unused(id).is_ok())
+            .expect("an unused id")
+    }
 
-    CMDLINE.get_or_init(|| {
-        let cmdline = CMDLINE_BUF.get_or_init(|| {
-            fs::read_to_string("/proc/cmdline")
-                .unwrap_or_default()
-                .into()
-        });
+    /// Remove the entry with the given name, returning it, unless it is protected.
+    ///
+    /// This does not remove the entry file from the disk.
+    pub fn remove_entry(&mut self, entry: &str) -> Result<Entry, Error> {
+        let pos = self.position(entry).ok_or(Error::NotFound)?;
+        check_unprotected(&self.entries[pos])?;
 
-        cmdline
-            .split_ascii_whitespace()
-            .collect::<Vec<&'static str>>()
-            .into()
-    })
+        let entry = self.entries.remove(pos);
+        self.reindex();
+        Ok(entry)
+    }
+
+    /// Remove the entry with the given name, along with its entry file.
+    pub fn delete_entry(&mut self, entry: &str) -> Result<Entry, Error> {
+        let pos = self.position(entry).ok_or(Error::NotFound)?;
+        self.check_owned(&self.entries[pos])?;
+        check_unprotected(&self.entries[pos])?;
+
+        let path = self.entry_path(&self.entries[pos]);
+
+        if let Err(source) = self.fs.remove(&path) {
+            return Err(Error::EntryRemove { path, source }.classify());
+        }
+        info!("removed entry file {:?}", path);
+
+        let entry = self.entries.remove(pos);
+        self.reindex();
+        self.notify(ChangeEvent::EntryRemoved {
+            id: entry.id.clone(),
+        });
+        Ok(entry)
+    }
+
+    /// The kernel version of the image that an entry boots, read from the image itself.
+    ///
+    /// This is the `linux` image, or the `efi` image of an entry which boots a UKI. `None` is
+    /// returned if the image does not record its version in a known way.
+    pub fn read_kernel_version(&self, entry: &Entry) -> Result<Option<String>, Error> {
+        let paths = entry.resolved_paths(self);
+        let path = match paths.linux.or(paths.efi) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        match self.fs.read(&path) {
+            Ok(data) => Ok(kernel::version_from_image(&data)),
+            Err(source) => Err(Error::ImageRead { path, source }.classify()),
+        }
+    }
+
+    /// Whether the `linux` image of an entry is an EFI-stub kernel, which systemd-boot requires,
+    /// or `None` for an entry which boots another EFI program.
+    pub fn is_efi_stub(&self, entry: &Entry) -> Result<Option<bool>, Error> {
+        let path = match entry.resolved_paths(self).linux {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        match self.fs.read(&path) {
+            Ok(data) => Ok(Some(kernel::is_efi_stub(&data))),
+            Err(source) => Err(Error::ImageRead { path, source }.classify()),
+        }
+    }
+
+    /// The boot loader, and the kernels and UKIs that the entries boot, which secure boot
+    /// firmware requires to be signed.
+    ///
+    /// The boot loader is `systemd-boot*.efi` in `EFI/systemd`, and the fallback loaders in
+    /// `EFI/BOOT`. Each image is listed once, even if several entries boot it.
+    pub fn boot_payloads(&self) -> Vec<PathBuf> {
+        let mut payloads = self
+            .loader_images()
+            .into_iter()
+            .map(|image| image.to_path(&self.efi_mount))
+            .collect::<Vec<_>>();
+
+        for entry in &self.entries {
+            let resolved = entry.resolved_paths(self);
+            for image in resolved.efi.into_iter().chain(resolved.linux) {
+                if !payloads.contains(&image) {
+                    payloads.push(image);
+                }
+            }
+        }
+
+        payloads
+    }
+
+    /// The systemd-boot and fallback boot loader images on the ESP.
+    fn loader_images(&self) -> Vec<EspPath> {
+        let mut payloads = Vec::new();
+
+        let loaders = [("/EFI/systemd", "systemd-boot"), ("/EFI/BOOT", "boot")];
+        for &(dir, prefix) in &loaders {
+            let mut images = self
+                .fs
+                .read_dir(&EspPath::new(dir).to_path(&self.efi_mount))
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|path| {
+                    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+                    if name.starts_with(prefix) && name.ends_with(".efi") {
+                        Some(EspPath::new(
+                            &[dir, "/", path.file_name()?.to_str()?].concat(),
+                        ))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            images.sort();
+            payloads.extend(images);
+        }
+
+        payloads
+    }
+
+    /// Compares the removable media fallback loader of each architecture that systemd-boot is
+    /// installed for to systemd-boot.
+    ///
+    /// A stale fallback loader causes machines which boot through it to run an old version of
+    /// systemd-boot, which may misparse newer configuration.
+    pub fn check_fallback_loaders(&self) -> Result<Vec<FallbackLoader>, Error> {
+        let mut loaders = Vec::new();
+
+        for image in self.loader_images() {
+            let name = image.as_str().rsplit('/').next().unwrap_or_default();
+            let architecture = match name
+                .strip_prefix("systemd-boot")
+                .and_then(|name| name.strip_suffix(".efi"))
+            {
+                Some(architecture) if !architecture.is_empty() => architecture.to_owned(),
+                _ => continue,
+            };
+
+            let loader = image.to_path(&self.efi_mount);
+            let fallback = EspPath::new(&format!(
+                "/EFI/BOOT/BOOT{}.EFI",
+                architecture.to_ascii_uppercase()
+            ))
+            .to_path(&self.efi_mount);
+
+            let loader_data = match self.fs.read(&loader) {
+                Ok(data) => data,
+                Err(source) => {
+                    return Err(Error::ImageRead {
+                        path: loader,
+                        source,
+                    }
+                    .classify())
+                }
+            };
+            let fallback_data = match self.fs.read(&fallback) {
+                Ok(data) => Some(data),
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => None,
+                Err(source) => {
+                    return Err(Error::ImageRead {
+                        path: fallback,
+                        source,
+                    }
+                    .classify())
+                }
+            };
+
+            let loader_version = status::loader_version_from_image(&loader_data);
+            let fallback_version = fallback_data
+                .as_deref()
+                .and_then(status::loader_version_from_image);
+
+            let state = match fallback_data {
+                None => FallbackState::Missing,
+                Some(ref data) if *data == loader_data => FallbackState::Identical,
+                Some(_)
+                    if fallback_version
+                        .as_deref()
+                        .is_some_and(|version| version.starts_with("systemd-boot")) =>
+                {
+                    FallbackState::Stale
+                }
+                Some(_) => FallbackState::Foreign,
+            };
+
+            loaders.push(FallbackLoader {
+                architecture,
+                loader,
+                fallback,
+                loader_version,
+                fallback_version,
+                state,
+            });
+        }
+
+        Ok(loaders)
+    }
+
+    /// Replaces stale and missing fallback loaders with copies of systemd-boot, returning the
+    /// fallback loaders which were written.
+    ///
+    /// Fallback loaders which are not systemd-boot, such as shim, are left alone.
+    pub fn fix_fallback_loaders(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut fixed = Vec::new();
+        let loaders: Vec<_> = self
+            .check_fallback_loaders()?
+            .into_iter()
+            .filter(|loader| loader.needs_fix())
+            .collect();
+
+        let growth = loaders
+            .iter()
+            .filter_map(|loader| {
+                let len = self.fs.metadata(&loader.loader).ok()?.len;
+                let existing = self.fs.metadata(&loader.fallback).map_or(0, |m| m.len);
+                Some(len.saturating_sub(existing))
+            })
+            .sum();
+
+        self.check_space(&self.efi_mount, growth, true)?;
+
+        for loader in loaders {
+            let data = self.fs.read(&loader.loader).map_err(|source| {
+                Error::ImageRead {
+                    path: loader.loader.clone(),
+                    source,
+                }
+                .classify()
+            })?;
+            let write = || {
+                if let Some(parent) = loader.fallback.parent() {
+                    self.fs.create_dir_all(parent)?;
+                }
+
+                self.write_file(&loader.fallback, &data)
+            };
+
+            if let Err(source) = write() {
+                return Err(Error::FallbackWrite {
+                    path: loader.fallback,
+                    source,
+                }
+                .classify());
+            }
+
+            info!("updated fallback boot loader {:?}", loader.fallback);
+            fixed.push(loader.fallback);
+        }
+
+        Ok(fixed)
+    }
+
+    /// The architectures which systemd-boot should be installed for: that of the firmware, and
+    /// those which entries are restricted to, such as `aa64` on an ESP shared with an ARM board.
+    pub fn loader_architectures(&self) -> Vec<EfiArch> {
+        let mut architectures: Vec<EfiArch> = self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.architecture.as_deref())
+            .filter_map(EfiArch::parse)
+            .chain(EfiArch::firmware())
+            .collect();
+
+        architectures.sort_unstable();
+        architectures.dedup();
+        architectures
+    }
+
+    /// Install systemd-boot for each architecture from `source`, such as
+    /// `/usr/lib/systemd/boot/efi`, to `EFI/systemd` and to the fallback path of the
+    /// architecture, such as `EFI/BOOT/BOOTAA64.EFI`, returning the files written.
+    ///
+    /// The signed image, `systemd-boot<arch>.efi.signed`, is preferred where it exists, as
+    /// bootctl does. Fallback loaders which are not systemd-boot, such as shim, are left alone.
+    pub fn install_loaders(
+        &self,
+        source: &Path,
+        architectures: &[EfiArch],
+    ) -> Result<Vec<PathBuf>, Error> {
+        let mut images = Vec::new();
+        for &arch in architectures {
+            let unsigned = source.join(arch.loader_name());
+            let signed = source.join([&arch.loader_name(), ".signed"].concat());
+
+            let (path, data) = match self.fs.read(&signed) {
+                Ok(data) => (signed, data),
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+                    match self.fs.read(&unsigned) {
+                        Ok(data) => (unsigned, data),
+                        Err(source) => {
+                            return Err(Error::ImageRead {
+                                path: unsigned,
+                                source,
+                            }
+                            .classify())
+                        }
+                    }
+                }
+                Err(source) => {
+                    return Err(Error::ImageRead {
+                        path: signed,
+                        source,
+                    }
+                    .classify())
+                }
+            };
+
+            let machine = PeImage::parse(&data).ok().map(|image| image.machine);
+            if machine.and_then(EfiArch::from_machine) != Some(arch) {
+                return Err(Error::WrongArchitecture {
+                    path,
+                    expected: arch,
+                });
+            }
+
+            let loader = self.efi_mount.join("EFI/systemd").join(arch.loader_name());
+            let fallback = EspPath::new(&["/EFI/BOOT/", &arch.fallback_name()].concat())
+                .to_path(&self.efi_mount);
+
+            images.push((data, loader, fallback));
+        }
+
+        let mut targets = Vec::new();
+        for (data, loader, fallback) in &images {
+            targets.push((data, loader, false));
+
+            let foreign = self.fs.read(fallback).ok().is_some_and(|existing| {
+                !status::loader_version_from_image(&existing)
+                    .is_some_and(|version| version.starts_with("systemd-boot"))
+            });
+
+            if foreign {
+                info!("leaving the fallback boot loader {:?} alone", fallback);
+            } else {
+                targets.push((data, fallback, true));
+            }
+        }
+
+        let growth = targets
+            .iter()
+            .map(|(data, path, _)| {
+                let existing = self.fs.metadata(path).map_or(0, |m| m.len);
+                (data.len() as u64).saturating_sub(existing)
+            })
+            .sum();
+
+        self.check_space(&self.efi_mount, growth, true)?;
+
+        let mut written = Vec::new();
+        for (data, path, is_fallback) in targets {
+            let write = || {
+                if let Some(parent) = path.parent() {
+                    self.fs.create_dir_all(parent)?;
+                }
+
+                self.write_file(path, data)
+            };
+
+            if let Err(source) = write() {
+                let path = path.clone();
+                let error = if is_fallback {
+                    Error::FallbackWrite { path, source }
+                } else {
+                    Error::LoaderWrite { path, source }
+                };
+
+                return Err(error.classify());
+            }
+
+            info!("installed boot loader {:?}", path);
+            written.push(path.clone());
+        }
+
+        Ok(written)
+    }
+
+    /// The Authenticode signatures of each of the `boot_payloads`.
+    #[cfg(feature = "authenticode")]
+    pub fn payload_signatures(&self) -> Vec<authenticode::PayloadSignature> {
+        self.boot_payloads()
+            .into_iter()
+            .map(|path| {
+                let signatures = self
+                    .fs
+                    .read(&path)
+                    .map_err(authenticode::AuthenticodeError::Open)
+                    .and_then(|data| authenticode::signatures(&data));
+
+                authenticode::PayloadSignature { path, signatures }
+            })
+            .collect()
+    }
+
+    /// The SBAT metadata of each of the `boot_payloads`, to find images which a revocation
+    /// policy would reject.
+    pub fn payload_sbat(&self) -> Vec<sbat::PayloadSbat> {
+        self.boot_payloads()
+            .into_iter()
+            .map(|path| {
+                let entries = self
+                    .fs
+                    .read(&path)
+                    .map_err(sbat::SbatError::Open)
+                    .and_then(|data| sbat::from_image(&data));
+
+                sbat::PayloadSbat { path, entries }
+            })
+            .collect()
+    }
+
+    /// Kernels, initrds, UKIs, and devicetrees which no entry references, in the directories
+    /// that hold the images of the entries.
+    ///
+    /// Images which systemd-boot finds without entries, such as UKIs in `EFI/Linux`, are not
+    /// orphans. As the ESP is FAT, paths are compared case-insensitively.
+    ///
+    /// This fails if any entry files were not loaded, as in `non_utf8_entries` and
+    /// `skipped_entries`, since the images they boot would otherwise be reported as orphans.
+    pub fn orphaned_payloads(&self) -> Result<Vec<OrphanedFile>, Error> {
+        let unloaded = self
+            .non_utf8_entries
+            .iter()
+            .chain(&self.skipped_entries)
+            .cloned()
+            .collect::<Vec<_>>();
+        if !unloaded.is_empty() {
+            return Err(Error::UnloadedEntries(unloaded));
+        }
+
+        let mut referenced = HashSet::new();
+        let mut dirs = Vec::new();
+
+        for entry in &self.entries {
+            let resolved = entry.resolved_paths(self);
+            let files = resolved
+                .linux
+                .into_iter()
+                .chain(resolved.efi)
+                .chain(resolved.initrd)
+                .chain(resolved.devicetree);
+
+            for file in files {
+                if let Some(dir) = file.parent() {
+                    if !dirs.iter().any(|d: &PathBuf| d == dir) {
+                        dirs.push(dir.to_owned());
+                    }
+                }
+
+                referenced.insert(file.to_string_lossy().to_lowercase());
+            }
+        }
+
+        let unmanaged = self.unmanaged_dirs();
+        dirs.retain(|dir| !unmanaged.contains(&dir.to_string_lossy().to_lowercase()));
+        dirs.sort();
+
+        let mut orphans = Vec::new();
+        for dir in dirs {
+            let files = match self.fs.read_dir(&dir) {
+                Ok(files) => files,
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(Error::OrphanRead { path: dir, source }.classify()),
+            };
+
+            let mut files = files
+                .into_iter()
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(gc::is_payload_name)
+                })
+                .filter(|path| !referenced.contains(&path.to_string_lossy().to_lowercase()))
+                .collect::<Vec<_>>();
+
+            files.sort();
+
+            for path in files {
+                match self.fs.symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => orphans.push(OrphanedFile {
+                        size: metadata.len,
+                        path,
+                    }),
+                    Ok(_) => (),
+                    Err(source) => return Err(Error::OrphanRead { path, source }.classify()),
+                }
+            }
+        }
+
+        Ok(orphans)
+    }
+
+    /// The lowercase paths of the directories whose images systemd-boot finds without entries.
+    fn unmanaged_dirs(&self) -> Vec<String> {
+        gc::UNMANAGED_DIRS
+            .iter()
+            .flat_map(|dir| {
+                let dir = EspPath::new(dir);
+                vec![dir.to_path(&self.efi_mount), dir.to_path(self.entry_root())]
+            })
+            .map(|dir| dir.to_string_lossy().to_lowercase())
+            .collect()
+    }
+
+    /// The entries which a retention policy keeps and prunes, and the images which pruning them
+    /// would leave unreferenced, without changing anything.
+    pub fn retention_plan(&self, policy: &RetentionPolicy, history: &BootHistory) -> RetentionPlan {
+        let mut kept = vec![false; self.entries.len()];
+
+        for (pos, entry) in self.entries.iter().enumerate() {
+            let foreign = self
+                .ownership
+                .as_ref()
+                .is_some_and(|owner| !owner.owns(entry));
+            kept[pos] = foreign || entry.protection().is_some();
+        }
+
+        let now = SystemTime::now();
+        let current = self.current_entry().map(|entry| entry.id.as_str());
+        let default = match self.resolve_default() {
+            Some(DefaultTarget::Entry(entry)) => Some(entry.id.as_str()),
+            _ => None,
+        };
+
+        for rule in &policy.rules {
+            match *rule {
+                RetentionRule::LatestPerToken(count) => {
+                    let mut tokens: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+                    for (pos, entry) in self.entries.iter().enumerate() {
+                        tokens.entry(retention::token(entry)).or_default().push(pos);
+                    }
+
+                    for positions in tokens.values_mut() {
+                        positions.sort_by(|&a, &b| {
+                            let (a, b) = (&self.entries[a], &self.entries[b]);
+                            b.kernel_version().cmp(&a.kernel_version())
+                        });
+
+                        for &pos in positions.iter().take(count) {
+                            kept[pos] = true;
+                        }
+                    }
+                }
+                RetentionRule::BootedWithin(age) => {
+                    for (pos, entry) in self.entries.iter().enumerate() {
+                        // Boots recorded in the future, after the clock was changed, are recent.
+                        let recent = match history.last_booted(&entry.id) {
+                            Some(booted) => now.duration_since(booted).unwrap_or_default() <= age,
+                            None => false,
+                        };
+
+                        kept[pos] |= recent;
+                    }
+                }
+                RetentionRule::Current => {
+                    if let Some(pos) = current.and_then(|id| self.position(id)) {
+                        kept[pos] = true;
+                    }
+                }
+                RetentionRule::Default => {
+                    if let Some(pos) = default.and_then(|id| self.position(id)) {
+                        kept[pos] = true;
+                    }
+                }
+            }
+        }
+
+        let mut plan = RetentionPlan::default();
+        let mut referenced = HashSet::new();
+        let mut candidates = Vec::new();
+
+        for (entry, kept) in self.entries.iter().zip(kept) {
+            let resolved = entry.resolved_paths(self);
+            let files = resolved
+                .linux
+                .into_iter()
+                .chain(resolved.efi)
+                .chain(resolved.initrd)
+                .chain(resolved.devicetree);
+
+            if kept {
+                plan.kept.push(entry.id.clone());
+                referenced.extend(files.map(|file| file.to_string_lossy().to_lowercase()));
+            } else {
+                plan.pruned.push(entry.id.clone());
+                candidates.extend(files);
+            }
+        }
+
+        let unmanaged = self.unmanaged_dirs();
+        for path in candidates {
+            let name = path.to_string_lossy().to_lowercase();
+            let dir = path
+                .parent()
+                .map(|dir| dir.to_string_lossy().to_lowercase());
+
+            if referenced.contains(&name) || dir.is_some_and(|dir| unmanaged.contains(&dir)) {
+                continue;
+            }
+
+            if let Ok(metadata) = self.fs.symlink_metadata(&path) {
+                if metadata.is_file() {
+                    referenced.insert(name);
+                    plan.payloads.push(OrphanedFile {
+                        size: metadata.len,
+                        path,
+                    });
+                }
+            }
+        }
+
+        plan
+    }
+
+    /// Delete the entries which a retention policy does not keep, and the images which only they
+    /// referenced, returning what was removed.
+    pub fn prune(
+        &mut self,
+        policy: &RetentionPolicy,
+        history: &BootHistory,
+    ) -> Result<RetentionPlan, Error> {
+        let plan = self.retention_plan(policy, history);
+
+        for id in &plan.pruned {
+            self.delete_entry(id)?;
+        }
+
+        for payload in &plan.payloads {
+            if let Err(source) = self.fs.remove(&payload.path) {
+                return Err(Error::OrphanRemove {
+                    path: payload.path.clone(),
+                    source,
+                }
+                .classify());
+            }
+
+            info!("removed boot image {:?} of a pruned entry", payload.path);
+        }
+
+        Ok(plan)
+    }
+
+    /// Remove the `orphaned_payloads`, except for those that are excluded, to reclaim space on
+    /// the ESP.
+    pub fn gc(&self, options: &GcOptions) -> Result<GcReport, Error> {
+        let mut report = GcReport {
+            dry_run: options.dry_run,
+            files: Vec::new(),
+        };
+
+        for orphan in self.orphaned_payloads()? {
+            if options.excludes(&orphan.path) {
+                continue;
+            }
+
+            if !options.dry_run {
+                if let Err(source) = self.fs.remove(&orphan.path) {
+                    return Err(Error::OrphanRemove {
+                        path: orphan.path,
+                        source,
+                    }
+                    .classify());
+                }
+
+                info!("removed orphaned boot image {:?}", orphan.path);
+            }
+
+            report.files.push(orphan);
+        }
+
+        Ok(report)
+    }
+
+    /// A SHA-256 manifest of the loader configuration, the entries, and the files they boot.
+    ///
+    /// Files which are missing are left out of the manifest.
+    pub fn manifest(&self) -> Result<Manifest, Error> {
+        self.hash_files(self.manifest_paths())
+    }
+
+    /// Compares the files on the ESP to a manifest made earlier, including files that the
+    /// configuration has referenced since.
+    pub fn verify_manifest(&self, manifest: &Manifest) -> Result<ManifestDiff, Error> {
+        let mut paths = self.manifest_paths();
+        for path in manifest.files.keys() {
+            if !paths.contains(path) {
+                paths.push(path.clone());
+            }
+        }
+
+        Ok(manifest.compare(&self.hash_files(paths)?))
+    }
+
+    /// Make the ESP mounted at `target` identical to this one, so that either may boot the
+    /// system.
+    ///
+    /// The loader conf, the entries, the images they boot, and the boot loaders are copied if
+    /// they differ. Entries, images, and boot loaders on the mirror which are not on this ESP are
+    /// removed. Files outside of this ESP, such as on an XBOOTLDR partition, are not mirrored.
+    pub fn sync_to<P: AsRef<Path>>(&self, target: P) -> Result<SyncReport, Error> {
+        let target = target.as_ref();
+        let mirror = |path: &Path| {
+            path.strip_prefix(&self.efi_mount)
+                .ok()
+                .map(|relative| target.join(relative))
+        };
+
+        let mut report = SyncReport::default();
+        let mut mirrored = HashSet::new();
+
+        let paths = self.manifest_paths();
+        let mut bytes = 0;
+        let total_bytes = paths
+            .iter()
+            .filter_map(|path| self.fs.metadata(path).ok())
+            .map(|metadata| metadata.len)
+            .sum();
+
+        let growth = paths
+            .iter()
+            .filter_map(|path| {
+                let len = self.fs.metadata(path).ok()?.len;
+                let existing = mirror(path)
+                    .and_then(|dest| self.fs.metadata(&dest).ok())
+                    .map_or(0, |metadata| metadata.len);
+                Some(len.saturating_sub(existing))
+            })
+            .sum();
+
+        self.check_space(target, growth, false)?;
+
+        for path in paths {
+            let dest = match mirror(&path) {
+                Some(dest) => dest,
+                None => continue,
+            };
+
+            mirrored.insert(dest.clone());
+
+            let contents = match self.fs.read(&path) {
+                Ok(contents) => contents,
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(Error::SyncCopy { path, source }.classify()),
+            };
+
+            let file_bytes = contents.len() as u64;
+            if self.fs.read(&dest).ok().as_deref() == Some(&contents[..]) {
+                bytes += file_bytes;
+                continue;
+            }
+
+            self.report_progress(&Progress {
+                operation: Operation::Sync,
+                path: &dest,
+                file_bytes,
+                bytes,
+                total_bytes,
+            });
+
+            bytes += file_bytes;
+
+            let copy = || {
+                if let Some(parent) = dest.parent() {
+                    self.fs.create_dir_all(parent)?;
+                }
+
+                self.write_file(&dest, &contents)
+            };
+
+            if let Err(source) = copy() {
+                return Err(Error::SyncCopy { path: dest, source }.classify());
+            }
+
+            info!("mirrored {:?} to {:?}", path, dest);
+            report.copied.push(dest);
+        }
+
+        // Load the mirror as it is now, to find what its stale entries reference.
+        let (entries_path, loader_path) =
+            match (mirror(&self.entries_path), mirror(&self.loader_path)) {
+                (Some(entries_path), Some(loader_path)) => (entries_path, loader_path),
+                _ => return Ok(report),
+            };
+
+        let mut secondary = self.clone();
+        secondary.efi_mount = target.to_owned();
+        secondary.entries_path = entries_path;
+        secondary.loader_path = loader_path;
+        secondary.xbootldr_mount = None;
+        secondary.parse_mode = ParseMode::Lenient;
+        secondary.load_conf()?;
+        secondary.load_entries()?;
+
+        for path in secondary.manifest_paths() {
+            if mirrored.contains(&path) || self.fs.symlink_metadata(&path).is_err() {
+                continue;
+            }
+
+            if let Err(source) = self.fs.remove(&path) {
+                return Err(Error::SyncRemove { path, source }.classify());
+            }
+
+            info!("removed {:?} from the mirrored ESP", path);
+            report.removed.push(path);
+        }
+
+        Ok(report)
+    }
+
+    fn manifest_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.loader_path.clone()];
+
+        for entry in &self.entries {
+            paths.push(self.entry_path(entry));
+
+            let resolved = entry.resolved_paths(self);
+            let files = resolved.initrd.into_iter().chain(resolved.devicetree);
+            for path in files {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        for path in self.boot_payloads() {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+
+        paths
+    }
+
+    fn hash_files(&self, paths: Vec<PathBuf>) -> Result<Manifest, Error> {
+        let mut manifest = Manifest::default();
+
+        for path in paths {
+            match self.fs.read(&path) {
+                Ok(contents) => manifest.insert(path, &contents),
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => (),
+                Err(source) => return Err(Error::ManifestRead { path, source }.classify()),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// The CPU microcode images on the ESP which an entry could load.
+    ///
+    /// Images are searched for in the root of the ESP, where distributions install them, and in
+    /// the directory of the entry's kernel.
+    pub fn microcode_images(&self, entry: &Entry) -> Vec<String> {
+        let mut dirs = vec![EspPath::new("/")];
+        if !entry.linux.is_empty() {
+            let linux = EspPath::new(&entry.linux);
+            if let Some(pos) = linux.as_str().rfind('/') {
+                dirs.push(EspPath::new(&linux.as_str()[..pos]));
+            }
+        }
+
+        dirs.dedup();
+
+        let mut images = Vec::new();
+        for dir in dirs {
+            for name in MICROCODE {
+                let image = EspPath::new(&[dir.as_str(), "/", name].concat());
+                let exists = self
+                    .fs
+                    .metadata(&image.to_path(self.entry_root()))
+                    .is_ok_and(|metadata| metadata.is_file());
+
+                if exists {
+                    images.push(image.to_string());
+                }
+            }
+        }
+
+        images
+    }
+
+    /// Add the microcode images found on the ESP to an existing entry, and load them first.
+    ///
+    /// Returns whether the entry was changed, after which it should be written with
+    /// `overwrite_entry_conf`.
+    pub fn attach_microcode(&mut self, entry: &str) -> Result<bool, Error> {
+        let pos = self.position(entry).ok_or(Error::NotFound)?;
+        let images = self.microcode_images(&self.entries[pos]);
+        let changed = self.entries[pos].attach_microcode(images);
+
+        if changed {
+            info!("attached microcode to entry {}", entry);
+        }
+
+        Ok(changed)
+    }
+
+    /// Rebuild the id lookup table after modifying `entries` directly.
+    ///
+    /// Lookups remain correct without this, but may fall back to a linear search.
+    pub fn reindex(&mut self) {
+        let &mut SystemdBootConf {
+            ref entries,
+            ref mut index,
+            ..
+        } = self;
+
+        index.clear();
+        for (pos, entry) in entries.iter().enumerate() {
+            index.entry(entry.id.clone()).or_insert(pos);
+        }
+    }
+
+    /// Finds the position of an entry, verifying the lookup table against the entries.
+    fn position(&self, entry: &str) -> Option<usize> {
+        if let Some(&pos) = self.index.get(entry) {
+            if self.entries.get(pos).is_some_and(|e| e.id == entry) {
+                return Some(pos);
+            }
+        }
+
+        self.entries.iter().position(|e| e.id == entry)
+    }
+
+    /// Finds the position of an entry by its id, preferring an exact match to one which differs
+    /// by case.
+    fn position_of(&self, id: &EntryId) -> Option<usize> {
+        self.position(id.as_str())
+            .or_else(|| self.entries.iter().position(|e| id.matches(&e.id)))
+    }
+
+    /// The `entries.srel` file beside the entries directory, which declares the format of the
+    /// entries to tools such as `bootctl`.
+    pub fn entries_srel_path(&self) -> PathBuf {
+        match self.entries_path.parent() {
+            Some(parent) => parent.join("entries.srel"),
+            None => PathBuf::from("entries.srel"),
+        }
+    }
+
+    /// Attempt to re-read the loader configuration.
+    pub fn load_conf(&mut self) -> Result<(), Error> {
+        let &mut SystemdBootConf {
+            ref mut loader_conf,
+            ref loader_path,
+            parse_mode,
+            ref mut warnings,
+            ref fs,
+            ..
+        } = self;
+
+        warnings.retain(|warning| warning.path != *loader_path);
+
+        let start = Instant::now();
+        let result = match fs.metadata(loader_path) {
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(LoaderConf::default()),
+            Err(why) => Err(LoaderError::Open(why)),
+            Ok(ref metadata) if !metadata.is_file() => Err(LoaderError::NotAFile),
+            Ok(_) => fs
+                .read(loader_path)
+                .map_err(LoaderError::Open)
+                .and_then(|contents| {
+                    LoaderConf::parse_file(loader_path, &contents, parse_mode, warnings)
+                }),
+        };
+
+        *loader_conf = result.map_err(move |source| {
+            warn!(
+                "failed to load loader conf at {:?}: {}",
+                loader_path, source
+            );
+            Error::Loader {
+                path: loader_path.to_path_buf(),
+                source,
+            }
+            .classify()
+        })?;
+
+        debug!(
+            "loaded loader conf from {:?} in {:?}: {:?}",
+            loader_path,
+            start.elapsed(),
+            loader_conf
+        );
+
+        Ok(())
+    }
+
+    /// Attempt to load all of the available entries in the system.
+    pub fn load_entries(&mut self) -> Result<(), Error> {
+        let srel_path = self.entries_srel_path();
+        self.entries_srel = match self.fs.read(&srel_path) {
+            Ok(contents) => Some(String::from_utf8_lossy(&contents).trim().to_owned()),
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => None,
+            Err(why) => {
+                warn!("failed to read {:?}: {}", srel_path, why);
+                None
+            }
+        };
+
+        let &mut SystemdBootConf {
+            ref mut entries,
+            ref entries_path,
+            ref mut non_utf8_entries,
+            ref mut skipped_entries,
+            symlink_policy,
+            parse_mode,
+            ref mut warnings,
+            ref loader_path,
+            ref fs,
+            ..
+        } = self;
+        let start = Instant::now();
+        let dir_entries = fs.read_dir(entries_path).map_err(|why| {
+            warn!(
+                "failed to read entries directory {:?}: {}",
+                entries_path, why
+            );
+            Error::EntriesDir {
+                path: entries_path.clone(),
+                source: why,
+            }
+            .classify()
+        })?;
+
+        entries.clear();
+        non_utf8_entries.clear();
+        skipped_entries.clear();
+        warnings.retain(|warning| warning.path == *loader_path);
+        for path in dir_entries {
+            let file_name = path.file_name().map(|name| name.to_string_lossy());
+
+            // Only consider conf files in the directory.
+            let is_file = fs.metadata(&path).is_ok_and(|metadata| metadata.is_file());
+            if !is_file || !file_name.is_some_and(|name| is_entry_file_name(&name)) {
+                trace!("ignoring {:?} in entries directory", path);
+                continue;
+            }
+
+            let metadata = match fs.symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(source) => return Err(Error::FileEntry { path, source }.classify()),
+            };
+
+            if metadata.is_symlink() {
+                match symlink_policy {
+                    SymlinkPolicy::Follow => (),
+                    SymlinkPolicy::Skip => {
+                        debug!("skipping symlinked entry at {:?}", path);
+                        skipped_entries.push(path);
+                        continue;
+                    }
+                    SymlinkPolicy::Error => return Err(Error::EntrySymlink(path)),
+                }
+            }
+
+            let result = id_from_path(&path).and_then(|id| {
+                let contents = fs.read(&path).map_err(EntryError::Open)?;
+                Entry::parse_with_id(&path, id, &contents, parse_mode, warnings)
+            });
+
+            let entry = match result {
+                Ok(entry) => entry,
+                Err(EntryError::Utf8Filename) => {
+                    warn!("entry at {:?} has a file name which is not UTF-8", path);
+                    non_utf8_entries.push(path);
+                    continue;
+                }
+                Err(source) => {
+                    warn!("failed to parse entry at {:?}: {}", path, source);
+                    return Err(Error::Entry { path, source }.classify());
+                }
+            };
+
+            if entry.microcode_misordered() {
+                warn!("entry {} loads microcode after another initrd", entry.id);
+            }
+
+            trace!("loaded entry {} from {:?}", entry.id, path);
+            entries.push(entry);
+        }
+
+        debug!(
+            "loaded {} entries from {:?} in {:?}",
+            entries.len(),
+            entries_path,
+            start.elapsed()
+        );
+
+        self.reindex();
+
+        Ok(())
+    }
+
+    /// Re-read the loader conf and entries from disk, returning how they differ from the
+    /// configuration in memory.
+    ///
+    /// If either fails to load, the configuration in memory is left as it was. Listeners are not
+    /// notified, as the changes were not made by this instance.
+    pub fn reload(&mut self) -> Result<ChangeSet, Error> {
+        let mut fresh = self.clone();
+        fresh.load_conf()?;
+        fresh.load_entries()?;
+
+        let mut changes = ChangeSet {
+            loader: events::loader_changes(&self.loader_conf, &fresh.loader_conf),
+            ..ChangeSet::default()
+        };
+
+        for entry in &self.entries {
+            if fresh.position(&entry.id).is_none() {
+                changes.removed.push(entry.id.clone());
+            }
+        }
+
+        for entry in &fresh.entries {
+            match self.find(&entry.id) {
+                None => changes.added.push(entry.id.clone()),
+                Some(previous) => {
+                    let fields = events::entry_changes(previous, entry);
+                    if !fields.is_empty() {
+                        changes.modified.push((entry.id.clone(), fields));
+                    }
+                }
+            }
+        }
+
+        debug!(
+            "reloaded configuration from {:?}: {:?}",
+            self.efi_mount, changes
+        );
+        *self = fresh;
+
+        Ok(changes)
+    }
+
+    /// Give an entry file with a non-UTF-8 file name a new id, and load it.
+    ///
+    /// The path should be one of the paths in `non_utf8_entries`.
+    pub fn rename_non_utf8_entry(&mut self, path: &Path, id: &str) -> Result<(), Error> {
+        let pos = match self.non_utf8_entries.iter().position(|p| p == path) {
+            Some(pos) => pos,
+            None => return Err(Error::NotFound),
+        };
+
+        self.check_id_unused(id)?;
+
+        let new_path = self.entries_path.join(format!("{}.conf", id));
+        if let Err(source) = validate_id(id) {
+            return Err(Error::Entry {
+                path: new_path,
+                source,
+            });
+        }
+
+        if let Err(source) = self.fs.rename(path, &new_path) {
+            return Err(Error::EntryRename {
+                path: path.to_path_buf(),
+                to: new_path,
+                source,
+            }
+            .classify());
+        }
+        self.non_utf8_entries.remove(pos);
+
+        let result = self
+            .fs
+            .read(&new_path)
+            .map_err(EntryError::Open)
+            .and_then(|contents| {
+                let mode = self.parse_mode;
+                Entry::parse_with_id(
+                    &new_path,
+                    id.to_owned(),
+                    &contents,
+                    mode,
+                    &mut self.warnings,
+                )
+            });
+
+        let entry = result.map_err(|source| Error::Entry {
+            path: new_path,
+            source,
+        })?;
+
+        self.insert_entry(entry)?;
+        self.notify(ChangeEvent::EntryAdded { id: id.to_owned() });
+        Ok(())
+    }
+
+    /// Enable boot counting for an entry, giving it `tries` boot attempts.
+    ///
+    /// The entry file is renamed on disk to carry the new counter, and the id of the entry is
+    /// updated to match.
+    pub fn set_boot_tries(&mut self, entry: &str, tries: u32) -> Result<(), Error> {
+        let entries_path = self.entries_path.clone();
+        let fs = self.fs.clone();
+        let pos = self.position(entry).ok_or(Error::NotFound)?;
+        self.check_owned(&self.entries[pos])?;
+
+        let counter = BootCounter {
+            tries_left: tries,
+            tries_done: 0,
+        };
+
+        let id = [self.entries[pos].base_id(), &counter.to_string()].concat();
+        if id != self.entries[pos].id {
+            self.check_id_unused(&id)?;
+        }
+
+        let entry = &mut self.entries[pos];
+
+        let path = entries_path.join(entry.conf_file_name());
+        let file_name = format!("{}.conf", id);
+        let to = entries_path.join(&file_name);
+        if let Err(source) = fs.rename(&path, &to) {
+            return Err(Error::EntryRename { path, to, source }.classify());
+        }
+
+        entry.file_name = Some(file_name);
+
+        info!("enabled boot counting for {} as {}", entry.id, id);
+        let from = std::mem::replace(&mut entry.id, id.clone());
+        self.reindex();
+        self.notify(ChangeEvent::EntryRenamed { from, to: id });
+
+        Ok(())
+    }
+
+    /// Overwrite the conf file with stored values.
+    pub fn overwrite_loader_conf(&self) -> Result<(), Error> {
+        let previous = if self.listeners.is_empty() {
+            None
+        } else {
+            let contents = self.fs.read(&self.loader_path).unwrap_or_default();
+            let previous = LoaderConf::parse_file(
+                &self.loader_path,
+                &contents,
+                ParseMode::Lenient,
+                &mut Vec::new(),
+            );
+            Some(previous.unwrap_or_default())
+        };
+
+        let result = self.try_io(&self.loader_path, |file| {
+            render_loader_conf(&self.loader_conf, &self.write_style, file)
+        });
+
+        result.map_err(|source| {
+            Error::LoaderWrite {
+                path: self.loader_path.clone(),
+                source,
+            }
+            .classify()
+        })?;
+
+        if let Some(previous) = previous {
+            let current = &self.loader_conf;
+            if previous.default != current.default {
+                self.notify(ChangeEvent::DefaultChanged {
+                    old: previous.default,
+                    new: current.default.clone(),
+                });
+            }
+
+            if previous.timeout != current.timeout {
+                self.notify(ChangeEvent::TimeoutChanged {
+                    old: previous.timeout,
+                    new: current.timeout,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rewrite the loader conf and every entry in canonical formatting, returning the files which
+    /// changed.
+    ///
+    /// Keys are written in lowercase, in a fixed order, with single spaces, and the options of an
+    /// entry are joined into one line. Keys which are not modelled follow the others in the order
+    /// they were read. Every entry is validated before anything is written, and
+    /// if a write fails, the files which were already rewritten are restored.
+    pub fn normalize(&mut self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+
+        let mut contents = Vec::new();
+        render_loader_conf(&self.loader_conf, &self.write_style, &mut contents).map_err(
+            |source| Error::LoaderWrite {
+                path: self.loader_path.clone(),
+                source,
+            },
+        )?;
+        files.push((self.loader_path.clone(), contents, false));
+
+        for entry in &mut self.entries {
+            if !self.ownership.as_ref().is_none_or(|o| o.owns(entry)) {
+                debug!("not normalizing entry {} of another installation", entry.id);
+                continue;
+            }
+
+            let path = self.entries_path.join(entry.conf_file_name());
+            if let Err(source) = entry.validate() {
+                return Err(Error::Entry { path, source });
+            }
+
+            entry.options_raw.clear();
+            let mut contents = Vec::new();
+            if let Err(source) = render_entry_conf(entry, &self.write_style, &mut contents) {
+                return Err(Error::EntryWrite { path, source });
+            }
+            files.push((path, contents, true));
+        }
+
+        let mut written: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+        for (path, contents, is_entry) in files {
+            let original = self.fs.read(&path).ok();
+            if original.as_deref() == Some(&contents[..]) {
+                continue;
+            }
+
+            if let Err(why) = self.write_file(&path, &contents) {
+                warn!(
+                    "failed to normalize {:?}: {}; restoring other files",
+                    path, why
+                );
+                for (path, original) in written.into_iter().rev() {
+                    let _ = match original {
+                        Some(original) => self.write_file(&path, &original),
+                        None => self.fs.remove(&path),
+                    };
+                }
+
+                let error = if is_entry {
+                    Error::EntryWrite { path, source: why }
+                } else {
+                    Error::LoaderWrite { path, source: why }
+                };
+
+                return Err(error.classify());
+            }
+
+            info!("normalized {:?}", path);
+            written.push((path, original));
+        }
+
+        Ok(written.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Overwrite the entry conf for the given entry.
+    pub fn overwrite_entry_conf(&self, id: &EntryId) -> Result<(), Error> {
+        let entry = self.get(id).ok_or(Error::NotFound)?;
+        self.check_owned(entry)?;
+        self.write_entry_conf(entry)
+    }
+
+    /// Fails if the ownership guard is set, and the entry belongs to another installation.
+    fn check_owned(&self, entry: &Entry) -> Result<(), Error> {
+        match self.ownership {
+            Some(ref ownership) if !ownership.owns(entry) => {
+                warn!(
+                    "refusing to modify entry {} of another installation",
+                    entry.id
+                );
+                Err(Error::NotOwned(entry.id.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run `f` with the ownership guard lifted, such as to clean up after an installation which
+    /// was removed, and restore it afterwards.
+    pub fn override_ownership<T, F: FnOnce(&mut Self) -> T>(&mut self, f: F) -> T {
+        let ownership = self.ownership.take();
+        let result = f(self);
+        self.ownership = ownership;
+        result
+    }
+
+    /// Whether the ESP, or the XBOOTLDR partition if there is one, is mounted read-only, so that
+    /// writing to it would fail with `Error::ReadOnly`.
+    pub fn is_read_only(&self) -> io::Result<bool> {
+        for mount in self.mounts() {
+            if self.fs.is_read_only(mount)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Run `f` with the ESP and XBOOTLDR partition mounted read-write, remounting those which
+    /// are mounted read-only first, and remounting them read-only again afterwards, even if `f`
+    /// fails.
+    ///
+    /// This requires root privileges if a partition is remounted.
+    pub fn with_read_write<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        let mut remounted: Vec<PathBuf> = Vec::new();
+        for mount in self.mounts() {
+            let read_only = self.fs.is_read_only(mount).unwrap_or(false);
+            if !read_only || remounted.iter().any(|path| path == mount) {
+                continue;
+            }
+
+            if let Err(source) = mount::remount(mount, false) {
+                // The partitions which were remounted are restored on a best effort basis.
+                let _ = self.restore_read_only(&remounted);
+                let path = mount.to_owned();
+                return Err(Error::Remount { path, source }.classify());
+            }
+
+            remounted.push(mount.to_owned());
+        }
+
+        let result = f(self);
+        let restored = self.restore_read_only(&remounted);
+
+        // A failure of `f` is reported over a failure to restore the partitions.
+        let value = result?;
+        restored.map(|()| value)
+    }
+
+    /// Remount partitions read-only again, returning the first failure.
+    fn restore_read_only(&self, mounts: &[PathBuf]) -> Result<(), Error> {
+        let mut result = Ok(());
+        for mount in mounts {
+            if let Err(source) = mount::remount(mount, true) {
+                warn!("unable to remount {:?} read-only: {}", mount, source);
+                if result.is_ok() {
+                    result = Err(Error::Remount {
+                        path: mount.to_owned(),
+                        source,
+                    }
+                    .classify());
+                }
+            }
+        }
+
+        result
+    }
+
+    /// The path of the conf file of an entry.
+    fn entry_path(&self, entry: &Entry) -> PathBuf {
+        self.entries_path.join(entry.conf_file_name())
+    }
+
+    /// The mount points of the ESP and the XBOOTLDR partition.
+    fn mounts(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.efi_mount.as_path()).chain(self.xbootldr_mount.as_deref())
+    }
+
+    fn write_entry_conf(&self, entry: &Entry) -> Result<(), Error> {
+        let path = self.entry_path(entry);
+        if let Err(source) = entry.validate() {
+            warn!("refusing to write invalid entry {}: {}", entry.id, source);
+            return Err(Error::Entry { path, source });
+        }
+
+        // The entry as it was on disk, to report what changed.
+        let previous = if self.listeners.is_empty() {
+            None
+        } else {
+            Some(self.fs.read(&path).ok().map(|contents| {
+                let id = entry.id.clone();
+                Entry::parse_with_id(&path, id, &contents, ParseMode::Lenient, &mut Vec::new())
+                    .unwrap_or_default()
+            }))
+        };
+
+        let result = self.try_io(&path, |file| {
+            render_entry_conf(entry, &self.write_style, file)
+        });
+
+        result.map_err(|source| Error::EntryWrite { path, source }.classify())?;
+
+        let id = entry.id.clone();
+        match previous {
+            Some(None) => self.notify(ChangeEvent::EntryAdded { id }),
+            Some(Some(previous)) => {
+                let changes = events::entry_changes(&previous, entry);
+                if !changes.is_empty() {
+                    self.notify(ChangeEvent::EntryModified { id, changes });
+                }
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    /// Write the loader conf, `entries.srel`, and every entry into the ESP tree at `target`,
+    /// creating its directories, and return the configuration as persisted there.
+    ///
+    /// Entries are written to `loader/entries` in the target, even if they were read from an
+    /// XBOOTLDR partition. Every entry is validated before anything is written.
+    pub fn persist_to<P: Into<PathBuf>>(&self, target: P) -> Result<SystemdBootConf, Error> {
+        let target = target.into();
+
+        for entry in &self.entries {
+            if let Err(source) = entry.validate() {
+                let path = self.entry_path(entry);
+                return Err(Error::Entry { path, source });
+            }
+        }
+
+        let relative = |path: &Path, root: &Path, default: &str| match path.strip_prefix(root) {
+            Ok(relative) => target.join(relative),
+            Err(_) => target.join(default),
+        };
+
+        let mut persisted = self.clone();
+        persisted.entries_path = relative(&self.entries_path, self.entry_root(), "loader/entries");
+        persisted.loader_path = relative(&self.loader_path, &self.efi_mount, "loader/loader.conf");
+        persisted.xbootldr_mount = None;
+        persisted.efi_mount = target;
+
+        info!(
+            "persisting loader configuration to {:?}",
+            persisted.efi_mount
+        );
+
+        let create_dir = |path: &Path| {
+            self.fs.create_dir_all(path).map_err(|source| {
+                Error::CreateDir {
+                    path: path.to_path_buf(),
+                    source,
+                }
+                .classify()
+            })
+        };
+
+        create_dir(&persisted.entries_path)?;
+        if let Some(parent) = persisted.loader_path.parent() {
+            create_dir(parent)?;
+        }
+
+        persisted.overwrite_loader_conf()?;
+
+        let srel_path = persisted.entries_srel_path();
+        let srel = format!("{}\n", self.entries_srel.as_deref().unwrap_or("type1"));
+        if let Err(source) = persisted.write_file(&srel_path, srel.as_bytes()) {
+            return Err(Error::LoaderWrite {
+                path: srel_path,
+                source,
+            }
+            .classify());
+        }
+
+        for entry in &self.entries {
+            persisted.write_entry_conf(entry)?;
+        }
+
+        Ok(persisted)
+    }
+
+    /// The directory of key sets which systemd-boot may enroll for secure boot.
+    pub fn keys_path(&self) -> PathBuf {
+        self.efi_mount.join("loader/keys")
+    }
+
+    /// The key sets in `loader/keys`, sorted by name.
+    pub fn secure_boot_key_sets(&self) -> Result<Vec<KeySet>, Error> {
+        let keys_path = self.keys_path();
+        let dirs = match self.fs.read_dir(&keys_path) {
+            Ok(dirs) => dirs,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(Error::KeysDir {
+                    path: keys_path,
+                    source,
+                }
+                .classify())
+            }
+        };
+
+        let mut sets = Vec::new();
+        for dir in dirs {
+            let is_dir = self.fs.metadata(&dir).is_ok_and(|m| m.is_dir());
+            let name = match dir.file_name().and_then(|name| name.to_str()) {
+                Some(name) if is_dir => name.to_owned(),
+                _ => continue,
+            };
+
+            let keys = KeyKind::ALL
+                .iter()
+                .cloned()
+                .filter(|kind| {
+                    let path = dir.join(kind.file_name());
+                    self.fs.metadata(&path).is_ok_and(|m| m.is_file())
+                })
+                .collect();
+
+            sets.push(KeySet { name, keys });
+        }
+
+        sets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(sets)
+    }
+
+    /// Check that a key set has the keys that systemd-boot requires, and that each key file is
+    /// a signed variable update.
+    pub fn validate_secure_boot_keys(&self, name: &str) -> Result<(), Error> {
+        let path = self.keys_path().join(name);
+        let error = |source| Error::Keys {
+            path: path.clone(),
+            source,
+        };
+
+        secure_boot::validate_name(name).map_err(error)?;
+
+        for &kind in &KeyKind::ALL {
+            let data = match self.fs.read(&path.join(kind.file_name())) {
+                Ok(data) => data,
+                Err(ref why) if why.kind() == io::ErrorKind::NotFound => {
+                    if KeyKind::REQUIRED.contains(&kind) {
+                        return Err(error(KeyError::Missing(kind)));
+                    }
+
+                    continue;
+                }
+                Err(why) => return Err(error(KeyError::Open(kind, why))),
+            };
+
+            secure_boot::validate_auth(kind, &data).map_err(error)?;
+        }
+
+        Ok(())
+    }
+
+    /// The credentials which systemd-stub passes to every UKI, or to one UKI, sorted by name.
+    pub fn credentials(&self, scope: &StubScope) -> Result<Vec<Credential>, Error> {
+        let dir = scope.dir(&self.efi_mount, stub::CREDENTIALS_DIR);
+        let files = self.stub_files(dir, stub::CREDENTIAL_SUFFIX)?;
+
+        Ok(files
+            .into_iter()
+            .map(|(name, path, size)| Credential { name, path, size })
+            .collect())
+    }
+
+    /// Install an encrypted credential for systemd-stub to pass to every UKI, or to one UKI,
+    /// replacing any credential of the same name, and return its path.
+    ///
+    /// The contents are written as they are given, so they should be encrypted with
+    /// `systemd-creds encrypt` beforehand.
+    pub fn install_credential(
+        &self,
+        scope: &StubScope,
+        name: &str,
+        contents: &[u8],
+    ) -> Result<PathBuf, Error> {
+        let path = self.credential_path(scope, name)?;
+
+        let write = || {
+            if let Some(parent) = path.parent() {
+                self.fs.create_dir_all(parent)?;
+            }
+
+            self.write_file(&path, contents)
+        };
+
+        if let Err(source) = write() {
+            return Err(Error::StubWrite { path, source }.classify());
+        }
+
+        info!("installed credential {:?}", path);
+        Ok(path)
+    }
+
+    /// Remove a credential, returning its path.
+    pub fn remove_credential(&self, scope: &StubScope, name: &str) -> Result<PathBuf, Error> {
+        let path = self.credential_path(scope, name)?;
+
+        if let Err(source) = self.fs.remove(&path) {
+            return Err(Error::StubRemove { path, source }.classify());
+        }
+
+        info!("removed credential {:?}", path);
+        Ok(path)
+    }
+
+    fn credential_path(&self, scope: &StubScope, name: &str) -> Result<PathBuf, Error> {
+        if !stub::is_valid_credential_name(name) {
+            return Err(Error::InvalidCredentialName(name.to_owned()));
+        }
+
+        let file_name = [name, stub::CREDENTIAL_SUFFIX].concat();
+        Ok(scope
+            .dir(&self.efi_mount, stub::CREDENTIALS_DIR)
+            .join(file_name))
+    }
+
+    /// The addons which systemd-stub loads for every UKI, or for one UKI, sorted by name, with
+    /// the command lines that they append.
+    pub fn addons(&self, scope: &StubScope) -> Result<Vec<Addon>, Error> {
+        let dir = scope.dir(&self.efi_mount, stub::ADDONS_DIR);
+        let mut addons = Vec::new();
+
+        for (name, path, size) in self.stub_files(dir, stub::ADDON_SUFFIX)? {
+            let cmdline = match self.fs.read(&path) {
+                Ok(image) => stub::addon_cmdline(&image),
+                Err(source) => return Err(Error::StubDir { path, source }.classify()),
+            };
+
+            addons.push(Addon {
+                name,
+                path,
+                size,
+                cmdline,
+            });
+        }
+
+        Ok(addons)
+    }
+
+    /// Install an addon for systemd-stub to load for every UKI, or for one UKI, replacing any
+    /// addon of the same name, and return its path.
+    ///
+    /// The image must be a PE image, which should be signed for secure boot.
+    pub fn install_addon(
+        &self,
+        scope: &StubScope,
+        name: &str,
+        image: &[u8],
+    ) -> Result<PathBuf, Error> {
+        let path = self.addon_path(scope, name)?;
+
+        if let Err(source) = PeImage::parse(image) {
+            return Err(Error::InvalidAddon { path, source });
+        }
+
+        let write = || {
+            if let Some(parent) = path.parent() {
+                self.fs.create_dir_all(parent)?;
+            }
+
+            self.write_file(&path, image)
+        };
+
+        if let Err(source) = write() {
+            return Err(Error::StubWrite { path, source }.classify());
+        }
+
+        info!("installed addon {:?}", path);
+        Ok(path)
+    }
+
+    /// Remove an addon, returning its path.
+    pub fn remove_addon(&self, scope: &StubScope, name: &str) -> Result<PathBuf, Error> {
+        let path = self.addon_path(scope, name)?;
+
+        if let Err(source) = self.fs.remove(&path) {
+            return Err(Error::StubRemove { path, source }.classify());
+        }
+
+        info!("removed addon {:?}", path);
+        Ok(path)
+    }
+
+    fn addon_path(&self, scope: &StubScope, name: &str) -> Result<PathBuf, Error> {
+        if !stub::is_valid_addon_name(name) {
+            return Err(Error::InvalidAddonName(name.to_owned()));
+        }
+
+        let file_name = [name, stub::ADDON_SUFFIX].concat();
+        Ok(scope.dir(&self.efi_mount, stub::ADDONS_DIR).join(file_name))
+    }
+
+    /// The names, paths, and sizes of the files in a directory that systemd-stub loads, whose
+    /// names end with `suffix`, ignoring its case, sorted by name.
+    fn stub_files(&self, dir: PathBuf, suffix: &str) -> Result<Vec<(String, PathBuf, u64)>, Error> {
+        let paths = match self.fs.read_dir(&dir) {
+            Ok(paths) => paths,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(Error::StubDir { path: dir, source }.classify()),
+        };
+
+        let mut files = Vec::new();
+        for path in paths {
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) if name.len() > suffix.len() => name,
+                _ => continue,
+            };
+
+            let (name, name_suffix) = name.split_at(name.len() - suffix.len());
+            if !name_suffix.eq_ignore_ascii_case(suffix) {
+                continue;
+            }
+
+            match self.fs.metadata(&path) {
+                Ok(metadata) if metadata.is_file() => {
+                    files.push((name.to_owned(), path, metadata.len))
+                }
+                Ok(_) => (),
+                Err(source) => return Err(Error::StubDir { path, source }.classify()),
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// The addons, credentials, and other files in the `.extra.d` directory of a UKI.
+    pub fn uki_extras(&self, uki: &Path) -> Result<UkiExtras, Error> {
+        let scope = StubScope::Uki(uki.to_owned());
+        let addons = self.addons(&scope)?;
+        let credentials = self.credentials(&scope)?;
+        let dir = stub::extra_dir(uki);
+
+        let mut other = match self.fs.read_dir(&dir) {
+            Ok(paths) => paths,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(source) => return Err(Error::StubDir { path: dir, source }.classify()),
+        };
+
+        other.retain(|path| {
+            !addons.iter().any(|addon| &addon.path == path)
+                && !credentials
+                    .iter()
+                    .any(|credential| &credential.path == path)
+        });
+        other.sort();
+
+        Ok(UkiExtras {
+            uki: uki.to_owned(),
+            dir,
+            addons,
+            credentials,
+            other,
+        })
+    }
+
+    /// Install a unified kernel image, such as one built with `UkiBuilder`, as
+    /// `EFI/Linux/<name>.efi`, replacing any image of the same name, and return its path.
+    ///
+    /// The image is installed to the XBOOTLDR partition if entries are kept there, as
+    /// kernel-install installs them.
+    pub fn install_uki(&self, name: &str, image: &[u8]) -> Result<PathBuf, Error> {
+        if !uki::is_valid_name(name) {
+            return Err(Error::InvalidUkiName(name.to_owned()));
+        }
+
+        let dir = self.entry_root().join("EFI/Linux");
+        let path = dir.join([name, uki::UKI_SUFFIX].concat());
+
+        if let Err(source) = uki::validate(image) {
+            return Err(Error::InvalidUki { path, source });
+        }
+
+        self.check_space(&dir, image.len() as u64, true)?;
+
+        let write = || {
+            self.fs.create_dir_all(&dir)?;
+            self.write_file(&path, image)
+        };
+
+        if let Err(source) = write() {
+            return Err(Error::UkiWrite { path, source }.classify());
+        }
+
+        info!("installed unified kernel image {:?}", path);
+        Ok(path)
+    }
+
+    /// Remove a UKI along with its `.extra.d` directory, returning the paths removed.
+    ///
+    /// The UKI is removed first, so that a failure to remove its extra files leaves an orphaned
+    /// directory for `orphaned_extra_dirs` to find, rather than a UKI without its credentials.
+    pub fn remove_uki(&self, uki: &Path) -> Result<Vec<PathBuf>, Error> {
+        if let Err(source) = self.fs.remove(uki) {
+            return Err(Error::UkiRemove {
+                path: uki.to_owned(),
+                source,
+            }
+            .classify());
+        }
+
+        info!("removed unified kernel image {:?}", uki);
+
+        let mut removed = vec![uki.to_owned()];
+        removed.extend(self.remove_extra_dir(uki)?);
+        Ok(removed)
+    }
+
+    /// Remove the `.extra.d` directory of a UKI and everything in it, returning the paths
+    /// removed, which are none if it does not exist.
+    pub fn remove_extra_dir(&self, uki: &Path) -> Result<Vec<PathBuf>, Error> {
+        let dir = stub::extra_dir(uki);
+        let mut removed = Vec::new();
+
+        match self.fs.symlink_metadata(&dir) {
+            Ok(_) => self.remove_tree(&dir, &mut removed)?,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => (),
+            Err(source) => return Err(Error::StubDir { path: dir, source }.classify()),
+        }
+
+        if !removed.is_empty() {
+            info!("removed {:?} and everything in it", dir);
+        }
+
+        Ok(removed)
+    }
+
+    /// The `.extra.d` directories in `EFI/Linux` whose UKI no longer exists, such as those left
+    /// behind when a UKI is removed by another tool.
+    pub fn orphaned_extra_dirs(&self) -> Vec<PathBuf> {
+        let mut orphans = Vec::new();
+        for dir in self.uki_dirs() {
+            let mut dirs = self
+                .fs
+                .read_dir(&dir)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|path| {
+                    stub::extra_dir_uki(path).is_some_and(|uki| self.fs.metadata(&uki).is_err())
+                })
+                .filter(|path| self.fs.symlink_metadata(path).is_ok_and(|m| m.is_dir()))
+                .collect::<Vec<_>>();
+
+            dirs.sort();
+            orphans.extend(dirs);
+        }
+
+        orphans
+    }
+
+    /// Remove a file, or a directory and everything in it, without following symlinks.
+    fn remove_tree(&self, path: &Path, removed: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let error = |source| {
+            Error::StubRemove {
+                path: path.to_owned(),
+                source,
+            }
+            .classify()
+        };
+
+        let metadata = self.fs.symlink_metadata(path).map_err(error)?;
+        if metadata.is_dir() {
+            for child in self.fs.read_dir(path).map_err(error)? {
+                self.remove_tree(&child, removed)?;
+            }
+
+            self.fs.remove_dir(path).map_err(error)?;
+        } else {
+            self.fs.remove(path).map_err(error)?;
+        }
+
+        removed.push(path.to_owned());
+        Ok(())
+    }
+
+    /// Write a fresh random seed to `loader/random-seed`, which systemd-boot passes to the kernel
+    /// and refreshes on each boot, returning its path. The seed is only readable by its owner.
+    ///
+    /// As bootctl does, pair this with `EfiVars::ensure_system_token`, so that systems which are
+    /// installed from the same image do not boot with the same seed.
+    pub fn install_random_seed(&self) -> Result<PathBuf, Error> {
+        let path = self.efi_mount.join("loader/random-seed");
+
+        let write = || {
+            let seed = efivars::random_bytes(efivars::RANDOM_SEED_SIZE)?;
+            if let Some(parent) = path.parent() {
+                self.fs.create_dir_all(parent)?;
+            }
+
+            self.write_private_file(&path, &seed)
+        };
+
+        if let Err(source) = write() {
+            return Err(Error::RandomSeedWrite { path, source }.classify());
+        }
+
+        info!("wrote random seed to {:?}", path);
+        Ok(path)
+    }
+
+    /// Install a key set into `loader/keys/<name>`, replacing any keys of the same kinds.
+    ///
+    /// The keys are validated before anything is written, and must include the keys that
+    /// systemd-boot requires.
+    pub fn install_secure_boot_keys(
+        &self,
+        name: &str,
+        keys: &[(KeyKind, &[u8])],
+    ) -> Result<(), Error> {
+        let path = self.keys_path().join(name);
+        let error = |source| Error::Keys {
+            path: path.clone(),
+            source,
+        };
+
+        secure_boot::validate_name(name).map_err(error)?;
+
+        for &kind in &KeyKind::REQUIRED {
+            if !keys.iter().any(|&(k, _)| k == kind) {
+                return Err(error(KeyError::Missing(kind)));
+            }
+        }
+
+        for &(kind, data) in keys {
+            secure_boot::validate_auth(kind, data).map_err(error)?;
+        }
+
+        if let Err(source) = self.fs.create_dir_all(&path) {
+            return Err(Error::KeysWrite { path, source }.classify());
+        }
+
+        for &(kind, data) in keys {
+            let file = path.join(kind.file_name());
+            let result = self.try_io(&file, |contents| {
+                contents.extend_from_slice(data);
+                Ok(())
+            });
+
+            if let Err(source) = result {
+                return Err(Error::KeysWrite { path: file, source }.classify());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn notify(&self, event: ChangeEvent) {
+        for listener in &self.listeners {
+            listener.changed(&event);
+        }
+    }
+
+    /// Fails if writing `bytes` more to the filesystem containing `path` would leave less than
+    /// `min_free_space`, listing the orphaned images of this ESP if they could be pruned there.
+    fn check_space(&self, path: &Path, bytes: u64, prunable: bool) -> Result<(), Error> {
+        let reserve = match self.min_free_space {
+            Some(reserve) if bytes > 0 => reserve,
+            _ => return Ok(()),
+        };
+
+        let available = match self.fs.available_space(path) {
+            Ok(Some(available)) => available,
+            Ok(None) => return Ok(()),
+            Err(why) => {
+                warn!("unable to find the free space of {:?}: {}", path, why);
+                return Ok(());
+            }
+        };
+
+        let needed = bytes.saturating_add(reserve);
+        if available >= needed {
+            return Ok(());
+        }
+
+        let prunable = if prunable {
+            self.orphaned_payloads().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        Err(Error::InsufficientSpace {
+            path: path.to_owned(),
+            needed,
+            available,
+            prunable,
+        })
+    }
+
+    fn report_progress(&self, progress: &Progress) {
+        for listener in &self.progress {
+            listener.progress(progress);
+        }
+    }
+
+    /// Writes a file through the filesystem, running the write hooks around it.
+    ///
+    /// Files on a filesystem which is mounted read-only are refused before anything is written.
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.write_file_with(path, contents, |path| self.fs.write(path, contents))
+    }
+
+    /// Writes a file as `write_file` does, readable and writable only by its owner.
+    fn write_private_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.write_file_with(path, contents, |path| self.fs.write_private(path, contents))
+    }
+
+    fn write_file_with<F: FnOnce(&Path) -> io::Result<()>>(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        write: F,
+    ) -> io::Result<()> {
+        let dir = path.parent().unwrap_or(path);
+        if self.fs.is_read_only(dir).unwrap_or(false) {
+            return Err(io::Error::new(
+                io::ErrorKind::ReadOnlyFilesystem,
+                format!("{:?} is on a filesystem which is mounted read-only", path),
+            ));
+        }
+
+        for hook in &self.hooks {
+            hook.before_write(path, contents)?;
+        }
+
+        write(path)?;
+
+        if self.verify_writes {
+            self.verify_file(path, contents)?;
+        }
+
+        for hook in &self.hooks {
+            hook.after_write(path, contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Syncs a file which was just written, and checks that it reads back as it was written.
+    fn verify_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.fs.sync(path)?;
+
+        if self.fs.read(path)? != contents {
+            warn!("{:?} does not contain what was written to it", path);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} does not contain what was written to it, which may mean that the media is failing",
+                    path
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn try_io<F: FnMut(&mut Vec<u8>) -> io::Result<()>>(
+        &self,
+        path: &Path,
+        mut instructions: F,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+
+        let mut contents = Vec::new();
+        let result = instructions(&mut contents).and_then(|_| self.write_file(path, &contents));
+
+        match result {
+            Ok(()) => info!("wrote {:?} in {:?}", path, start.elapsed()),
+            Err(ref why) => warn!("failed to write {:?}: {}", path, why),
+        }
+
+        result
+    }
+}
+
+/// Loads a `SystemdBootConf` from custom paths.
+#[derive(Debug, Clone)]
+pub struct SystemdBootConfBuilder {
+    efi_mount: PathBuf,
+    entries_path: Option<PathBuf>,
+    loader_path: Option<PathBuf>,
+    symlink_policy: SymlinkPolicy,
+    parse_mode: ParseMode,
+    attach_microcode: bool,
+    grubenv: Option<GrubEnv>,
+    xbootldr_mount: Option<PathBuf>,
+    write_style: WriteStyle,
+    naming_scheme: NamingScheme,
+    host: Host,
+    ownership: Option<Ownership>,
+    min_free_space: Option<u64>,
+    verify_writes: bool,
+    retry: Option<RetryPolicy>,
+    fs: Arc<dyn Filesystem>,
+    hooks: Vec<Arc<dyn WriteHook>>,
+    listeners: Vec<Arc<dyn ChangeListener>>,
+    progress: Vec<Arc<dyn ProgressListener>>,
+}
+
+impl SystemdBootConfBuilder {
+    /// Read entries from this directory instead of `loader/entries` in the EFI mount.
+    pub fn entries_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.entries_path = Some(path.into());
+        self
+    }
+
+    /// Read the loader configuration from this file instead of `loader/loader.conf` in the EFI mount.
+    pub fn loader_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.loader_path = Some(path.into());
+        self
+    }
+
+    /// Choose how symlinks in the entries directory are handled.
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// Choose how strictly the loader conf and entries are parsed.
+    pub fn parse_mode(mut self, mode: ParseMode) -> Self {
+        self.parse_mode = mode;
+        self
+    }
+
+    /// Add the microcode images found on the ESP to entries given to `add_entry`.
+    pub fn attach_microcode(mut self, attach: bool) -> Self {
+        self.attach_microcode = attach;
+        self
+    }
+
+    /// Read entries from `loader/entries` on the XBOOTLDR partition mounted here, rather than
+    /// from the ESP, unless another entries path is given.
+    pub fn xbootldr_mount<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.xbootldr_mount = Some(path.into());
+        self
+    }
+
+    /// Expand the variables of this GRUB environment in entry options, such as `$kernelopts`.
+    pub fn grubenv(mut self, env: GrubEnv) -> Self {
+        self.grubenv = Some(env);
+        self
+    }
+
+    /// Format the files which are written in this style.
+    pub fn write_style(mut self, style: WriteStyle) -> Self {
+        self.write_style = style;
+        self
+    }
+
+    /// Name the entries given to `add_entry` with this scheme.
+    pub fn naming_scheme(mut self, scheme: NamingScheme) -> Self {
+        self.naming_scheme = scheme;
+        self
+    }
+
+    /// Manage entries for the system installed at `root`, rather than the running system.
+    pub fn host_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.host = Host::at(root);
+        self
+    }
+
+    /// Refuse to modify or delete the entries of installations other than this one.
+    pub fn ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = Some(ownership);
+        self
+    }
+
+    /// Fail operations which copy images before they begin, if they would leave less than this
+    /// many bytes free on the filesystem.
+    pub fn min_free_space(mut self, bytes: u64) -> Self {
+        self.min_free_space = Some(bytes);
+        self
+    }
+
+    /// Sync and read back every file after it is written, and fail if its contents differ.
+    ///
+    /// The read may be served from the page cache rather than the device, so this catches
+    /// failures which are reported when syncing, and corruption by the filesystem driver, but not
+    /// every fault of the media.
+    pub fn verify_writes(mut self, verify: bool) -> Self {
+        self.verify_writes = verify;
+        self
+    }
+
+    /// Retry the reads and writes of the filesystem which fail with transient errors, such as
+    /// the occasional `EIO` from marginal flash media.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Perform all file operations through this filesystem, instead of the real one.
+    pub fn filesystem<F: Filesystem + 'static>(mut self, fs: F) -> Self {
+        self.fs = Arc::new(fs);
+        self
+    }
+
+    /// Run this hook around every file that is written.
+    pub fn write_hook<H: WriteHook + 'static>(mut self, hook: H) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Notify this listener of the changes made to the configuration.
+    pub fn listener<L: ChangeListener + 'static>(mut self, listener: L) -> Self {
+        self.listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Report the progress of operations which copy many or large files to this listener.
+    pub fn progress_listener<L: ProgressListener + 'static>(mut self, listener: L) -> Self {
+        self.progress.push(Arc::new(listener));
+        self
+    }
+
+    /// Record every file which is written, and every change which is made, in this audit log.
+    pub fn audit_log(mut self, log: AuditLog) -> Self {
+        let log = Arc::new(log);
+        self.hooks.push(log.clone());
+        self.listeners.push(log);
+        self
+    }
+
+    /// Load the loader configuration and entries from the configured paths.
+    pub fn build(self) -> Result<SystemdBootConf, Error> {
+        let mut manager = self.into_manager();
+        manager.load_conf()?;
+        manager.load_entries().map_err(|why| {
+            // An empty mount point has no entries directory either.
+            let empty = manager
+                .fs
+                .read_dir(&manager.efi_mount)
+                .map_or(true, |files| files.is_empty());
+            if why.kind() == ErrorKind::NotFound && empty {
+                Error::NotMounted(manager.efi_mount.clone())
+            } else {
+                why
+            }
+        })?;
+
+        Ok(manager)
+    }
+
+    /// Construct the configuration without reading anything from disk.
+    pub fn empty(self) -> SystemdBootConf {
+        self.into_manager()
+    }
+
+    /// Create the entries directory and loader configuration if they are missing, then load them.
+    pub fn create(self) -> Result<SystemdBootConf, Error> {
+        let mut manager = self.into_manager();
+
+        info!("initializing loader layout in {:?}", manager.efi_mount);
+        let fs = manager.fs.clone();
+
+        // Never create the layout on the root filesystem in place of the ESP.
+        if let Err(why) = fs.metadata(&manager.efi_mount) {
+            return Err(match why.kind() {
+                io::ErrorKind::NotFound => Error::NotMounted(manager.efi_mount),
+                _ => Error::CreateDir {
+                    path: manager.efi_mount,
+                    source: why,
+                }
+                .classify(),
+            });
+        }
+
+        let create_dir = |path: &Path| {
+            fs.create_dir_all(path).map_err(|source| {
+                Error::CreateDir {
+                    path: path.to_path_buf(),
+                    source,
+                }
+                .classify()
+            })
+        };
+
+        create_dir(&manager.entries_path)?;
+        if let Some(parent) = manager.loader_path.parent() {
+            create_dir(parent)?;
+        }
+
+        if fs.symlink_metadata(&manager.loader_path).is_err() {
+            manager.overwrite_loader_conf()?;
+        }
+
+        // Declare the format of the entries, as bootctl does since systemd 251.
+        let srel_path = manager.entries_srel_path();
+        if fs.symlink_metadata(&srel_path).is_err() {
+            if let Err(source) = manager.write_file(&srel_path, b"type1\n") {
+                return Err(Error::LoaderWrite {
+                    path: srel_path,
+                    source,
+                }
+                .classify());
+            }
+        }
+
+        manager.load_conf()?;
+        manager.load_entries()?;
+
+        Ok(manager)
+    }
+
+    fn into_manager(self) -> SystemdBootConf {
+        let SystemdBootConfBuilder {
+            efi_mount,
+            entries_path,
+            loader_path,
+            symlink_policy,
+            parse_mode,
+            attach_microcode,
+            grubenv,
+            xbootldr_mount,
+            write_style,
+            naming_scheme,
+            host,
+            ownership,
+            min_free_space,
+            verify_writes,
+            retry,
+            mut fs,
+            hooks,
+            listeners,
+            progress,
+        } = self;
+
+        if let Some(policy) = retry {
+            fs = Arc::new(RetryingFilesystem::from_arc(fs, policy));
+        }
+
+        let entries_root = xbootldr_mount.as_ref().unwrap_or(&efi_mount);
+        let entries_path = entries_path.unwrap_or_else(|| entries_root.join("loader/entries"));
+        let loader_path = loader_path.unwrap_or_else(|| efi_mount.join("loader/loader.conf"));
+
+        SystemdBootConf {
+            efi_mount,
+            entries_path,
+            loader_path,
+            entries: Vec::default(),
+            entries_srel: None,
+            loader_conf: LoaderConf::default(),
+            non_utf8_entries: Vec::new(),
+            skipped_entries: Vec::new(),
+            symlink_policy,
+            parse_mode,
+            warnings: Vec::new(),
+            attach_microcode,
+            grubenv,
+            xbootldr_mount,
+            write_style,
+            naming_scheme,
+            host,
+            ownership,
+            min_free_space,
+            verify_writes,
+            fs,
+            hooks,
+            listeners,
+            progress,
+            index: HashMap::new(),
+        }
+    }
+}
+
+/// How symlinked files in the entries directory are handled when loading entries.
+///
+/// Regardless of the policy, writing an entry through a symlink modifies the file that the link
+/// points to, rather than replacing the link.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SymlinkPolicy {
+    /// Load the entry that the link points to.
+    #[default]
+    Follow,
+    /// Ignore symlinked entries.
+    Skip,
+    /// Fail to load the entries with `Error::EntrySymlink`.
+    Error,
+}
+
+/// What the default of the loader conf refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DefaultTarget<'a> {
+    Entry(&'a Entry),
+    /// A unified kernel image in `EFI/Linux`, which is booted without an entry file.
+    Uki(PathBuf),
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DefaultState {
+    #[default]
+    NotDefined,
+    Exists,
+    DoesNotExist,
+}
+
+/// Fetches the kernel command line, and lazily initialize it if it has not been fetched.
+///
+/// The command line is empty without the `proc-cmdline` feature.
+pub fn kernel_cmdline() -> &'static [&'static str] {
+    static CMDLINE_ARGS: OnceCell<Vec<String>> = OnceCell::new();
+    static CMDLINE: OnceCell<Box<[&'static str]>> = OnceCell::new();
+
+    CMDLINE.get_or_init(|| {
+        let args = CMDLINE_ARGS.get_or_init(|| {
+            if cfg!(feature = "proc-cmdline") {
+                cmdline::split(&fs::read_to_string("/proc/cmdline").unwrap_or_default())
+            } else {
+                Vec::new()
+            }
+        });
+
+        args.iter()
+            .map(String::as_str)
+            .collect::<Vec<&'static str>>()
+            .into()
+    })
+}
+
+/// Fails if the entry is protected from removal.
+fn check_unprotected(entry: &Entry) -> Result<(), Error> {
+    match entry.protection() {
+        Some(reason) => {
+            info!("skipping protected entry {}: {}", entry.id, reason);
+            Err(Error::Protected {
+                id: entry.id.clone(),
+                reason: reason.to_owned(),
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Whether a file in the entries directory should be loaded as an entry.
+///
+/// The extension is matched case-insensitively, as the ESP is a FAT filesystem. Hidden files, which
+/// include editor lock files such as `.#foo.conf`, are ignored, as are backups and swap files such
+/// as `foo.conf~` and `.foo.conf.swp`.
+fn is_entry_file_name(name: &str) -> bool {
+    !name.starts_with('.') && name.len() > 5 && name.to_ascii_lowercase().ends_with(".conf")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFilesystem;
+    use crate::fixtures::{EspFixture, EspFixtureBuilder};
+
+    const ENTRY: &str = "title Pop!_OS\nlinux /vmlinuz\n";
+
+    fn load(fs: &MemoryFilesystem) -> SystemdBootConf {
+        SystemdBootConf::builder("/efi")
+            .filesystem(fs.clone())
+            .build()
+            .unwrap()
+    }
+
+    fn id(id: &str) -> EntryId {
+        EntryId::new(id).unwrap()
+    }
+
+    #[test]
+    fn load_fixture() {
+        let fixture = EspFixture::builder()
+            .entries(2)
+            .default_entry("fixture-1")
+            .timeout(5)
+            .build()
+            .unwrap();
+        let conf = fixture.load().unwrap();
+
+        assert_eq!(conf.entries.len(), 2);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("fixture-1"));
+        assert_eq!(conf.loader_conf.timeout, Some(5));
+    }
+
+    #[test]
+    fn default_matches_id_without_boot_counter() {
+        let fs = EspFixtureBuilder::default()
+            .file("loader/entries/pop+3.conf", ENTRY)
+            .file("loader/entries/other.conf", ENTRY)
+            .default_entry("pop")
+            .build_in_memory("/efi")
+            .unwrap();
+        let conf = load(&fs);
+
+        assert_eq!(conf.default_entry_exists(), DefaultState::Exists);
+        assert!(matches!(
+            conf.resolve_default(),
+            Some(DefaultTarget::Entry(entry)) if entry.id == "pop+3"
+        ));
+        assert!(conf.is_default(&conf.entries[conf.position("pop+3").unwrap()]));
+        assert!(!conf.is_default(&conf.entries[conf.position("other").unwrap()]));
+    }
+
+    #[test]
+    fn default_written_without_boot_counter_exists() {
+        let fs = EspFixtureBuilder::default()
+            .file("loader/entries/pop+2-1.conf", ENTRY)
+            .build_in_memory("/efi")
+            .unwrap();
+        let mut conf = load(&fs);
+        assert_eq!(conf.default_entry_exists(), DefaultState::NotDefined);
+
+        conf.loader_conf.default = Some(conf.entries[0].base_id().to_owned());
+        conf.overwrite_loader_conf().unwrap();
+
+        let conf = load(&fs);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("pop"));
+        assert_eq!(conf.default_entry_exists(), DefaultState::Exists);
+        assert!(conf.is_default(&conf.entries[0]));
+    }
+
+    #[test]
+    fn set_boot_tries_renames_entry() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/loader/entries/a.conf", ENTRY);
+        fs.add_file("/efi/loader/entries/b.conf", ENTRY);
+        let mut conf = load(&fs);
+
+        conf.set_boot_tries("a", 3).unwrap();
+        assert_eq!(conf.entries[0].id, "a+3");
+        assert!(fs.read_to_string("/efi/loader/entries/a+3.conf").is_some());
+        assert!(fs.read_to_string("/efi/loader/entries/a.conf").is_none());
+
+        // The counter is replaced, rather than appended to.
+        conf.set_boot_tries("a+3", 5).unwrap();
+        let entry = &conf.entries[0];
+        assert_eq!(entry.id, "a+5");
+        assert_eq!(entry.base_id(), "a");
+        assert_eq!(
+            entry.boot_counter(),
+            Some(BootCounter {
+                tries_left: 5,
+                tries_done: 0
+            })
+        );
+        assert!(fs.read_to_string("/efi/loader/entries/a+3.conf").is_none());
+        assert_eq!(
+            fs.read_to_string("/efi/loader/entries/a+5.conf").as_deref(),
+            Some(ENTRY)
+        );
+
+        assert!(conf.set_boot_tries("c", 3).is_err());
+    }
+
+    #[test]
+    fn write_and_remove_entries_in_memory() {
+        let fs = EspFixtureBuilder::default()
+            .entries(1)
+            .broken_entry("broken")
+            .build_in_memory("/efi")
+            .unwrap();
+        fs.remove(Path::new("/efi/loader/entries/broken.conf"))
+            .unwrap();
+        let mut conf = load(&fs);
+        assert_eq!(conf.entries.len(), 1);
+
+        let entry = Entry {
+            id: "added".into(),
+            title: "Added".into(),
+            linux: "/EFI/fixture/vmlinuz-6.0.0.efi".into(),
+            options: vec!["quiet".into()],
+            ..Entry::default()
+        };
+        conf.add_entry(entry).unwrap();
+        conf.overwrite_entry_conf(&id("added")).unwrap();
+        assert_eq!(
+            fs.read_to_string("/efi/loader/entries/added.conf")
+                .as_deref(),
+            Some("title Added\nlinux /EFI/fixture/vmlinuz-6.0.0.efi\noptions quiet\n")
+        );
+
+        let reloaded = load(&fs);
+        assert_eq!(reloaded.entries.len(), 2);
+        assert!(reloaded.get(&id("added")).is_some());
+
+        conf.delete_entry("fixture-0").unwrap();
+        assert!(fs
+            .read_to_string("/efi/loader/entries/fixture-0.conf")
+            .is_none());
+        assert_eq!(load(&fs).entries.len(), 1);
+        assert_eq!(
+            conf.delete_entry("fixture-0").unwrap_err().kind(),
+            ErrorKind::EntryNotFound
+        );
+    }
+
+    #[test]
+    fn entry_file_names() {
+        for name in ["pop.conf", "pop.CONF", "pop+3-1.Conf", "a.b.conf"] {
+            assert!(is_entry_file_name(name), "{}", name);
+        }
+
+        for name in [
+            ".conf",
+            ".pop.conf",
+            ".#pop.conf",
+            "pop.conf~",
+            ".pop.conf.swp",
+            "pop",
+        ] {
+            assert!(!is_entry_file_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn load_skips_other_files() {
+        let fs = EspFixtureBuilder::default()
+            .entries(1)
+            .file("loader/entries/.fixture-0.conf.swp", "swap")
+            .file("loader/entries/fixture-0.conf~", "backup")
+            .file("loader/entries/README", "readme")
+            .build_in_memory("/efi")
+            .unwrap();
+        fs.create_dir_all(Path::new("/efi/loader/entries/dir.conf"))
+            .unwrap();
+
+        let conf = load(&fs);
+        assert_eq!(conf.entries.len(), 1);
+        assert_eq!(conf.entries[0].id, "fixture-0");
+    }
+
+    #[test]
+    fn gc_dry_run() {
+        let fs = EspFixtureBuilder::default()
+            .entries(1)
+            .file("EFI/fixture/vmlinuz-5.0.0.efi", "old")
+            .build_in_memory("/efi")
+            .unwrap();
+        let conf = load(&fs);
+
+        let options = GcOptions {
+            dry_run: true,
+            ..GcOptions::default()
+        };
+        let report = conf.gc(&options).unwrap();
+        let orphan = Path::new("/efi/EFI/fixture/vmlinuz-5.0.0.efi");
+        assert_eq!(
+            report.files,
+            vec![OrphanedFile {
+                path: orphan.to_path_buf(),
+                size: 3,
+            }]
+        );
+        assert!(fs.metadata(orphan).is_ok());
+    }
+
+    #[test]
+    fn gc_refuses_with_unloaded_entries() {
+        let fs = EspFixtureBuilder::default()
+            .entries(1)
+            .file("EFI/fixture/vmlinuz-5.0.0.efi", "old")
+            .build_in_memory("/efi")
+            .unwrap();
+        fs.add_file(
+            "/efi/loader/old.conf",
+            "title Old\nlinux /EFI/fixture/vmlinuz-5.0.0.efi\n",
+        );
+        fs.add_symlink("/efi/loader/entries/old.conf", "../old.conf");
+
+        let conf = SystemdBootConf::builder("/efi")
+            .filesystem(fs.clone())
+            .symlink_policy(SymlinkPolicy::Skip)
+            .build()
+            .unwrap();
+        assert_eq!(conf.entries.len(), 1);
+
+        let why = conf.gc(&GcOptions::default()).unwrap_err();
+        assert!(matches!(why, Error::UnloadedEntries(ref paths) if paths.len() == 1));
+        assert!(fs
+            .metadata(Path::new("/efi/EFI/fixture/vmlinuz-5.0.0.efi"))
+            .is_ok());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            fs.remove(Path::new("/efi/loader/entries/old.conf"))
+                .unwrap();
+            let name = std::ffi::OsStr::from_bytes(b"old-\xff.conf");
+            fs.add_file(Path::new("/efi/loader/entries").join(name), "title Old\n");
+
+            let conf = load(&fs);
+            assert_eq!(conf.non_utf8_entries.len(), 1);
+            assert!(conf.orphaned_payloads().is_err());
+        }
+    }
+
+    #[test]
+    fn entries_keep_their_file_names() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/loader/entries/pop.CONF", ENTRY);
+        fs.add_file("/efi/loader/entries/old.Conf", ENTRY);
+        let mut conf = load(&fs);
+
+        let entry = conf.get_mut(&id("pop")).unwrap();
+        assert_eq!(entry.file_name.as_deref(), Some("pop.CONF"));
+        entry.set_title("Pop!_OS 24.04").unwrap();
+        conf.overwrite_entry_conf(&id("pop")).unwrap();
+
+        let written = fs.read_to_string("/efi/loader/entries/pop.CONF").unwrap();
+        assert!(written.contains("title Pop!_OS 24.04"));
+        assert!(fs.read_to_string("/efi/loader/entries/pop.conf").is_none());
+
+        conf.set_boot_tries("pop", 3).unwrap();
+        assert!(fs.read_to_string("/efi/loader/entries/pop.CONF").is_none());
+        assert!(fs
+            .read_to_string("/efi/loader/entries/pop+3.conf")
+            .is_some());
+
+        conf.delete_entry("old").unwrap();
+        assert_eq!(
+            fs.files().keys().collect::<Vec<_>>(),
+            vec![Path::new("/efi/loader/entries/pop+3.conf")]
+        );
+    }
+
+    #[test]
+    fn new_id_is_written_to_new_file() {
+        let fs = MemoryFilesystem::new();
+        fs.add_file("/efi/loader/entries/pop.CONF", ENTRY);
+        let mut conf = load(&fs);
+
+        let entry = conf.get_mut(&id("pop")).unwrap();
+        entry.set_id("pop-old").unwrap();
+        assert_eq!(entry.conf_file_name(), "pop-old.conf");
+    }
 }
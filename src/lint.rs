@@ -0,0 +1,423 @@
+//! Checks for configurations which systemd-boot accepts, but which are likely mistakes.
+
+use crate::esp_path::EspPath;
+use crate::loader::MenuMode;
+use crate::SystemdBootConf;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// Kernel parameters which take effect once, so that giving them different values is a conflict.
+const SINGLE_VALUED_OPTIONS: &[&str] = &[
+    "init",
+    "resume",
+    "resume_offset",
+    "root",
+    "rootflags",
+    "rootfstype",
+];
+
+/// A likely mistake in the configuration, found by `SystemdBootConf::lint`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Lint {
+    /// Entries whose ids differ only by case, whose files are the same file on the
+    /// case-insensitive FAT filesystem of the ESP.
+    CaseCollision { entries: Vec<String> },
+    /// The options of an entry differ from the command line that kernel-install gives to new
+    /// entries, found by `SystemdBootConf::check_cmdline`.
+    CmdlineDrift {
+        entry: String,
+        /// Options of the entry which the command line lacks.
+        extra: Vec<String>,
+        /// Options of the command line which the entry lacks.
+        missing: Vec<String>,
+    },
+    /// Kernel options in an entry which contradict each other, such as `ro` and `rw`, or two
+    /// different values for `root=`.
+    ConflictingOptions { entry: String, options: Vec<String> },
+    /// An option which is given more than once in an entry, which `Entry::dedup_options`
+    /// removes.
+    DuplicateOption { entry: String, option: String },
+    /// The machine id of an entry is not formatted as a machine id, so kernel-install will not
+    /// recognize the entry as belonging to any installation.
+    InvalidMachineId { entry: String, machine_id: String },
+    /// Entries which are sorted by version against each other, as they share a sort key and
+    /// machine id, have versions in different schemes, such as `6.8.0` and `20240101`, so
+    /// that the entry which sorts as the newest may not be.
+    InconsistentVersions { entries: Vec<String> },
+    /// The options of an entry do not unlock a LUKS volume which the system needs, found by
+    /// `SystemdBootConf::check_luks`.
+    LuksNotUnlocked { entry: String, uuid: String },
+    /// The machine id of an entry differs from that of the system, found by
+    /// `SystemdBootConf::check_machine_id`.
+    MachineIdMismatch {
+        entry: String,
+        machine_id: String,
+        expected: String,
+    },
+    /// The boot menu is hidden, which locks the user out of recovery options.
+    MenuUnreachable(MenuUnreachable),
+    /// An entry has no version, while the entries it is sorted against by version do, so it
+    /// sorts as the oldest of them.
+    MissingVersion { entry: String },
+    /// The `linux` image of an entry is not an EFI-stub kernel, so systemd-boot cannot boot it.
+    NotEfiStub { entry: String, path: String },
+    /// A path in an entry is a path on the host, such as `/boot/vmlinuz`, rather than a path
+    /// within the partition that systemd-boot reads the entry from.
+    PathOnHost {
+        entry: String,
+        key: &'static str,
+        path: String,
+    },
+    /// A path in an entry contains a `..` component, which systemd-boot does not resolve.
+    PathEscape {
+        entry: String,
+        key: &'static str,
+        path: String,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lint::CaseCollision { entries } => write!(
+                f,
+                "entries {} differ only by case, which the ESP does not distinguish",
+                entries.join(", ")
+            ),
+            Lint::CmdlineDrift {
+                entry,
+                extra,
+                missing,
+            } => {
+                write!(
+                    f,
+                    "entry {}: options differ from the kernel command line",
+                    entry
+                )?;
+                if !extra.is_empty() {
+                    write!(f, "; extra: {}", extra.join(" "))?;
+                }
+
+                if !missing.is_empty() {
+                    write!(f, "; missing: {}", missing.join(" "))?;
+                }
+
+                Ok(())
+            }
+            Lint::ConflictingOptions { entry, options } => write!(
+                f,
+                "entry {}: options conflict with each other: {}",
+                entry,
+                options.join(" ")
+            ),
+            Lint::DuplicateOption { entry, option } => {
+                write!(
+                    f,
+                    "entry {}: option {} is given more than once",
+                    entry, option
+                )
+            }
+            Lint::InconsistentVersions { entries } => write!(
+                f,
+                "entries {} have versions which do not sort consistently",
+                entries.join(", ")
+            ),
+            Lint::InvalidMachineId { entry, machine_id } => write!(
+                f,
+                "entry {}: machine-id {} is not 32 lowercase hexadecimal characters",
+                entry, machine_id
+            ),
+            Lint::LuksNotUnlocked { entry, uuid } => write!(
+                f,
+                "entry {}: options do not unlock the LUKS volume {}",
+                entry, uuid
+            ),
+            Lint::MachineIdMismatch {
+                entry,
+                machine_id,
+                expected,
+            } => write!(
+                f,
+                "entry {}: machine-id {} does not match the machine id of the system, {}",
+                entry, machine_id, expected
+            ),
+            Lint::MenuUnreachable(reason) => write!(f, "the boot menu is unreachable: {}", reason),
+            Lint::MissingVersion { entry } => write!(
+                f,
+                "entry {}: version is missing, while the entries sorted with it have one",
+                entry
+            ),
+            Lint::NotEfiStub { entry, path } => write!(
+                f,
+                "entry {}: linux image {} is not an EFI-stub kernel, which systemd-boot cannot \
+                 boot; use a kernel built with CONFIG_EFI_STUB",
+                entry, path
+            ),
+            Lint::PathOnHost { entry, key, path } => write!(
+                f,
+                "entry {}: {} path {} is on the host, where systemd-boot cannot read it",
+                entry, key, path
+            ),
+            Lint::PathEscape { entry, key, path } => write!(
+                f,
+                "entry {}: {} path {} escapes the partition with '..'",
+                entry, key, path
+            ),
+        }
+    }
+}
+
+/// Why a hidden boot menu leaves the user without a way to recover the system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MenuUnreachable {
+    /// `timeout menu-disabled` prevents the menu from ever being shown.
+    Disabled,
+    /// The kernel command line cannot be edited, such as to boot into a rescue target.
+    EditorDisabled,
+    /// There is no other entry to boot if the default fails.
+    NoAlternateEntry,
+}
+
+impl fmt::Display for MenuUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            MenuUnreachable::Disabled => "the menu is disabled, and can never be shown",
+            MenuUnreachable::EditorDisabled => {
+                "the menu is hidden, and the editor is disabled as well"
+            }
+            MenuUnreachable::NoAlternateEntry => {
+                "the menu is hidden, and there is no other entry to boot"
+            }
+        })
+    }
+}
+
+/// Flags a hidden menu which leaves no way to recover the system, since few users know that
+/// holding a key while the loader starts shows it.
+pub(crate) fn menu(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    let loader = &conf.loader_conf;
+    let hidden = match loader.menu {
+        Some(MenuMode::Disabled) => {
+            lints.push(Lint::MenuUnreachable(MenuUnreachable::Disabled));
+            return;
+        }
+        Some(MenuMode::Hidden) => true,
+        Some(MenuMode::Force) => false,
+        // systemd-boot hides the menu unless a timeout is configured.
+        None => loader.timeout.unwrap_or(0) == 0,
+    };
+
+    if !hidden {
+        return;
+    }
+
+    let bootable = conf.entries.iter().filter(|e| !e.is_foreign()).count() + conf.ukis().len();
+    if bootable < 2 {
+        lints.push(Lint::MenuUnreachable(MenuUnreachable::NoAlternateEntry));
+    }
+
+    if loader.editor == Some(false) {
+        lints.push(Lint::MenuUnreachable(MenuUnreachable::EditorDisabled));
+    }
+}
+
+/// Flags Linux entries whose kernels cannot be executed by systemd-boot. Images which are
+/// missing or unreadable are left to other checks.
+pub(crate) fn efi_stubs(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    for entry in &conf.entries {
+        if let Ok(Some(false)) = conf.is_efi_stub(entry) {
+            lints.push(Lint::NotEfiStub {
+                entry: entry.id.clone(),
+                path: entry.linux.clone(),
+            });
+        }
+    }
+}
+
+/// Flags paths in entries which systemd-boot cannot read, as they are not within the ESP or
+/// XBOOTLDR partition that the entry is read from.
+pub(crate) fn paths(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    let root = conf.entry_root();
+    let mounts: Vec<&Path> = Some(conf.efi_mount.as_path())
+        .into_iter()
+        .chain(conf.xbootldr_mount.as_deref())
+        .collect();
+
+    for entry in &conf.entries {
+        let paths = Some(("linux", &entry.linux))
+            .filter(|(_, linux)| !linux.is_empty())
+            .into_iter()
+            .chain(entry.efi.iter().map(|efi| ("efi", efi)))
+            .chain(entry.initrd.iter().map(|initrd| ("initrd", initrd)))
+            .chain(entry.devicetree.iter().map(|dt| ("devicetree", dt)));
+
+        for (key, path) in paths {
+            let lint = if path.split(['/', '\\']).any(|component| component == "..") {
+                Lint::PathEscape {
+                    entry: entry.id.clone(),
+                    key,
+                    path: path.clone(),
+                }
+            } else {
+                let fs = conf.filesystem();
+                let host = Path::new(path);
+                let missing = fs.metadata(&EspPath::new(path).to_path(root)).is_err();
+                let on_host = mounts.iter().any(|mount| host.starts_with(mount))
+                    || (host.is_absolute() && fs.metadata(host).is_ok());
+
+                if !(missing && on_host) {
+                    continue;
+                }
+
+                Lint::PathOnHost {
+                    entry: entry.id.clone(),
+                    key,
+                    path: path.clone(),
+                }
+            };
+
+            lints.push(lint);
+        }
+    }
+}
+
+/// Flags kernel options which are repeated, or which conflict with each other.
+pub(crate) fn options(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    for entry in &conf.entries {
+        let options = conf.effective_options(entry);
+
+        let mut seen = HashSet::new();
+        let mut reported = HashSet::new();
+        for option in &options {
+            if !seen.insert(option) && reported.insert(option) {
+                lints.push(Lint::DuplicateOption {
+                    entry: entry.id.clone(),
+                    option: option.clone(),
+                });
+            }
+        }
+
+        let mut conflict = |conflicting: Vec<String>| {
+            lints.push(Lint::ConflictingOptions {
+                entry: entry.id.clone(),
+                options: conflicting,
+            })
+        };
+
+        let has = |name: &str| options.iter().any(|option| option == name);
+        if has("ro") && has("rw") {
+            conflict(vec!["ro".to_owned(), "rw".to_owned()]);
+        }
+
+        for key in SINGLE_VALUED_OPTIONS {
+            let mut values: Vec<String> = Vec::new();
+            for option in &options {
+                let matches = option
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.starts_with('='));
+                if matches && !values.contains(option) {
+                    values.push(option.clone());
+                }
+            }
+
+            if values.len() > 1 {
+                conflict(values);
+            }
+        }
+    }
+}
+
+/// Flags machine ids which are not formatted as machine ids.
+pub(crate) fn machine_ids(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    for entry in &conf.entries {
+        if let Some(ref machine_id) = entry.machine_id {
+            if !crate::machine_id::is_valid(machine_id) {
+                lints.push(Lint::InvalidMachineId {
+                    entry: entry.id.clone(),
+                    machine_id: machine_id.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Flags versions which will not sort sensibly against the entries that systemd-boot sorts them
+/// with: those which share a sort key and machine id.
+pub(crate) fn versions(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for entry in &conf.entries {
+        if entry.sort_key.is_some() || entry.machine_id.is_some() {
+            groups
+                .entry((&entry.sort_key, &entry.machine_id))
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    for entries in groups.values().filter(|entries| entries.len() > 1) {
+        let versioned: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| Some((entry, entry.version.as_deref()?)))
+            .collect();
+
+        if versioned.is_empty() {
+            continue;
+        }
+
+        for entry in entries.iter().filter(|entry| entry.version.is_none()) {
+            lints.push(Lint::MissingVersion {
+                entry: entry.id.clone(),
+            });
+        }
+
+        let scheme = version_scheme(versioned[0].1);
+        if versioned
+            .iter()
+            .any(|(_, version)| version_scheme(version) != scheme)
+        {
+            let mut ids: Vec<String> = versioned.iter().map(|(e, _)| e.id.clone()).collect();
+            ids.sort();
+            lints.push(Lint::InconsistentVersions { entries: ids });
+        }
+    }
+}
+
+/// A coarse classification of versions, which tells apart those that will not sort sensibly
+/// against each other.
+#[derive(PartialEq)]
+enum VersionScheme {
+    /// Starts with a name, such as `linux-6.8.0`.
+    Named,
+    /// Starts with a short number, such as the major version of `6.8.0`.
+    Numbered,
+    /// Starts with a date or other long number, such as `20240101`.
+    Dated,
+}
+
+fn version_scheme(version: &str) -> VersionScheme {
+    match version.bytes().take_while(u8::is_ascii_digit).count() {
+        0 => VersionScheme::Named,
+        1..=7 => VersionScheme::Numbered,
+        _ => VersionScheme::Dated,
+    }
+}
+
+/// Flags entries whose ids differ only by case.
+pub(crate) fn case_collisions(conf: &SystemdBootConf, lints: &mut Vec<Lint>) {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in &conf.entries {
+        groups
+            .entry(entry.id.to_lowercase())
+            .or_default()
+            .push(entry.id.clone());
+    }
+
+    for (_, mut entries) in groups {
+        if entries.len() > 1 {
+            entries.sort();
+            lints.push(Lint::CaseCollision { entries });
+        }
+    }
+}
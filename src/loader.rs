@@ -1,66 +1,341 @@
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use crate::id::EntryId;
+use crate::parse::{tolerate, ParseMode, ParseWarning, MAX_CONF_SIZE};
+use crate::ErrorKind;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
 use std::path::Path;
+use std::str;
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum LoaderError {
+    #[error("{} was defined more than once", _0)]
+    DuplicateKey(String),
+    #[error("editor has a value ({}) which is not a boolean", _0)]
+    InvalidEditor(String),
+    #[error("secure-boot-enroll has an unknown value ({})", _0)]
+    InvalidSecureBootEnroll(String),
     #[error("error reading line in loader conf")]
     Line(#[source] io::Error),
     #[error("loader conf is not a file")]
     NotAFile,
     #[error("default was defined without a value")]
     NoValueForDefault,
+    #[error("editor was defined without a value")]
+    NoValueForEditor,
+    #[error("secure-boot-enroll was defined without a value")]
+    NoValueForSecureBootEnroll,
     #[error("timeout was defined without a value")]
     NoValueForTimeout,
     #[error("error opening loader file")]
     Open(#[source] io::Error),
     #[error("timeout was defined with a value ({}) which is not a number", _0)]
     TimeoutNaN(String),
+    #[error(
+        "loader conf is {} bytes, which is larger than a loader conf may be",
+        _0
+    )]
+    TooLarge(usize),
 }
 
-#[derive(Debug, Default, Clone)]
+impl LoaderError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            LoaderError::Line(why) | LoaderError::Open(why) => ErrorKind::from_io(why),
+            _ => ErrorKind::InvalidData,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// Whether systemd-boot enrolls the secure boot keys in `loader/keys`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SecureBootEnroll {
+    /// Never offer to enroll keys.
+    Off,
+    /// Offer key sets as entries in the menu.
+    Manual,
+    /// Enroll keys automatically, if the machine is known to be safe to enroll on.
+    IfSafe,
+    /// Enroll keys automatically, even where it may brick the machine.
+    Force,
+}
+
+impl SecureBootEnroll {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecureBootEnroll::Off => "off",
+            SecureBootEnroll::Manual => "manual",
+            SecureBootEnroll::IfSafe => "if-safe",
+            SecureBootEnroll::Force => "force",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(SecureBootEnroll::Off),
+            "manual" => Some(SecureBootEnroll::Manual),
+            "if-safe" => Some(SecureBootEnroll::IfSafe),
+            "force" => Some(SecureBootEnroll::Force),
+            _ => None,
+        }
+    }
+}
+
+/// How the menu is shown, when `timeout` is given a keyword rather than a number of seconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MenuMode {
+    /// The menu is shown until a key is pressed.
+    Force,
+    /// The menu is only shown if a key is pressed while the loader starts, as with a timeout of 0.
+    Hidden,
+    /// The menu is never shown.
+    Disabled,
+}
+
+impl MenuMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MenuMode::Force => "menu-force",
+            MenuMode::Hidden => "menu-hidden",
+            MenuMode::Disabled => "menu-disabled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "menu-force" => Some(MenuMode::Force),
+            "menu-hidden" => Some(MenuMode::Hidden),
+            "menu-disabled" => Some(MenuMode::Disabled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct LoaderConf {
-    pub default: Option<Box<str>>,
+    pub default: Option<String>,
+    /// Whether the kernel command line may be edited from the menu, which it may by default.
+    pub editor: Option<bool>,
+    /// A keyword given as the timeout, which takes precedence over a number of seconds.
+    pub menu: Option<MenuMode>,
+    /// Keys which are not modelled above, such as `console-mode` and `beep`, with their values,
+    /// in the order they appear. They are written back after the others.
+    pub other_keys: Vec<(String, String)>,
+    pub secure_boot_enroll: Option<SecureBootEnroll>,
     pub timeout: Option<u32>,
 }
 
 impl LoaderConf {
+    /// The default as an entry id, unless it is unset, or is a pattern such as `pop-*`.
+    pub fn default_id(&self) -> Option<EntryId> {
+        let default = self.default.as_deref()?;
+        if default.contains(['*', '?', '[']) {
+            return None;
+        }
+
+        EntryId::new(default).ok()
+    }
+
+    /// Make the entry with this id the default.
+    pub fn set_default_id(&mut self, id: EntryId) {
+        self.default = Some(id.into_string());
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, LoaderError> {
+        Self::from_path_with(path, ParseMode::Strict, &mut Vec::new())
+    }
+
+    /// Parse the loader conf with the given parse mode, appending any tolerated problems to
+    /// `warnings`.
+    pub fn from_path_with<P: AsRef<Path>>(
+        path: P,
+        mode: ParseMode,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, LoaderError> {
         let path = path.as_ref();
 
-        let mut loader = LoaderConf::default();
         if !path.exists() {
-            return Ok(loader);
+            return Ok(LoaderConf::default());
         }
 
         if !path.is_file() {
             return Err(LoaderError::NotAFile);
         }
 
-        let file = File::open(path).map_err(LoaderError::Open)?;
+        let contents = fs::read(path).map_err(LoaderError::Open)?;
+        Self::parse_file(path, &contents, mode, warnings)
+    }
+
+    /// Parse the contents of a loader conf strictly, such as one received over the network.
+    ///
+    /// Malformed input of any kind is reported as an error, rather than causing a panic, and
+    /// input larger than `parse::MAX_CONF_SIZE` is refused before it is parsed.
+    pub fn parse(contents: &[u8]) -> Result<Self, LoaderError> {
+        Self::parse_file(Path::new(""), contents, ParseMode::Strict, &mut Vec::new())
+    }
+
+    /// Parse the contents of a loader conf, where `path` is only used to report warnings.
+    pub(crate) fn parse_file(
+        path: &Path,
+        contents: &[u8],
+        mode: ParseMode,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Self, LoaderError> {
+        if contents.len() > MAX_CONF_SIZE {
+            return Err(LoaderError::TooLarge(contents.len()));
+        }
+
+        let contents = str::from_utf8(contents)
+            .map_err(|why| LoaderError::Line(io::Error::new(io::ErrorKind::InvalidData, why)))?;
+
+        let mut loader = LoaderConf::default();
 
-        for line in BufReader::new(file).lines() {
-            let line = line.map_err(LoaderError::Line)?;
+        let mut issue =
+            |line: Option<usize>, error: LoaderError| tolerate(mode, warnings, path, line, error);
+
+        let mut seen = HashSet::new();
+        for (number, line) in contents.lines().enumerate() {
+            let number = Some(number + 1);
+            let line = crate::format::clean_line(line).trim_start();
             let mut fields = line.split_whitespace();
-            match fields.next() {
+            // Keys are matched case-insensitively, as systemd-boot does.
+            let key = fields.next().map(str::to_ascii_lowercase);
+
+            if let Some(key) = key.as_deref() {
+                if !key.starts_with('#') && !seen.insert(key.to_owned()) {
+                    issue(number, LoaderError::DuplicateKey(key.to_owned()))?;
+                }
+            }
+
+            match key.as_deref() {
                 Some("default") => match fields.next() {
-                    Some(default) => loader.default = Some(default.into()),
-                    None => return Err(LoaderError::NoValueForDefault),
+                    Some(default) => loader.default = Some(default.to_owned()),
+                    None => issue(number, LoaderError::NoValueForDefault)?,
+                },
+                Some("editor") => match fields.next() {
+                    Some(value) => match parse_bool(value) {
+                        Some(value) => loader.editor = Some(value),
+                        None => issue(number, LoaderError::InvalidEditor(value.to_owned()))?,
+                    },
+                    None => issue(number, LoaderError::NoValueForEditor)?,
+                },
+                Some("secure-boot-enroll") => match fields.next() {
+                    Some(value) => match SecureBootEnroll::parse(value) {
+                        Some(value) => loader.secure_boot_enroll = Some(value),
+                        None => issue(
+                            number,
+                            LoaderError::InvalidSecureBootEnroll(value.to_owned()),
+                        )?,
+                    },
+                    None => issue(number, LoaderError::NoValueForSecureBootEnroll)?,
                 },
                 Some("timeout") => match fields.next() {
                     Some(timeout) => {
                         if let Ok(timeout) = timeout.parse::<u32>() {
                             loader.timeout = Some(timeout);
+                        } else if let Some(menu) = MenuMode::parse(timeout) {
+                            loader.menu = Some(menu);
                         } else {
-                            return Err(LoaderError::TimeoutNaN(timeout.into()));
+                            issue(number, LoaderError::TimeoutNaN(timeout.into()))?;
                         }
                     }
-                    None => return Err(LoaderError::NoValueForTimeout),
+                    None => issue(number, LoaderError::NoValueForTimeout)?,
                 },
-                _ => (),
+                Some(key) if key.starts_with('#') => (),
+                Some(key) => {
+                    let value = line[key.len()..].trim();
+                    loader.other_keys.push((key.to_owned(), value.to_owned()));
+                }
+                None => (),
             }
         }
 
         Ok(loader)
     }
 }
+
+/// Parses a boolean as systemd-boot does.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "yes" | "y" | "true" | "t" | "on" => Some(true),
+        "0" | "no" | "n" | "false" | "f" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use crate::style::WriteStyle;
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        let oversized = vec![b'#'; MAX_CONF_SIZE + 1];
+        assert!(matches!(
+            LoaderConf::parse(&oversized),
+            Err(LoaderError::TooLarge(_))
+        ));
+
+        let corpus: &[&[u8]] = &[
+            b"default \xff\xfe\n",
+            b"\xef\xbb",
+            b"timeout\n",
+            b"timeout -1\n",
+            b"timeout 99999999999999999999\n",
+            b"editor maybe\n",
+            b"default a\ndefault b\n",
+        ];
+
+        for contents in corpus {
+            assert!(LoaderConf::parse(contents).is_err(), "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn crlf_bom_and_case() {
+        let conf =
+            LoaderConf::parse(b"\xef\xbb\xbfDefault pop\r\nTIMEOUT 5\r\nEditor no\r\n").unwrap();
+        assert_eq!(conf.default.as_deref(), Some("pop"));
+        assert_eq!(conf.timeout, Some(5));
+        assert_eq!(conf.editor, Some(false));
+        assert!(conf.other_keys.is_empty());
+
+        let duplicate = LoaderConf::parse(b"timeout 5\nTimeout 3\n");
+        assert!(matches!(duplicate, Err(LoaderError::DuplicateKey(ref key)) if key == "timeout"));
+    }
+
+    #[test]
+    fn menu_keywords() {
+        let conf = LoaderConf::parse(b"timeout menu-force\n").unwrap();
+        assert_eq!(conf.menu, Some(MenuMode::Force));
+        assert_eq!(conf.timeout, None);
+        assert!(LoaderConf::parse(b"timeout soon\n").is_err());
+    }
+
+    #[test]
+    fn unmodelled_keys_are_kept() {
+        let contents = "default pop\n# a comment\nconsole-mode max\ntimeout 5\nauto-entries no\n\
+            auto-firmware yes\nbeep yes\nreboot-for-bitlocker yes\nrandom-seed-mode always\n";
+        let conf = LoaderConf::parse(contents.as_bytes()).unwrap();
+        assert_eq!(conf.other_keys.len(), 6);
+        assert_eq!(
+            conf.other_keys[0],
+            ("console-mode".to_owned(), "max".to_owned())
+        );
+
+        let written = format::loader_conf(&conf, &WriteStyle::default());
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "default pop\ntimeout 5\nconsole-mode max\nauto-entries no\nauto-firmware yes\n\
+             beep yes\nreboot-for-bitlocker yes\nrandom-seed-mode always\n"
+        );
+    }
+}
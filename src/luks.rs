@@ -0,0 +1,262 @@
+//! Generating and checking the kernel options which unlock LUKS volumes in the initrd, from
+//! `/etc/crypttab` or from the header of a volume.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// The magic number at the start of LUKS1 and LUKS2 headers.
+const LUKS_MAGIC: &[u8] = b"LUKS\xba\xbe";
+
+/// The offset of the UUID in LUKS1 and LUKS2 headers, which is a NUL-padded string of 40 bytes.
+const UUID_OFFSET: usize = 168;
+const UUID_LEN: usize = 40;
+
+/// Options of `/etc/crypttab` which do not apply to systemd-cryptsetup, or which are implied.
+const CRYPTTAB_ONLY_OPTIONS: &[&str] = &["luks", "initramfs", "keyscript", "none"];
+
+/// A line of `/etc/crypttab`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CrypttabEntry {
+    /// The name of the unlocked volume, as in `/dev/mapper/<name>`.
+    pub name: String,
+    /// The encrypted device, such as `UUID=<uuid>` or `/dev/nvme0n1p3`.
+    pub device: String,
+    /// The key file, which is `None` for `none` or `-`, where a passphrase is asked for.
+    pub key_file: Option<String>,
+    pub options: Vec<String>,
+}
+
+impl CrypttabEntry {
+    /// The UUID of the encrypted device, if it is given as `UUID=` or `/dev/disk/by-uuid/`.
+    pub fn uuid(&self) -> Option<String> {
+        device_uuid(&self.device)
+    }
+
+    /// Whether the volume is unlocked with LUKS, rather than as a plain, TrueCrypt, or BitLocker
+    /// volume.
+    pub fn is_luks(&self) -> bool {
+        !self.options.iter().any(|option| {
+            matches!(option.as_str(), "plain" | "tcrypt" | "bitlk") || option.starts_with("plain=")
+        })
+    }
+
+    /// The volume to unlock in the initrd, if it is a LUKS volume with a UUID.
+    ///
+    /// Its options are those of the line, except for those which only Debian's cryptsetup
+    /// scripts understand, such as `initramfs` and `keyscript=`.
+    pub fn volume(&self) -> Option<LuksVolume> {
+        if !self.is_luks() {
+            return None;
+        }
+
+        let options = self
+            .options
+            .iter()
+            .filter(|option| {
+                let key = option
+                    .split_once('=')
+                    .map_or(option.as_str(), |(key, _)| key);
+                !CRYPTTAB_ONLY_OPTIONS.contains(&key)
+            })
+            .cloned()
+            .collect();
+
+        Some(LuksVolume {
+            uuid: self.uuid()?,
+            name: Some(self.name.clone()),
+            options,
+        })
+    }
+}
+
+/// Parse `/etc/crypttab`, skipping comments and malformed lines.
+pub fn parse_crypttab(contents: &str) -> Vec<CrypttabEntry> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_owned();
+            let device = fields.next()?.to_owned();
+            let key_file = fields
+                .next()
+                .filter(|key| *key != "none" && *key != "-")
+                .map(str::to_owned);
+            let options = fields
+                .next()
+                .map(|options| {
+                    options
+                        .split(',')
+                        .filter(|option| !option.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Some(CrypttabEntry {
+                name,
+                device,
+                key_file,
+                options,
+            })
+        })
+        .collect()
+}
+
+/// Read `/etc/crypttab` of the running system.
+pub fn load_crypttab() -> io::Result<Vec<CrypttabEntry>> {
+    load_crypttab_from_root("/")
+}
+
+/// Read `etc/crypttab` of the system installed at `root`, which is empty if it does not exist.
+pub fn load_crypttab_from_root<P: AsRef<Path>>(root: P) -> io::Result<Vec<CrypttabEntry>> {
+    match fs::read_to_string(root.as_ref().join("etc/crypttab")) {
+        Ok(contents) => Ok(parse_crypttab(&contents)),
+        Err(ref why) if why.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(why) => Err(why),
+    }
+}
+
+/// A LUKS volume which the initrd unlocks.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LuksVolume {
+    pub uuid: String,
+    /// The name of the unlocked volume, which is `luks-<uuid>` when it is not given.
+    pub name: Option<String>,
+    /// Options of systemd-cryptsetup, such as `discard` or `tpm2-device=auto`.
+    pub options: Vec<String>,
+}
+
+impl LuksVolume {
+    pub fn new<S: Into<String>>(uuid: S) -> Self {
+        Self {
+            uuid: uuid.into().to_lowercase(),
+            name: None,
+            options: Vec::new(),
+        }
+    }
+
+    /// The volume of a LUKS device or image, by the UUID in its header.
+    pub fn from_device<P: AsRef<Path>>(device: P) -> io::Result<Self> {
+        let mut header = [0; UUID_OFFSET + UUID_LEN];
+        File::open(device)?.read_exact(&mut header)?;
+
+        if !header.starts_with(LUKS_MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a LUKS volume",
+            ));
+        }
+
+        let uuid = &header[UUID_OFFSET..];
+        let end = uuid.iter().position(|&b| b == 0).unwrap_or(uuid.len());
+        match std::str::from_utf8(&uuid[..end]) {
+            Ok(uuid) if !uuid.is_empty() => Ok(Self::new(uuid)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the LUKS header has no valid UUID",
+            )),
+        }
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn option<S: Into<String>>(mut self, option: S) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// The options which systemd's initrd unlocks the volume with: `rd.luks.uuid=`, and
+    /// `rd.luks.name=` and `rd.luks.options=` where they are needed.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![format!("rd.luks.uuid={}", self.uuid)];
+
+        if let Some(ref name) = self.name {
+            args.push(format!("rd.luks.name={}={}", self.uuid, name));
+        }
+
+        if !self.options.is_empty() {
+            args.push(format!(
+                "rd.luks.options={}={}",
+                self.uuid,
+                self.options.join(",")
+            ));
+        }
+
+        args
+    }
+
+    /// The `cryptdevice=` option which the `encrypt` hook of mkinitcpio, and other initrds
+    /// which predate systemd, unlock the volume with.
+    pub fn cryptdevice_arg(&self) -> String {
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| ["luks-", &self.uuid].concat());
+
+        let mut arg = format!("cryptdevice=UUID={}:{}", self.uuid, name);
+        if self.options.iter().any(|option| option == "discard") {
+            arg.push_str(":allow-discards");
+        }
+
+        arg
+    }
+}
+
+/// The UUIDs of the LUKS volumes which kernel options unlock explicitly, in lowercase.
+///
+/// These are given by `rd.luks.uuid=`, `rd.luks.name=`, and `cryptdevice=`, or by their
+/// variants without the `rd.` prefix. Without any of them, systemd's initrd unlocks every
+/// volume of the crypttab within it, which cannot be checked from the options alone. Options
+/// which disable unlocking, such as `rd.luks=0`, unlock nothing.
+pub fn unlocked_uuids<S: AsRef<str>>(options: &[S]) -> Vec<String> {
+    let mut uuids = Vec::new();
+
+    for option in options {
+        let (key, value) = match option.as_ref().split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let uuid = match key.strip_prefix("rd.").unwrap_or(key) {
+            "luks" if matches!(value, "0" | "no" | "false" | "off") => return Vec::new(),
+            "luks.uuid" => value.to_owned(),
+            "luks.name" => match value.split_once('=') {
+                Some((uuid, _)) => uuid.to_owned(),
+                None => continue,
+            },
+            "cryptdevice" => match value.split(':').next().and_then(device_uuid) {
+                Some(uuid) => uuid,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        let uuid = uuid.strip_prefix("luks-").unwrap_or(&uuid).to_lowercase();
+        if !uuids.contains(&uuid) {
+            uuids.push(uuid);
+        }
+    }
+
+    uuids
+}
+
+/// Whether kernel options explicitly unlock the LUKS volume with this UUID.
+pub fn unlocks<S: AsRef<str>>(options: &[S], uuid: &str) -> bool {
+    let uuid = uuid.to_lowercase();
+    unlocked_uuids(options).contains(&uuid)
+}
+
+/// The UUID of a device which is given as `UUID=<uuid>` or `/dev/disk/by-uuid/<uuid>`.
+fn device_uuid(device: &str) -> Option<String> {
+    device
+        .strip_prefix("UUID=")
+        .or_else(|| device.strip_prefix("/dev/disk/by-uuid/"))
+        .filter(|uuid| !uuid.is_empty())
+        .map(str::to_lowercase)
+}
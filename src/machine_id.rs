@@ -0,0 +1,30 @@
+//! The machine id, which kernel-install records in entries to tell installations apart.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Whether an id is formatted as a machine id: 32 lowercase hexadecimal characters.
+pub fn is_valid(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Read the machine id of the running system from `/etc/machine-id`.
+pub fn load() -> io::Result<String> {
+    load_from_root("/")
+}
+
+/// Read the machine id of the system installed at `root`, such as from an installer.
+pub fn load_from_root<P: AsRef<Path>>(root: P) -> io::Result<String> {
+    let contents = fs::read_to_string(root.as_ref().join("etc/machine-id"))?;
+    let id = contents.trim();
+
+    if is_valid(id) {
+        Ok(id.to_owned())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is not a valid machine id", id),
+        ))
+    }
+}
@@ -0,0 +1,42 @@
+//! Logging macros which forward to the `log` crate when the `log` feature is enabled, and
+//! otherwise compile to nothing.
+
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! info {
+    ($($arg:tt)*) => { log::info!($($arg)*) };
+}
+
+#[cfg(feature = "log")]
+macro_rules! warn {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! info {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
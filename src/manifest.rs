@@ -0,0 +1,127 @@
+//! SHA-256 manifests of the boot configuration, to detect files on the ESP which were modified
+//! or corrupted since the manifest was made.
+//!
+//! Manifests use the format of `sha256sum`, so they may also be checked with `sha256sum -c`.
+
+use crate::ErrorKind;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ManifestError {
+    #[error("line {} is not a SHA-256 digest followed by a path", _0)]
+    InvalidLine(usize),
+}
+
+impl ManifestError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::InvalidData
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// The SHA-256 digests of a set of files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// The lowercase hex digest of each file.
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+impl Manifest {
+    /// Parses a manifest in the format of `sha256sum`.
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let mut files = BTreeMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let invalid = || ManifestError::InvalidLine(number + 1);
+            let (digest, path) = line.split_at(line.find(' ').ok_or_else(invalid)?);
+            // Binary mode is marked with a `*` before the path.
+            let path = path.strip_prefix("  ").or_else(|| path.strip_prefix(" *"));
+
+            match path {
+                Some(path) if !path.is_empty() && is_digest(digest) => {
+                    files.insert(PathBuf::from(path), digest.to_ascii_lowercase());
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(Manifest { files })
+    }
+
+    /// Records the digest of a file's contents.
+    pub fn insert<P: Into<PathBuf>>(&mut self, path: P, contents: &[u8]) {
+        self.files.insert(path.into(), digest(contents));
+    }
+
+    /// The differences from this manifest to a newer one.
+    pub fn compare(&self, current: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+
+        for (path, digest) in &self.files {
+            match current.files.get(path) {
+                Some(current) if current == digest => (),
+                Some(_) => diff.changed.push(path.clone()),
+                None => diff.removed.push(path.clone()),
+            }
+        }
+
+        diff.added = current
+            .files
+            .keys()
+            .filter(|path| !self.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, digest) in &self.files {
+            writeln!(f, "{}  {}", digest, path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The files which differ between two manifests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    pub added: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl ManifestDiff {
+    /// Whether the manifests describe the same files.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The lowercase hex SHA-256 digest of some data.
+pub fn digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn is_digest(digest: &str) -> bool {
+    digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())
+}
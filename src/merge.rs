@@ -0,0 +1,44 @@
+//! Options for combining the entries of another loader configuration with `SystemdBootConf::merge`.
+
+use std::path::PathBuf;
+
+/// What to do with an entry whose id is already used by an existing entry.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MergeConflict {
+    /// Keep the existing entry, and skip the merged one.
+    #[default]
+    Keep,
+    /// Replace the existing entry with the merged one.
+    Replace,
+    /// Add the merged entry with a `-merged` suffix on its id.
+    Rename,
+    /// Fail with `Error::EntryExists`, before any entry is merged.
+    Fail,
+}
+
+/// Options for `SystemdBootConf::merge`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct MergeOptions {
+    pub conflict: MergeConflict,
+    /// Rewrite the machine id of merged entries to this one, including where the old machine id
+    /// is the entry token at the start of an entry's id.
+    pub machine_id: Option<String>,
+    /// Copy the images which merged entries boot, if they are missing from this ESP.
+    pub copy_payloads: bool,
+}
+
+/// The changes made by `SystemdBootConf::merge`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct MergeReport {
+    /// The ids of the entries which were added or replaced, which should be written with
+    /// `overwrite_entry_conf`.
+    pub merged: Vec<String>,
+    /// Existing entries which were replaced.
+    pub replaced: Vec<String>,
+    /// The original and new ids of entries which were renamed to avoid a conflict.
+    pub renamed: Vec<(String, String)>,
+    /// Entries which were skipped, as an entry with the same id exists.
+    pub skipped: Vec<String>,
+    /// The images which were copied into this ESP.
+    pub copied: Vec<PathBuf>,
+}
@@ -0,0 +1,20 @@
+//! Keeping a mirrored ESP, such as the second disk of a software RAID, identical to the primary
+//! ESP with `SystemdBootConf::sync_to`.
+
+use std::path::PathBuf;
+
+/// The changes made to the mirrored ESP by `SystemdBootConf::sync_to`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct SyncReport {
+    /// Files which were missing or differed, and were copied from the primary ESP.
+    pub copied: Vec<PathBuf>,
+    /// Entries, images, and boot loaders which are not on the primary ESP, and were removed.
+    pub removed: Vec<PathBuf>,
+}
+
+impl SyncReport {
+    /// Whether the mirror was already identical to the primary ESP.
+    pub fn is_empty(&self) -> bool {
+        self.copied.is_empty() && self.removed.is_empty()
+    }
+}
@@ -0,0 +1,304 @@
+//! Finding and mounting the ESP where it is not mounted, such as in rescue environments, and
+//! remounting it where systemd's automount units mount it read-only or where the kernel
+//! remounted it read-only after errors, so that it may be written to.
+
+use crate::efivars::EfiVars;
+use crate::rootfs::unescape;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The partition type GUID of EFI system partitions.
+pub const ESP_PARTITION_TYPE: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+
+/// The filesystem type which ESPs are mounted with.
+const ESP_FSTYPE: &str = "vfat";
+
+/// Counts the temporary mount points created by this process, to give each a unique name.
+static MOUNT_POINTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The block device of the ESP.
+///
+/// This is the partition which the loader reports that it was booted from, if it does, and
+/// otherwise the first partition which udev reports to be of the ESP partition type.
+pub fn find_esp_partition() -> io::Result<Option<PathBuf>> {
+    if let Ok(Some(uuid)) = EfiVars::new().loader_partition_uuid() {
+        let device = Path::new("/dev/disk/by-partuuid").join(uuid);
+        if device.exists() {
+            return Ok(Some(device));
+        }
+    }
+
+    let mut partitions = Vec::new();
+    for entry in fs::read_dir("/sys/class/block")? {
+        let sys = entry?.path();
+        if !sys.join("partition").exists() {
+            continue;
+        }
+
+        let dev = match fs::read_to_string(sys.join("dev")) {
+            Ok(dev) => dev,
+            Err(_) => continue,
+        };
+
+        let udev = fs::read_to_string(["/run/udev/data/b", dev.trim()].concat());
+        let is_esp = udev.is_ok_and(|udev| {
+            udev.lines()
+                .filter_map(|line| line.strip_prefix("E:ID_PART_ENTRY_TYPE="))
+                .any(|kind| kind.eq_ignore_ascii_case(ESP_PARTITION_TYPE))
+        });
+
+        if let (true, Some(name)) = (is_esp, sys.file_name()) {
+            partitions.push(Path::new("/dev").join(name));
+        }
+    }
+
+    partitions.sort();
+    Ok(partitions.into_iter().next())
+}
+
+/// Where a block device is mounted, if it is.
+///
+/// This fails with `io::ErrorKind::Unsupported` without the `mountinfo` feature.
+pub fn mount_point(device: &Path) -> io::Result<Option<PathBuf>> {
+    if cfg!(not(feature = "mountinfo")) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "inspecting mounts requires the mountinfo feature",
+        ));
+    }
+
+    let device = fs::canonicalize(device)?;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    Ok(mountinfo.lines().find_map(|line| {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        let source = unescape(filesystem.split(' ').nth(1)?);
+        let source = fs::canonicalize(&source).unwrap_or_else(|_| PathBuf::from(source));
+
+        if source == device {
+            mount
+                .split(' ')
+                .nth(4)
+                .map(|point| PathBuf::from(unescape(point)))
+        } else {
+            None
+        }
+    }))
+}
+
+/// A filesystem mounted to a temporary directory, which is unmounted and removed when it is
+/// dropped.
+#[derive(Debug)]
+pub struct TempMount {
+    device: PathBuf,
+    path: PathBuf,
+    mounted: bool,
+}
+
+impl TempMount {
+    /// Mount an ESP to a new directory in the temporary directory, with permissions only for
+    /// root, as systemd mounts it.
+    ///
+    /// This requires root privileges.
+    pub fn esp<P: Into<PathBuf>>(device: P) -> io::Result<Self> {
+        Self::new(device, ESP_FSTYPE, "umask=0077")
+    }
+
+    /// Mount a filesystem to a new directory in the temporary directory, with options such as
+    /// `umask=0077` for its filesystem type.
+    pub fn new<P: Into<PathBuf>>(device: P, fstype: &str, options: &str) -> io::Result<Self> {
+        let device = device.into();
+        let path = create_mount_point()?;
+
+        if let Err(why) = mount(&device, &path, fstype, options) {
+            let _ = fs::remove_dir(&path);
+            return Err(why);
+        }
+
+        info!("mounted {:?} at {:?}", device, path);
+        Ok(Self {
+            device,
+            path,
+            mounted: true,
+        })
+    }
+
+    /// The device which is mounted.
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// Where the device is mounted.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unmount the filesystem and remove its mount point, reporting failures which dropping it
+    /// would ignore.
+    pub fn unmount(mut self) -> io::Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        if self.mounted {
+            unmount(&self.path)?;
+            self.mounted = false;
+            info!("unmounted {:?} from {:?}", self.device, self.path);
+        }
+
+        fs::remove_dir(&self.path)
+    }
+}
+
+impl Drop for TempMount {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(why) = self.release() {
+                warn!("unable to unmount {:?}: {}", self.path, why);
+            }
+        }
+    }
+}
+
+/// Remount the filesystem mounted at `path` read-only or read-write, keeping its other flags.
+#[cfg(target_os = "linux")]
+pub fn remount(path: &Path, read_only: bool) -> io::Result<()> {
+    use crate::filesystem::statvfs;
+
+    /// The mount flags which `statvfs` reports, and which a remount would otherwise clear.
+    const KEPT: &[(libc::c_ulong, libc::c_ulong)] = &[
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_SYNCHRONOUS, libc::MS_SYNCHRONOUS),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ];
+
+    let current = statvfs(path)?.f_flag;
+    let mut flags = libc::MS_REMOUNT;
+    for &(stat, mount) in KEPT {
+        if current & stat != 0 {
+            flags |= mount;
+        }
+    }
+
+    if read_only {
+        flags |= libc::MS_RDONLY;
+    }
+
+    let target = c_path(path)?;
+    let result = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            target.as_ptr(),
+            std::ptr::null(),
+            flags,
+            std::ptr::null(),
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    info!(
+        "remounted {:?} {}",
+        path,
+        if read_only { "read-only" } else { "read-write" }
+    );
+    Ok(())
+}
+
+/// Remounting is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn remount(_path: &Path, _read_only: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "remounting is only supported on Linux",
+    ))
+}
+
+/// Create an empty directory to mount a filesystem to.
+fn create_mount_point() -> io::Result<PathBuf> {
+    let dir = std::env::temp_dir();
+
+    loop {
+        let id = MOUNT_POINTS.fetch_add(1, Ordering::Relaxed);
+        let name = format!("systemd-boot-conf-{}-{}", std::process::id(), id);
+        let path = dir.join(name);
+
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(ref why) if why.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(why) => return Err(why),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn mount(device: &Path, path: &Path, fstype: &str, options: &str) -> io::Result<()> {
+    let device = c_path(device)?;
+    let target = c_path(path)?;
+    let fstype = c_string(fstype)?;
+    let options = c_string(options)?;
+    let flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+
+    let result = unsafe {
+        libc::mount(
+            device.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            flags,
+            options.as_ptr().cast(),
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn unmount(path: &Path) -> io::Result<()> {
+    let target = c_path(path)?;
+
+    if unsafe { libc::umount2(target.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount(_device: &Path, _path: &Path, _fstype: &str, _options: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "mounting is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unmount(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unmounting is only supported on Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))
+}
+
+#[cfg(target_os = "linux")]
+fn c_string(value: &str) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(value).map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))
+}
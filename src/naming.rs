@@ -0,0 +1,57 @@
+//! Schemes for naming the entries which are added, which determine their file names.
+
+use crate::entry::{Entry, EntryError};
+use std::fmt;
+use std::sync::Arc;
+
+/// How the id of an entry given to `SystemdBootConf::add_entry`, and so its file name, is
+/// chosen. A boot counter in the given id is kept.
+#[derive(Clone, Default)]
+pub enum NamingScheme {
+    /// Keep the id that the entry was given.
+    #[default]
+    Id,
+    /// `<token>-<version>`, where the token identifies the installation, such as the `ID` of
+    /// os-release, or the contents of `/etc/kernel/entry-token`.
+    Token(String),
+    /// `<machine-id>-<version>`, as `kernel-install` names entries by default.
+    MachineId,
+    /// Chosen by a function of the entry.
+    Custom(Arc<dyn Fn(&Entry) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for NamingScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NamingScheme::Id => f.write_str("Id"),
+            NamingScheme::Token(token) => f.debug_tuple("Token").field(token).finish(),
+            NamingScheme::MachineId => f.write_str("MachineId"),
+            NamingScheme::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl NamingScheme {
+    /// The id which an entry is given under this scheme.
+    pub fn id_for(&self, entry: &Entry) -> Result<String, EntryError> {
+        let version = || entry.version.as_deref().ok_or(EntryError::MissingVersion);
+
+        let id = match self {
+            NamingScheme::Id => return Ok(entry.id.clone()),
+            NamingScheme::Token(token) => [token, "-", version()?].concat(),
+            NamingScheme::MachineId => {
+                let machine_id = entry
+                    .machine_id
+                    .as_deref()
+                    .ok_or(EntryError::MissingMachineId)?;
+                [machine_id, "-", version()?].concat()
+            }
+            NamingScheme::Custom(name) => name(entry),
+        };
+
+        Ok(match entry.boot_counter() {
+            Some(counter) => [id, counter.to_string()].concat(),
+            None => id,
+        })
+    }
+}
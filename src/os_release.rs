@@ -0,0 +1,137 @@
+//! Reading `os-release`, to give generated entries the titles, ids, and sort keys that
+//! kernel-install would give them.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The locations of os-release in order of precedence, relative to the root of the system.
+const PATHS: &[&str] = &["etc/os-release", "usr/lib/os-release"];
+
+/// The fields of an os-release file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct OsRelease {
+    pub fields: BTreeMap<String, String>,
+}
+
+impl OsRelease {
+    /// Read the os-release of the running system.
+    pub fn load() -> io::Result<Self> {
+        Self::load_from_root("/")
+    }
+
+    /// Read the os-release of the system installed at `root`, such as from an installer.
+    pub fn load_from_root<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref();
+        let mut last_error = None;
+
+        for path in PATHS {
+            match fs::read_to_string(root.join(path)) {
+                Ok(contents) => return Ok(Self::parse(&contents)),
+                Err(why) => last_error = Some(why),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotFound)))
+    }
+
+    /// Parse the contents of an os-release file, which is a list of shell variable assignments.
+    ///
+    /// Lines which are not assignments are ignored.
+    pub fn parse(contents: &str) -> Self {
+        OsRelease {
+            fields: parse_assignments(contents),
+        }
+    }
+
+    /// The value of a field, if it is defined and not empty.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The title that kernel-install gives to entries for this OS.
+    ///
+    /// This is `PRETTY_NAME`, or `Linux` followed by the kernel version when it is not defined.
+    pub fn entry_title(&self, kernel_version: &str) -> String {
+        match self.get("PRETTY_NAME") {
+            Some(name) => name.to_owned(),
+            None => ["Linux ", kernel_version].concat(),
+        }
+    }
+
+    /// The sort key that kernel-install gives to entries for this OS, which is `IMAGE_ID` or `ID`.
+    pub fn sort_key(&self) -> Option<&str> {
+        self.get("IMAGE_ID").or_else(|| self.get("ID"))
+    }
+
+    /// The version of the OS, which is `IMAGE_VERSION` or `VERSION_ID`.
+    ///
+    /// This is how systemd-boot labels unified kernel images.
+    pub fn version(&self) -> Option<&str> {
+        self.get("IMAGE_VERSION").or_else(|| self.get("VERSION_ID"))
+    }
+
+    /// The id that kernel-install gives to an entry, which is named after the entry token and the
+    /// kernel version.
+    ///
+    /// The entry token is the machine id by default, or the os-release `IMAGE_ID` or `ID` if
+    /// `machine_id` is `None`.
+    pub fn entry_id(&self, machine_id: Option<&str>, kernel_version: &str) -> Option<String> {
+        let token = machine_id.or_else(|| self.sort_key())?;
+        Some([token, "-", kernel_version].concat())
+    }
+}
+
+/// Parse a list of shell variable assignments, as os-release and kernel-install's `install.conf`
+/// are written, ignoring the lines which are not assignments.
+pub(crate) fn parse_assignments(contents: &str) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.find('=') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => continue,
+        };
+
+        let valid_key =
+            !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+
+        if valid_key {
+            fields.insert(key.to_owned(), unquote(value));
+        }
+    }
+
+    fields
+}
+
+/// Removes the quotes and backslash escapes from a shell value.
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::with_capacity(value.len());
+    let mut quote = None;
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            // Backslashes only escape within double quotes or outside of quotes.
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    unquoted.push(escaped);
+                }
+            }
+            _ => unquoted.push(c),
+        }
+    }
+
+    unquoted
+}
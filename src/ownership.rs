@@ -0,0 +1,60 @@
+//! Guarding the entries of other OS installations on a shared ESP against modification.
+
+use crate::entry::Entry;
+use crate::host::Host;
+use std::io;
+
+/// The installation which the configuration is managed for, whose entries alone may be modified
+/// or deleted while `SystemdBootConf::ownership` is set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ownership {
+    pub machine_id: String,
+    /// The entry token that kernel-install names entries with, from `/etc/kernel/entry-token`.
+    pub entry_token: Option<String>,
+}
+
+impl Ownership {
+    pub fn new<S: Into<String>>(machine_id: S) -> Self {
+        Self {
+            machine_id: machine_id.into(),
+            entry_token: None,
+        }
+    }
+
+    /// The installation of the running system.
+    pub fn local() -> io::Result<Self> {
+        Self::of(&Host::running())
+    }
+
+    /// The installation of a host, which may be at an alternate root.
+    pub fn of(host: &Host) -> io::Result<Self> {
+        Ok(Self {
+            machine_id: host.machine_id()?,
+            entry_token: host.entry_token().ok(),
+        })
+    }
+
+    /// Whether an entry belongs to this installation.
+    ///
+    /// An entry with a machine id belongs to the installation of that id. An entry without one
+    /// belongs to this installation if its id starts with the machine id or entry token, as
+    /// kernel-install names them. If there is no entry token to tell, entries without a machine
+    /// id are assumed to belong to this installation.
+    pub fn owns(&self, entry: &Entry) -> bool {
+        if let Some(ref machine_id) = entry.machine_id {
+            return *machine_id == self.machine_id;
+        }
+
+        let named_by = |prefix: &str| {
+            entry
+                .id
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('-'))
+        };
+
+        match self.entry_token {
+            Some(ref token) => named_by(token) || named_by(&self.machine_id),
+            None => true,
+        }
+    }
+}
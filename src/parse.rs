@@ -0,0 +1,60 @@
+//! Settings and diagnostics shared by the entry and loader parsers.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The size of the largest entry or loader conf which is parsed, so that parsing untrusted input
+/// allocates a bounded amount of memory. Real files are a few hundred bytes.
+pub const MAX_CONF_SIZE: usize = 1024 * 1024;
+
+/// How strictly configuration files are parsed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ParseMode {
+    /// Fail on any malformed, duplicated, or missing value.
+    #[default]
+    Strict,
+    /// Apply the same fallbacks as systemd-boot, recording a warning for each problem instead.
+    Lenient,
+}
+
+/// A problem that was tolerated while parsing in lenient mode.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseWarning {
+    /// The file in which the problem was found.
+    pub path: PathBuf,
+    /// The line of the problem, starting from 1, if it applies to a specific line.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{:?}:{}: {}", self.path, line, self.message),
+            None => write!(f, "{:?}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Either fails with the error in strict mode, or records it as a warning in lenient mode.
+pub(crate) fn tolerate<E: fmt::Display>(
+    mode: ParseMode,
+    warnings: &mut Vec<ParseWarning>,
+    path: &Path,
+    line: Option<usize>,
+    error: E,
+) -> Result<(), E> {
+    match mode {
+        ParseMode::Strict => Err(error),
+        ParseMode::Lenient => {
+            warn!("{:?}: tolerating parse error: {}", path, error);
+            warnings.push(ParseWarning {
+                path: path.to_path_buf(),
+                line,
+                message: error.to_string(),
+            });
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! A minimal reader for the PE images that EFI firmware executes, such as EFI-stub kernels,
+//! unified kernel images, and systemd-boot itself.
+
+use std::borrow::Cow;
+
+/// The index of the certificate table in the data directories.
+pub const SECURITY_DIRECTORY: usize = 4;
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const SECTION_HEADER_LEN: usize = 40;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeError {
+    #[error("image has an unknown optional header magic ({:#x})", _0)]
+    InvalidMagic(u16),
+    #[error("image is not a PE executable")]
+    NotPe,
+    #[error("image is truncated")]
+    Truncated,
+}
+
+/// A section of a PE image.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Section {
+    pub name: String,
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub raw_size: u32,
+    pub raw_offset: u32,
+}
+
+/// The headers of a PE image, which borrows the image's contents.
+#[derive(Debug, Clone)]
+pub struct PeImage<'a> {
+    data: &'a [u8],
+    /// The `IMAGE_FILE_MACHINE_*` that the image was built for.
+    pub machine: u16,
+    /// Whether this is a 64-bit PE32+ image.
+    pub pe32_plus: bool,
+    /// The offset of the optional header in the file.
+    pub optional_header_offset: usize,
+    /// The virtual address and size of each data directory.
+    pub data_directories: Vec<(u32, u32)>,
+    pub sections: Vec<Section>,
+}
+
+impl<'a> PeImage<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, PeError> {
+        if !data.starts_with(b"MZ") {
+            return Err(PeError::NotPe);
+        }
+
+        let pe_offset = read_u32(data, 0x3c)? as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(b"PE\0\0") {
+            return Err(PeError::NotPe);
+        }
+
+        let coff = pe_offset + 4;
+        let machine = read_u16(data, coff)?;
+        let section_count = read_u16(data, coff + 2)? as usize;
+        let optional_header_len = read_u16(data, coff + 16)? as usize;
+
+        let optional_header_offset = coff + 20;
+        let magic = read_u16(data, optional_header_offset)?;
+        let (pe32_plus, directories_offset) = match magic {
+            PE32_MAGIC => (false, 96),
+            PE32_PLUS_MAGIC => (true, 112),
+            magic => return Err(PeError::InvalidMagic(magic)),
+        };
+
+        let mut data_directories = Vec::new();
+        if optional_header_len >= directories_offset {
+            let count = read_u32(data, optional_header_offset + directories_offset - 4)? as usize;
+            let available = (optional_header_len - directories_offset) / 8;
+
+            for index in 0..count.min(available) {
+                let offset = optional_header_offset + directories_offset + index * 8;
+                data_directories.push((read_u32(data, offset)?, read_u32(data, offset + 4)?));
+            }
+        }
+
+        let section_table = optional_header_offset + optional_header_len;
+        let mut sections = Vec::with_capacity(section_count);
+        for index in 0..section_count {
+            let offset = section_table + index * SECTION_HEADER_LEN;
+            let header = data
+                .get(offset..offset + SECTION_HEADER_LEN)
+                .ok_or(PeError::Truncated)?;
+
+            let name = &header[..8];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(8)];
+
+            sections.push(Section {
+                name: String::from_utf8_lossy(name).into_owned(),
+                virtual_size: read_u32(header, 8)?,
+                virtual_address: read_u32(header, 12)?,
+                raw_size: read_u32(header, 16)?,
+                raw_offset: read_u32(header, 20)?,
+            });
+        }
+
+        Ok(PeImage {
+            data,
+            machine,
+            pe32_plus,
+            optional_header_offset,
+            data_directories,
+            sections,
+        })
+    }
+
+    /// The contents of the image.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// The contents of a section, excluding the padding of its raw data.
+    pub fn section_data(&self, name: &str) -> Option<&'a [u8]> {
+        let section = self.section(name)?;
+        let len = match section.virtual_size {
+            0 => section.raw_size,
+            size => size.min(section.raw_size),
+        };
+
+        let start = section.raw_offset as usize;
+        self.data.get(start..start + len as usize)
+    }
+
+    /// The contents of a section as text, without trailing NUL bytes or whitespace.
+    pub fn section_text(&self, name: &str) -> Option<Cow<'a, str>> {
+        let data = self.section_data(name)?;
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        match String::from_utf8_lossy(&data[..end]) {
+            Cow::Borrowed(text) => Some(Cow::Borrowed(text.trim_end())),
+            Cow::Owned(text) => Some(Cow::Owned(text.trim_end().to_owned())),
+        }
+    }
+
+    /// The virtual address and size of a data directory, if it is present and not empty.
+    pub fn data_directory(&self, index: usize) -> Option<(u32, u32)> {
+        self.data_directories
+            .get(index)
+            .cloned()
+            .filter(|&(_, size)| size != 0)
+    }
+
+    /// The EFI name of the architecture the image was built for, such as `x64`.
+    pub fn architecture(&self) -> Option<&'static str> {
+        match self.machine {
+            0x014c => Some("ia32"),
+            0x8664 => Some("x64"),
+            0x01c2 | 0x01c4 => Some("arm"),
+            0xaa64 => Some("aa64"),
+            0x5032 => Some("riscv32"),
+            0x5064 => Some("riscv64"),
+            0x6232 => Some("loongarch32"),
+            0x6264 => Some("loongarch64"),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> Result<u16, PeError> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => Err(PeError::Truncated),
+    }
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> Result<u32, PeError> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(PeError::Truncated),
+    }
+}
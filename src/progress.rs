@@ -0,0 +1,46 @@
+//! Reporting the progress of operations which copy many or large files, so that frontends may
+//! show a progress bar while they run.
+
+use std::fmt::Debug;
+use std::path::Path;
+
+/// The operations which report their progress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Copying the images which merged entries boot, in `SystemdBootConf::merge`.
+    MergePayloads,
+    /// Copying files to a mirrored ESP, in `SystemdBootConf::sync_to`.
+    Sync,
+}
+
+/// How far an operation has come, reported before each file is copied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Progress<'a> {
+    pub operation: Operation,
+    /// The file which is about to be copied.
+    pub path: &'a Path,
+    /// The size of the file which is about to be copied, in bytes.
+    pub file_bytes: u64,
+    /// The bytes which have been copied, or found to be up to date, so far.
+    pub bytes: u64,
+    /// The bytes which the operation will have handled when it is done.
+    ///
+    /// This is an estimate, as files may be skipped or change size while the operation runs.
+    pub total_bytes: u64,
+}
+
+impl Progress<'_> {
+    /// The fraction of the operation which is done, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        match self.total_bytes {
+            0 => 0.0,
+            total => (self.bytes as f64 / total as f64).min(1.0),
+        }
+    }
+}
+
+/// An observer of the progress of long operations.
+pub trait ProgressListener: Debug + Send + Sync {
+    fn progress(&self, progress: &Progress);
+}
@@ -0,0 +1,85 @@
+//! Combinators for selecting entries.
+
+use crate::entry::{Entry, EntryKind};
+use crate::version;
+
+/// A selection of entries, narrowed down by chaining filters.
+#[derive(Debug, Clone)]
+pub struct EntryQuery<'a> {
+    entries: Vec<&'a Entry>,
+}
+
+impl<'a> EntryQuery<'a> {
+    pub fn new(entries: &'a [Entry]) -> Self {
+        Self {
+            entries: entries.iter().collect(),
+        }
+    }
+
+    /// Keep entries which satisfy the predicate.
+    pub fn filter<F: FnMut(&Entry) -> bool>(mut self, mut predicate: F) -> Self {
+        self.entries.retain(|entry| predicate(entry));
+        self
+    }
+
+    /// Keep entries which belong to the given machine id.
+    pub fn with_machine_id(self, machine_id: &str) -> Self {
+        self.filter(|entry| entry.machine_id.as_deref() == Some(machine_id))
+    }
+
+    /// Keep entries whose title contains the given text, ignoring case.
+    pub fn matching_title(self, text: &str) -> Self {
+        let text = text.to_lowercase();
+        self.filter(|entry| entry.title.to_lowercase().contains(&text))
+    }
+
+    /// Keep entries which may be booted on a machine of the given EFI architecture.
+    pub fn architecture(self, architecture: &str) -> Self {
+        self.filter(|entry| entry.bootable_on(architecture))
+    }
+
+    /// Keep entries which may be booted on this machine.
+    pub fn bootable(self) -> Self {
+        self.filter(|entry| !entry.is_foreign())
+    }
+
+    /// Keep entries of the given kind.
+    pub fn kind(self, kind: EntryKind) -> Self {
+        self.filter(|entry| entry.kind() == kind)
+    }
+
+    /// The entry with the newest version, as ordered by systemd-boot.
+    pub fn newest_version(self) -> Option<&'a Entry> {
+        self.entries
+            .into_iter()
+            .filter_map(|entry| entry.version.as_deref().map(|version| (entry, version)))
+            .max_by(|(_, a), (_, b)| version::compare(a, b))
+            .map(|(entry, _)| entry)
+    }
+
+    /// The first of the selected entries.
+    pub fn first(&self) -> Option<&'a Entry> {
+        self.entries.first().cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a Entry> + '_ {
+        self.entries.iter().cloned()
+    }
+}
+
+impl<'a> IntoIterator for EntryQuery<'a> {
+    type Item = &'a Entry;
+    type IntoIter = std::vec::IntoIter<&'a Entry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
@@ -0,0 +1,548 @@
+//! Asking the boot loader to show its menu on the next boot, such as when support staff need a
+//! user to choose another entry.
+
+use crate::efivars::{EfiVarError, EfiVars};
+use std::time::Duration;
+
+/// How the boot menu was requested.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MenuRequest {
+    /// Through logind, which sets the EFI variable when the system is rebooted.
+    Logind,
+    /// By setting the `LoaderConfigTimeoutOneShot` EFI variable directly.
+    EfiVar,
+}
+
+/// Show the boot menu for `timeout` on the next boot, after which the default entry is booted.
+///
+/// With the `logind` feature, this is requested through logind, as `systemctl reboot
+/// --boot-loader-menu` does. If logind is unavailable or refuses, or without the feature, the
+/// EFI variable is set instead. The timeout is rounded up to whole seconds.
+pub fn request_boot_menu(timeout: Duration, efivars: &EfiVars) -> Result<MenuRequest, EfiVarError> {
+    #[cfg(feature = "logind")]
+    match logind::set_reboot_to_boot_loader_menu(Some(timeout)) {
+        Ok(()) => return Ok(MenuRequest::Logind),
+        Err(why) => warn!("logind did not accept the boot menu request: {}", why),
+    }
+
+    efivars.set_menu_timeout_oneshot(Some(timeout_secs(timeout)))?;
+    Ok(MenuRequest::EfiVar)
+}
+
+/// Withdraw a request to show the boot menu on the next boot, however it was made.
+pub fn cancel_boot_menu(efivars: &EfiVars) -> Result<(), EfiVarError> {
+    #[cfg(feature = "logind")]
+    if let Err(why) = logind::set_reboot_to_boot_loader_menu(None) {
+        warn!("logind did not withdraw the boot menu request: {}", why);
+    }
+
+    efivars.set_menu_timeout_oneshot(None)
+}
+
+/// The timeout in whole seconds, as the boot loader counts it, which is at least one.
+fn timeout_secs(timeout: Duration) -> u32 {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    secs.clamp(1, u64::from(u32::MAX)) as u32
+}
+
+/// A client of just enough of the D-Bus protocol to call logind on the system bus.
+#[cfg(feature = "logind")]
+pub mod logind {
+    use std::env;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    const SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+
+    const METHOD_CALL: u8 = 1;
+    const METHOD_RETURN: u8 = 2;
+    const ERROR: u8 = 3;
+
+    const ALLOW_INTERACTIVE_AUTHORIZATION: u8 = 0x4;
+
+    const FIELD_PATH: u8 = 1;
+    const FIELD_INTERFACE: u8 = 2;
+    const FIELD_MEMBER: u8 = 3;
+    const FIELD_ERROR_NAME: u8 = 4;
+    const FIELD_REPLY_SERIAL: u8 = 5;
+    const FIELD_DESTINATION: u8 = 6;
+    const FIELD_SIGNATURE: u8 = 8;
+
+    /// Ask logind to show the boot menu for `timeout` on the next boot, or withdraw the request
+    /// with `None`.
+    ///
+    /// This may require authorization by polkit, which may ask the user interactively.
+    pub fn set_reboot_to_boot_loader_menu(timeout: Option<Duration>) -> io::Result<()> {
+        // logind takes the timeout in microseconds, where the maximum withdraws the request.
+        let usec = timeout.map_or(u64::MAX, |timeout| {
+            timeout.as_micros().min(u128::from(u64::MAX - 1)) as u64
+        });
+
+        let mut bus = Bus::connect()?;
+        bus.call(
+            "/org/freedesktop/login1",
+            "org.freedesktop.login1.Manager",
+            "SetRebootToBootLoaderMenu",
+            "org.freedesktop.login1",
+            Some(usec),
+        )
+    }
+
+    /// A connection to the bus over a stream, which is a `UnixStream` outside of tests.
+    struct Bus<S> {
+        reader: BufReader<S>,
+        serial: u32,
+    }
+
+    impl Bus<UnixStream> {
+        fn connect() -> io::Result<Self> {
+            let address = env::var("DBUS_SYSTEM_BUS_ADDRESS").ok();
+            let path = address
+                .as_deref()
+                .and_then(|address| address.strip_prefix("unix:path="))
+                .map(|path| path.split(',').next().unwrap_or(path))
+                .unwrap_or(SYSTEM_BUS);
+
+            let mut stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+            // Authenticate as the user of the process, as known to the bus from the socket.
+            stream.write_all(auth_external(unsafe { libc::getuid() }).as_bytes())?;
+
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if !line.starts_with("OK ") {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("the system bus rejected authentication: {}", line.trim()),
+                ));
+            }
+
+            reader.get_mut().write_all(b"BEGIN\r\n")?;
+
+            let mut bus = Self { reader, serial: 0 };
+            bus.call(
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "Hello",
+                "org.freedesktop.DBus",
+                None,
+            )?;
+
+            Ok(bus)
+        }
+    }
+
+    impl<S: Read + Write> Bus<S> {
+        /// Call a method which takes no arguments, or a single `u64`, and wait for its reply.
+        fn call(
+            &mut self,
+            path: &str,
+            interface: &str,
+            member: &str,
+            destination: &str,
+            argument: Option<u64>,
+        ) -> io::Result<()> {
+            self.serial += 1;
+            let serial = self.serial;
+
+            let body = argument.map_or_else(Vec::new, |argument| argument.to_le_bytes().to_vec());
+
+            let mut message = Message::default();
+            message.bytes.extend_from_slice(&[
+                b'l',
+                METHOD_CALL,
+                ALLOW_INTERACTIVE_AUTHORIZATION,
+                1,
+            ]);
+            message.u32(body.len() as u32);
+            message.u32(serial);
+
+            let fields_start = message.bytes.len() + 4;
+            message.u32(0);
+            message.field(FIELD_PATH, b'o', path);
+            message.field(FIELD_INTERFACE, b's', interface);
+            message.field(FIELD_MEMBER, b's', member);
+            message.field(FIELD_DESTINATION, b's', destination);
+            if argument.is_some() {
+                message.field(FIELD_SIGNATURE, b'g', "t");
+            }
+
+            let fields_len = (message.bytes.len() - fields_start) as u32;
+            message.bytes[fields_start - 4..fields_start]
+                .copy_from_slice(&fields_len.to_le_bytes());
+            message.align(8);
+            message.bytes.extend_from_slice(&body);
+
+            self.reader.get_mut().write_all(&message.bytes)?;
+
+            loop {
+                let reply = self.receive()?;
+                if reply.reply_serial != Some(serial) {
+                    continue;
+                }
+
+                return match reply.kind {
+                    METHOD_RETURN => Ok(()),
+                    ERROR => Err(reply.error()),
+                    _ => continue,
+                };
+            }
+        }
+
+        fn receive(&mut self) -> io::Result<Reply> {
+            let mut fixed = [0; 16];
+            self.reader.read_exact(&mut fixed)?;
+
+            let little = match fixed[0] {
+                b'l' => true,
+                b'B' => false,
+                _ => return Err(invalid("a message of unknown endianness")),
+            };
+
+            let u32_at = |bytes: &[u8], pos: usize| {
+                let word = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+                if little {
+                    u32::from_le_bytes(word)
+                } else {
+                    u32::from_be_bytes(word)
+                }
+            };
+
+            let body_len = u32_at(&fixed, 4) as usize;
+            let fields_len = u32_at(&fixed, 12) as usize;
+            let padded = (fields_len + 7) & !7;
+
+            let mut rest = vec![0; padded + body_len];
+            self.reader.read_exact(&mut rest)?;
+
+            let mut reply = Reply {
+                kind: fixed[1],
+                ..Reply::default()
+            };
+
+            // Offsets are relative to the start of the message, for alignment.
+            let fields = &rest[..fields_len];
+            let mut pos = 0;
+            while pos < fields.len() {
+                pos = (pos + 7) & !7;
+                let code = *fields
+                    .get(pos)
+                    .ok_or_else(|| invalid("a truncated header"))?;
+                let sig_len = *fields
+                    .get(pos + 1)
+                    .ok_or_else(|| invalid("a truncated header"))?;
+                let signature = fields
+                    .get(pos + 2..pos + 2 + usize::from(sig_len))
+                    .ok_or_else(|| invalid("a truncated header"))?;
+                pos += 3 + usize::from(sig_len);
+
+                match signature {
+                    b"s" | b"o" => {
+                        pos = (pos + 3) & !3;
+                        if pos + 4 > fields.len() {
+                            return Err(invalid("a truncated header"));
+                        }
+
+                        let len = u32_at(fields, pos) as usize;
+                        let value = fields
+                            .get(pos + 4..pos + 4 + len)
+                            .ok_or_else(|| invalid("a truncated header"))?;
+                        if code == FIELD_ERROR_NAME {
+                            reply.error_name = String::from_utf8_lossy(value).into_owned();
+                        }
+
+                        pos += 5 + len;
+                    }
+                    b"g" => {
+                        let len = usize::from(*fields.get(pos).unwrap_or(&0));
+                        if code == FIELD_SIGNATURE {
+                            reply.signature =
+                                fields.get(pos + 1..pos + 1 + len).unwrap_or(&[]).to_vec();
+                        }
+
+                        pos += 2 + len;
+                    }
+                    b"u" => {
+                        pos = (pos + 3) & !3;
+                        if pos + 4 > fields.len() {
+                            return Err(invalid("a truncated header"));
+                        }
+
+                        if code == FIELD_REPLY_SERIAL {
+                            reply.reply_serial = Some(u32_at(fields, pos));
+                        }
+
+                        pos += 4;
+                    }
+                    _ => return Err(invalid("a header field of an unexpected type")),
+                }
+            }
+
+            // The message of an error is the first string of its body.
+            let body = &rest[padded..];
+            if reply.signature.first() == Some(&b's') && body.len() >= 4 {
+                let len = u32_at(body, 0) as usize;
+                if let Some(message) = body.get(4..4 + len) {
+                    reply.error_message = String::from_utf8_lossy(message).into_owned();
+                }
+            }
+
+            Ok(reply)
+        }
+    }
+
+    /// The command which authenticates as `uid`, with the decimal digits of the uid in hex.
+    fn auth_external(uid: u32) -> String {
+        let uid = uid
+            .to_string()
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        format!("\0AUTH EXTERNAL {}\r\n", uid)
+    }
+
+    /// A message in the making, aligned from its start as the protocol requires.
+    #[derive(Default)]
+    struct Message {
+        bytes: Vec<u8>,
+    }
+
+    impl Message {
+        fn align(&mut self, to: usize) {
+            let len = self.bytes.len().next_multiple_of(to);
+            self.bytes.resize(len, 0);
+        }
+
+        fn u32(&mut self, value: u32) {
+            self.align(4);
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        /// A header field, which is a struct of its code and a variant of its value.
+        fn field(&mut self, code: u8, kind: u8, value: &str) {
+            self.align(8);
+            self.bytes.extend_from_slice(&[code, 1, kind, 0]);
+
+            if kind == b'g' {
+                self.bytes.push(value.len() as u8);
+            } else {
+                self.u32(value.len() as u32);
+            }
+
+            self.bytes.extend_from_slice(value.as_bytes());
+            self.bytes.push(0);
+        }
+    }
+
+    #[derive(Default)]
+    struct Reply {
+        kind: u8,
+        reply_serial: Option<u32>,
+        signature: Vec<u8>,
+        error_name: String,
+        error_message: String,
+    }
+
+    impl Reply {
+        fn error(&self) -> io::Error {
+            let kind = match self.error_name.as_str() {
+                "org.freedesktop.DBus.Error.AccessDenied"
+                | "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" => {
+                    io::ErrorKind::PermissionDenied
+                }
+                "org.freedesktop.DBus.Error.NotSupported"
+                | "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.UnknownMethod" => io::ErrorKind::Unsupported,
+                _ => io::ErrorKind::Other,
+            };
+
+            io::Error::new(kind, format!("{}: {}", self.error_name, self.error_message))
+        }
+    }
+
+    fn invalid(what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the system bus sent {}", what),
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::Cursor;
+
+        /// A stream which replies with canned bytes, and records what is written to it.
+        struct Stream {
+            input: Cursor<Vec<u8>>,
+            output: Vec<u8>,
+        }
+
+        impl Read for Stream {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.input.read(buf)
+            }
+        }
+
+        impl Write for Stream {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.output.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        fn bus(input: &[&[u8]]) -> Bus<Stream> {
+            let stream = Stream {
+                input: Cursor::new(input.concat()),
+                output: Vec::new(),
+            };
+
+            Bus {
+                reader: BufReader::new(stream),
+                serial: 0,
+            }
+        }
+
+        /// A little-endian reply to serial 1, with the sender ":1.0".
+        #[rustfmt::skip]
+        const RETURN: &[u8] = &[
+            b'l', 2, 1, 1, 0, 0, 0, 0, 7, 0, 0, 0, 21, 0, 0, 0,
+            // reply serial: 1
+            5, 1, b'u', 0, 1, 0, 0, 0,
+            // sender: ":1.0"
+            7, 1, b's', 0, 4, 0, 0, 0, b':', b'1', b'.', b'0', 0,
+            // padding to the body, which is empty
+            0, 0, 0,
+        ];
+
+        /// A little-endian error in reply to serial 1, with the message "no".
+        #[rustfmt::skip]
+        const ACCESS_DENIED: &[u8] = &[
+            b'l', 3, 1, 1, 7, 0, 0, 0, 8, 0, 0, 0, 63, 0, 0, 0,
+            // error name
+            4, 1, b's', 0, 39, 0, 0, 0,
+            b'o', b'r', b'g', b'.', b'f', b'r', b'e', b'e', b'd', b'e', b's', b'k', b't', b'o',
+            b'p', b'.', b'D', b'B', b'u', b's', b'.', b'E', b'r', b'r', b'o', b'r', b'.', b'A',
+            b'c', b'c', b'e', b's', b's', b'D', b'e', b'n', b'i', b'e', b'd', 0,
+            // reply serial: 1
+            5, 1, b'u', 0, 1, 0, 0, 0,
+            // signature: "s"
+            8, 1, b'g', 0, 1, b's', 0,
+            // padding to the body
+            0,
+            // body: "no"
+            2, 0, 0, 0, b'n', b'o', 0,
+        ];
+
+        /// A big-endian reply to serial 3, without a body.
+        #[rustfmt::skip]
+        const BIG_ENDIAN_RETURN: &[u8] = &[
+            b'B', 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 8,
+            // reply serial: 3
+            5, 1, b'u', 0, 0, 0, 0, 3,
+        ];
+
+        #[test]
+        fn auth_external_encodes_uid_digits() {
+            assert_eq!(auth_external(0), "\0AUTH EXTERNAL 30\r\n");
+            assert_eq!(auth_external(1000), "\0AUTH EXTERNAL 31303030\r\n");
+        }
+
+        #[test]
+        fn call_encodes_method_call() {
+            let mut bus = bus(&[RETURN]);
+            bus.call("/", "a.b", "M", "c.d", Some(5)).unwrap();
+
+            #[rustfmt::skip]
+            let expected: &[u8] = &[
+                b'l', 1, 4, 1, 8, 0, 0, 0, 1, 0, 0, 0, 71, 0, 0, 0,
+                // path: "/"
+                1, 1, b'o', 0, 1, 0, 0, 0, b'/', 0, 0, 0, 0, 0, 0, 0,
+                // interface: "a.b"
+                2, 1, b's', 0, 3, 0, 0, 0, b'a', b'.', b'b', 0, 0, 0, 0, 0,
+                // member: "M"
+                3, 1, b's', 0, 1, 0, 0, 0, b'M', 0, 0, 0, 0, 0, 0, 0,
+                // destination: "c.d"
+                6, 1, b's', 0, 3, 0, 0, 0, b'c', b'.', b'd', 0, 0, 0, 0, 0,
+                // signature: "t"
+                8, 1, b'g', 0, 1, b't', 0,
+                // padding to the body
+                0,
+                // body: 5
+                5, 0, 0, 0, 0, 0, 0, 0,
+            ];
+
+            assert_eq!(bus.reader.get_ref().output, expected);
+        }
+
+        #[test]
+        fn call_without_argument_has_no_signature() {
+            let mut bus = bus(&[RETURN]);
+            bus.call("/", "a.b", "M", "c.d", None).unwrap();
+
+            let output = &bus.reader.get_ref().output;
+            assert_eq!(&output[4..8], &[0, 0, 0, 0]);
+            assert_eq!(&output[12..16], &[60, 0, 0, 0]);
+            assert_eq!(output.len(), 16 + 64);
+        }
+
+        #[test]
+        fn call_waits_for_its_reply() {
+            let mut reply = RETURN.to_vec();
+            reply[20] = 2;
+
+            // A reply to another serial is skipped, and the error which follows is reported.
+            let mut bus = bus(&[&reply, ACCESS_DENIED]);
+            let why = bus.call("/", "a.b", "M", "c.d", None).unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(
+                why.to_string(),
+                "org.freedesktop.DBus.Error.AccessDenied: no"
+            );
+        }
+
+        #[test]
+        fn receive_parses_replies() {
+            let reply = bus(&[RETURN]).receive().unwrap();
+            assert_eq!(reply.kind, METHOD_RETURN);
+            assert_eq!(reply.reply_serial, Some(1));
+            assert!(reply.error_name.is_empty());
+
+            let reply = bus(&[ACCESS_DENIED]).receive().unwrap();
+            assert_eq!(reply.kind, ERROR);
+            assert_eq!(reply.signature, b"s");
+            assert_eq!(reply.error_message, "no");
+
+            let reply = bus(&[BIG_ENDIAN_RETURN]).receive().unwrap();
+            assert_eq!(reply.kind, METHOD_RETURN);
+            assert_eq!(reply.reply_serial, Some(3));
+        }
+
+        #[test]
+        fn receive_rejects_malformed_replies() {
+            let why = |input: &[u8]| bus(&[input]).receive().err().map(|why| why.kind());
+
+            assert_eq!(why(&RETURN[..20]), Some(io::ErrorKind::UnexpectedEof));
+
+            let mut unknown = RETURN.to_vec();
+            unknown[0] = b'x';
+            assert_eq!(why(&unknown), Some(io::ErrorKind::InvalidData));
+
+            // A string which runs past the end of the header fields.
+            let mut overlong = RETURN.to_vec();
+            overlong[28] = 200;
+            assert_eq!(why(&overlong), Some(io::ErrorKind::InvalidData));
+
+            let mut variant = RETURN.to_vec();
+            variant[18] = b'v';
+            assert_eq!(why(&variant), Some(io::ErrorKind::InvalidData));
+        }
+    }
+}
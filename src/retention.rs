@@ -0,0 +1,165 @@
+//! Policies which decide the entries to keep as new kernels are installed, and the entries and
+//! images to prune.
+
+use crate::entry::{BootCounter, Entry};
+use crate::gc::OrphanedFile;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A reason to keep an entry. Entries which no rule of a policy keeps are pruned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RetentionRule {
+    /// Keep the entries with the newest kernel versions of each token, which is the part of an
+    /// entry's id before its version, as in `<token>-<version>`.
+    LatestPerToken(usize),
+    /// Keep the entries which the boot history records as booted within this long.
+    BootedWithin(Duration),
+    /// Keep the entry which the system was booted from.
+    Current,
+    /// Keep the entry which the loader conf boots by default.
+    Default,
+}
+
+/// The rules which decide the entries that `SystemdBootConf::prune` keeps.
+///
+/// Protected entries, and the entries of other installations when an ownership guard is set,
+/// are always kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RetentionPolicy {
+    pub rules: Vec<RetentionRule>,
+}
+
+impl Default for RetentionPolicy {
+    /// Keep the latest two entries of each token, the current entry, and the default entry.
+    fn default() -> Self {
+        Self::new().keep_latest(2).keep_current().keep_default()
+    }
+}
+
+impl RetentionPolicy {
+    /// A policy without rules, which keeps nothing that is not always kept.
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn keep_latest(self, count: usize) -> Self {
+        self.rule(RetentionRule::LatestPerToken(count))
+    }
+
+    pub fn keep_booted_within(self, age: Duration) -> Self {
+        self.rule(RetentionRule::BootedWithin(age))
+    }
+
+    pub fn keep_current(self) -> Self {
+        self.rule(RetentionRule::Current)
+    }
+
+    pub fn keep_default(self) -> Self {
+        self.rule(RetentionRule::Default)
+    }
+
+    pub fn rule(mut self, rule: RetentionRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+}
+
+/// When each entry was last booted, which updaters record as the system boots, for the
+/// `BootedWithin` rule.
+///
+/// Entries are recorded by their id without a boot counter. The history is stored as lines of
+/// the time in seconds since the Unix epoch, followed by the id.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BootHistory {
+    booted: HashMap<String, SystemTime>,
+}
+
+impl BootHistory {
+    /// Parse a history in the format that it is displayed in, skipping malformed lines.
+    pub fn parse(contents: &str) -> Self {
+        let booted = contents
+            .lines()
+            .filter_map(|line| {
+                let (secs, id) = line.trim().split_once(' ')?;
+                let time = UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?);
+                Some((id.trim().to_owned(), time))
+            })
+            .collect();
+
+        Self { booted }
+    }
+
+    /// Record that an entry was booted at a time, unless a later boot was recorded.
+    ///
+    /// Times are kept to the second, as they are stored.
+    pub fn record(&mut self, id: &str, time: SystemTime) {
+        let time = UNIX_EPOCH + Duration::from_secs(unix_secs(time));
+        let id = base_id(id).to_owned();
+        let last = self.booted.entry(id).or_insert(time);
+        if *last < time {
+            *last = time;
+        }
+    }
+
+    /// When an entry was last booted, if it was recorded.
+    pub fn last_booted(&self, id: &str) -> Option<SystemTime> {
+        self.booted.get(base_id(id)).copied()
+    }
+
+    /// Forget the entries which no longer exist.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut keep: F) {
+        self.booted.retain(|id, _| keep(id));
+    }
+}
+
+impl fmt::Display for BootHistory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut booted: Vec<_> = self.booted.iter().collect();
+        booted.sort_unstable();
+
+        for (id, time) in booted {
+            writeln!(f, "{} {}", unix_secs(*time), id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The entries which a retention policy keeps and prunes, and the images which are no longer
+/// referenced once the pruned entries are removed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct RetentionPlan {
+    pub kept: Vec<String>,
+    pub pruned: Vec<String>,
+    /// Images which only the pruned entries reference.
+    pub payloads: Vec<OrphanedFile>,
+}
+
+impl RetentionPlan {
+    /// The space which pruning the images reclaims, in bytes.
+    pub fn reclaimed(&self) -> u64 {
+        self.payloads.iter().map(|file| file.size).sum()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+fn base_id(id: &str) -> &str {
+    BootCounter::parse(id).map_or(id, |(base, _)| base)
+}
+
+/// The group of entries which `LatestPerToken` keeps the newest of.
+pub(crate) fn token(entry: &Entry) -> &str {
+    let id = entry.base_id();
+    let version = entry.version.as_deref().unwrap_or("");
+
+    match id.strip_suffix(version).and_then(|id| id.strip_suffix('-')) {
+        Some(token) if !version.is_empty() && !token.is_empty() => token,
+        _ => entry.machine_id.as_deref().unwrap_or(id),
+    }
+}
@@ -0,0 +1,168 @@
+//! Discovering the root filesystem, to give new entries the `root=` and related options which
+//! boot it.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How the root filesystem is identified by the `root=` option.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RootIdentifier {
+    /// The UUID of the filesystem, which survives repartitioning but not reformatting.
+    #[default]
+    Uuid,
+    /// The UUID of the GPT partition, which does not require an initrd to resolve.
+    PartUuid,
+}
+
+/// The filesystem mounted at the root of a system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RootFs {
+    /// The block device that the filesystem is mounted from.
+    pub device: PathBuf,
+    pub fstype: String,
+    pub uuid: Option<String>,
+    pub partuuid: Option<String>,
+    /// The btrfs subvolume which is mounted, such as `@`, if it is not the top level.
+    pub subvolume: Option<String>,
+    pub read_only: bool,
+}
+
+impl RootFs {
+    /// Inspect the filesystem mounted at the root of the running system.
+    pub fn detect() -> io::Result<Self> {
+        Self::detect_at("/")
+    }
+
+    /// Inspect the filesystem mounted at `root`, such as the target of an installer.
+    ///
+    /// This fails with `io::ErrorKind::Unsupported` without the `mountinfo` feature.
+    pub fn detect_at<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        if cfg!(not(feature = "mountinfo")) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "inspecting mounts requires the mountinfo feature",
+            ));
+        }
+
+        let root = fs::canonicalize(root)?;
+        let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+        let mut rootfs = Self::from_mountinfo(&mountinfo, &root).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no filesystem is mounted at {:?}", root),
+            )
+        })?;
+
+        let device = fs::canonicalize(&rootfs.device).unwrap_or_else(|_| rootfs.device.clone());
+        rootfs.uuid = link_to(Path::new("/dev/disk/by-uuid"), &device);
+        rootfs.partuuid = link_to(Path::new("/dev/disk/by-partuuid"), &device);
+
+        Ok(rootfs)
+    }
+
+    /// Finds the filesystem mounted at `mount_point` in the contents of `/proc/self/mountinfo`.
+    ///
+    /// The UUIDs are not known from the mount table, and are left empty.
+    pub fn from_mountinfo(mountinfo: &str, mount_point: &Path) -> Option<Self> {
+        // Later mounts hide earlier mounts at the same point.
+        mountinfo.lines().rev().find_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let mut filesystem = filesystem.split(' ');
+
+            if mount.len() < 6 || Path::new(&unescape(mount[4])) != mount_point {
+                return None;
+            }
+
+            let fstype = filesystem.next()?.to_owned();
+            let device = PathBuf::from(unescape(filesystem.next()?));
+            let super_options = filesystem.next().unwrap_or("");
+
+            let subvolume = match fstype.as_str() {
+                "btrfs" => super_options
+                    .split(',')
+                    .find_map(|option| option.strip_prefix("subvol="))
+                    .map(|subvol| subvol.trim_start_matches('/'))
+                    .filter(|subvol| !subvol.is_empty())
+                    .map(unescape),
+                _ => None,
+            };
+
+            Some(RootFs {
+                device,
+                fstype,
+                uuid: None,
+                partuuid: None,
+                subvolume,
+                read_only: mount[5].split(',').any(|option| option == "ro"),
+            })
+        })
+    }
+
+    /// The kernel options which mount this filesystem as the root, such as
+    /// `root=UUID=... rootflags=subvol=@ rw`.
+    ///
+    /// The device path is used for `root=` if the filesystem lacks the requested identifier.
+    pub fn options(&self, identifier: RootIdentifier) -> Vec<String> {
+        let root = match identifier {
+            RootIdentifier::Uuid => self.uuid.as_ref().map(|uuid| ["UUID=", uuid].concat()),
+            RootIdentifier::PartUuid => self
+                .partuuid
+                .as_ref()
+                .map(|partuuid| ["PARTUUID=", partuuid].concat()),
+        };
+
+        let root = root.unwrap_or_else(|| self.device.to_string_lossy().into_owned());
+        let mut options = vec![["root=", &root].concat()];
+
+        if let Some(ref subvolume) = self.subvolume {
+            options.push(["rootflags=subvol=", subvolume].concat());
+        }
+
+        options.push(if self.read_only { "ro" } else { "rw" }.to_owned());
+        options
+    }
+}
+
+/// The name of the link in `dir`, such as `/dev/disk/by-uuid`, which points to `device`.
+fn link_to(dir: &Path, device: &Path) -> Option<String> {
+    fs::read_dir(dir).ok()?.flatten().find_map(|link| {
+        let target = fs::canonicalize(link.path()).ok()?;
+        if target == device {
+            link.file_name().into_string().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Reverses the octal escapes of spaces and other characters in the mount table.
+pub(crate) fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let octal = bytes.get(pos + 1..pos + 4).filter(|digits| {
+            bytes[pos] == b'\\' && digits.iter().all(|digit| (b'0'..=b'7').contains(digit))
+        });
+
+        match octal {
+            Some(digits) => {
+                let value = digits
+                    .iter()
+                    .fold(0u32, |value, d| value * 8 + u32::from(d - b'0'));
+                unescaped.push(value as u8);
+                pos += 4;
+            }
+            None => {
+                unescaped.push(bytes[pos]);
+                pos += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
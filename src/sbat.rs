@@ -0,0 +1,149 @@
+//! SBAT metadata, which shim and systemd-boot use to revoke vulnerable generations of EFI
+//! binaries without revoking the keys that signed them.
+//!
+//! The `.sbat` section of an image is CSV, where each line names a component, its generation,
+//! and the vendor that built it. A revocation policy lists the minimum generation of each
+//! component, and images with an older generation of any component are rejected.
+
+use crate::pe::{PeError, PeImage};
+use crate::ErrorKind;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SbatError {
+    #[error("image is not a valid PE image")]
+    Image(#[source] PeError),
+    #[error("{:?} is not a valid generation", _0)]
+    InvalidGeneration(String),
+    #[error("line {} does not have a component and generation", _0)]
+    InvalidLine(usize),
+    #[error("image has no .sbat section")]
+    Missing,
+    #[error("error reading image")]
+    Open(#[source] io::Error),
+}
+
+impl SbatError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SbatError::Open(why) => ErrorKind::from_io(why),
+            _ => ErrorKind::InvalidData,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// A component recorded in the `.sbat` section of an image, such as `systemd-boot,1,...`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SbatEntry {
+    pub component: String,
+    pub generation: u32,
+    pub vendor_name: String,
+    pub vendor_package: String,
+    pub vendor_version: String,
+    pub vendor_url: String,
+}
+
+/// Parses the contents of a `.sbat` section.
+pub fn parse(text: &str) -> Result<Vec<SbatEntry>, SbatError> {
+    let mut entries = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split(',').map(str::trim);
+        let (component, generation) = match (fields.next(), fields.next()) {
+            (Some(component), Some(generation)) if !component.is_empty() => (component, generation),
+            _ => return Err(SbatError::InvalidLine(number + 1)),
+        };
+
+        let mut field = || fields.next().unwrap_or_default().to_owned();
+
+        entries.push(SbatEntry {
+            component: component.to_owned(),
+            generation: parse_generation(generation)?,
+            vendor_name: field(),
+            vendor_package: field(),
+            vendor_version: field(),
+            vendor_url: field(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Reads the SBAT metadata of a PE image.
+pub fn from_image(data: &[u8]) -> Result<Vec<SbatEntry>, SbatError> {
+    let image = PeImage::parse(data).map_err(SbatError::Image)?;
+    let text = image.section_text(".sbat").ok_or(SbatError::Missing)?;
+    parse(&text)
+}
+
+/// A revocation policy, in the format of shim's `SbatLevel` variable: the minimum generation of
+/// each component that is allowed to boot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SbatPolicy {
+    pub minimum: Vec<(String, u32)>,
+}
+
+impl SbatPolicy {
+    /// Parses a policy, such as `sbat,1,2024010900\nshim,4\ngrub,4\n`.
+    ///
+    /// The first line, which names the `sbat` component and the date of the policy, is skipped.
+    pub fn parse(text: &str) -> Result<Self, SbatError> {
+        let mut minimum = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if line.is_empty() || (number == 0 && line.starts_with("sbat,")) {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            match (fields.next(), fields.next()) {
+                (Some(component), Some(generation)) if !component.is_empty() => {
+                    minimum.push((component.to_owned(), parse_generation(generation)?));
+                }
+                _ => return Err(SbatError::InvalidLine(number + 1)),
+            }
+        }
+
+        Ok(SbatPolicy { minimum })
+    }
+
+    /// The components of an image whose generation is older than this policy allows, which
+    /// would cause the image to be rejected.
+    pub fn revoked<'a>(&self, entries: &'a [SbatEntry]) -> Vec<&'a SbatEntry> {
+        entries
+            .iter()
+            .filter(|entry| {
+                self.minimum.iter().any(|(component, generation)| {
+                    *component == entry.component && entry.generation < *generation
+                })
+            })
+            .collect()
+    }
+}
+
+/// The SBAT metadata of an image which the configuration boots.
+#[derive(Debug)]
+pub struct PayloadSbat {
+    pub path: PathBuf,
+    pub entries: Result<Vec<SbatEntry>, SbatError>,
+}
+
+fn parse_generation(generation: &str) -> Result<u32, SbatError> {
+    generation
+        .parse::<u32>()
+        .map_err(|_| SbatError::InvalidGeneration(generation.to_owned()))
+}
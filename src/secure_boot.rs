@@ -0,0 +1,153 @@
+//! The `loader/keys` directory, from which systemd-boot enrolls secure boot keys.
+//!
+//! Each subdirectory is a set of keys that may be enrolled, which holds the `PK.auth`, `KEK.auth`,
+//! and `db.auth` files, and optionally `dbx.auth`. These are signed EFI variable updates, as
+//! produced by `sign-efi-sig-list`.
+
+use crate::ErrorKind;
+use std::fmt;
+use std::io;
+
+/// `WIN_CERT_TYPE_EFI_GUID`, the certificate type of authenticated variables.
+const WIN_CERT_TYPE_EFI_GUID: u16 = 0x0EF1;
+
+/// `EFI_CERT_TYPE_PKCS7_GUID`, in its mixed-endian binary form.
+const EFI_CERT_TYPE_PKCS7_GUID: [u8; 16] = [
+    0x9d, 0xd2, 0xaf, 0x4a, 0xdf, 0x68, 0xee, 0x49, 0x8a, 0xa9, 0x34, 0x7d, 0x37, 0x56, 0x65, 0xa7,
+];
+
+/// The size of the `EFI_TIME` which precedes the certificate.
+const EFI_TIME_LEN: usize = 16;
+
+/// The size of a `WIN_CERTIFICATE_UEFI_GUID` before its certificate data.
+const CERT_HEADER_LEN: usize = 24;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum KeyError {
+    #[error("{} is not a signed variable update: {}", _0, _1)]
+    InvalidAuth(KeyKind, &'static str),
+    #[error("key set name {:?} is not a valid directory name", _0)]
+    InvalidName(String),
+    #[error("{} is missing", _0)]
+    Missing(KeyKind),
+    #[error("error reading {}", _0)]
+    Open(KeyKind, #[source] io::Error),
+}
+
+impl KeyError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            KeyError::Open(_, why) => ErrorKind::from_io(why),
+            _ => ErrorKind::InvalidData,
+        }
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// The secure boot variables which a key set may update.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeyKind {
+    Pk,
+    Kek,
+    Db,
+    Dbx,
+}
+
+impl KeyKind {
+    /// The keys which systemd-boot requires before it offers to enroll a key set.
+    pub const REQUIRED: [KeyKind; 3] = [KeyKind::Pk, KeyKind::Kek, KeyKind::Db];
+
+    pub const ALL: [KeyKind; 4] = [KeyKind::Pk, KeyKind::Kek, KeyKind::Db, KeyKind::Dbx];
+
+    /// The name of the file in the key set directory.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            KeyKind::Pk => "PK.auth",
+            KeyKind::Kek => "KEK.auth",
+            KeyKind::Db => "db.auth",
+            KeyKind::Dbx => "dbx.auth",
+        }
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.file_name())
+    }
+}
+
+/// A directory of keys in `loader/keys`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySet {
+    pub name: String,
+    /// The key files which are present.
+    pub keys: Vec<KeyKind>,
+}
+
+impl KeySet {
+    /// The required keys which are not present.
+    pub fn missing(&self) -> Vec<KeyKind> {
+        KeyKind::REQUIRED
+            .iter()
+            .cloned()
+            .filter(|kind| !self.keys.contains(kind))
+            .collect()
+    }
+
+    /// Whether systemd-boot would offer to enroll this key set.
+    pub fn is_complete(&self) -> bool {
+        self.missing().is_empty()
+    }
+}
+
+/// Checks that a key file is a signed variable update, which starts with an
+/// `EFI_VARIABLE_AUTHENTICATION_2` header holding a PKCS #7 signature.
+///
+/// The signature itself is verified by the firmware when the key is enrolled.
+pub fn validate_auth(kind: KeyKind, data: &[u8]) -> Result<(), KeyError> {
+    let invalid = |reason| Err(KeyError::InvalidAuth(kind, reason));
+
+    if data.len() < EFI_TIME_LEN + CERT_HEADER_LEN {
+        return invalid("too short for an authentication header");
+    }
+
+    let cert = &data[EFI_TIME_LEN..];
+    let length = u32::from_le_bytes([cert[0], cert[1], cert[2], cert[3]]) as usize;
+    let revision = u16::from_le_bytes([cert[4], cert[5]]);
+    let cert_type = u16::from_le_bytes([cert[6], cert[7]]);
+
+    if revision != 0x0200 {
+        return invalid("unsupported certificate revision");
+    }
+
+    if cert_type != WIN_CERT_TYPE_EFI_GUID || cert[8..24] != EFI_CERT_TYPE_PKCS7_GUID {
+        return invalid("the certificate is not a PKCS #7 signature");
+    }
+
+    if length <= CERT_HEADER_LEN || length > cert.len() {
+        return invalid("the certificate length is out of bounds");
+    }
+
+    Ok(())
+}
+
+/// Key set names are directory names in `loader/keys`.
+pub(crate) fn validate_name(name: &str) -> Result<(), KeyError> {
+    let invalid = name.is_empty()
+        || name.starts_with('.')
+        || name
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control());
+
+    if invalid {
+        return Err(KeyError::InvalidName(name.to_owned()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! A handle for sharing the loader configuration between threads.
+
+use crate::SystemdBootConf;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A cloneable handle to a `SystemdBootConf` that may be shared between threads.
+///
+/// Any number of readers may access the configuration concurrently, while writers are serialized
+/// and given exclusive access. The handle is both `Send` and `Sync`, so multi-threaded daemons may
+/// hand a clone of it to each thread that serves requests.
+///
+/// A panic while holding a guard does not make the configuration inaccessible: the lock is
+/// recovered, and callers may `load_conf` and `load_entries` again to discard partial changes.
+#[derive(Debug, Clone)]
+pub struct SharedSystemdBootConf {
+    inner: Arc<RwLock<SystemdBootConf>>,
+}
+
+impl SharedSystemdBootConf {
+    pub fn new(conf: SystemdBootConf) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(conf)),
+        }
+    }
+
+    /// Acquire shared read access, blocking while a writer holds the lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, SystemdBootConf> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquire exclusive write access, blocking until all other guards are released.
+    pub fn write(&self) -> RwLockWriteGuard<'_, SystemdBootConf> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run a closure with shared read access.
+    pub fn with<T, F: FnOnce(&SystemdBootConf) -> T>(&self, func: F) -> T {
+        func(&self.read())
+    }
+
+    /// Run a closure with exclusive write access.
+    pub fn with_mut<T, F: FnOnce(&mut SystemdBootConf) -> T>(&self, func: F) -> T {
+        func(&mut self.write())
+    }
+}
+
+impl From<SystemdBootConf> for SharedSystemdBootConf {
+    fn from(conf: SystemdBootConf) -> Self {
+        Self::new(conf)
+    }
+}
+
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<SharedSystemdBootConf>();
+}
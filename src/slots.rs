@@ -0,0 +1,186 @@
+//! Helpers for A/B deployments, where two entries act as alternating OS slots.
+
+use crate::entry::{BootCounter, Entry};
+use crate::{Error, SystemdBootConf};
+
+/// One of the two slots of an A/B deployment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    /// The opposing slot.
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// Maps the A and B slots to the ids of their boot entries.
+///
+/// Ids are given without a boot counter suffix, so that the slots remain valid as systemd-boot
+/// renames the entry files while counting boot attempts.
+#[derive(Debug, Clone)]
+pub struct AbSlots {
+    pub a: String,
+    pub b: String,
+}
+
+impl AbSlots {
+    pub fn new<A: Into<String>, B: Into<String>>(a: A, b: B) -> Self {
+        Self {
+            a: a.into(),
+            b: b.into(),
+        }
+    }
+
+    /// The entry id assigned to the given slot.
+    pub fn id(&self, slot: Slot) -> &str {
+        match slot {
+            Slot::A => &self.a,
+            Slot::B => &self.b,
+        }
+    }
+
+    /// The slot that the given entry id belongs to, ignoring any boot counter suffix.
+    pub fn slot_of(&self, id: &str) -> Option<Slot> {
+        let id = BootCounter::parse(id).map_or(id, |(base, _)| base);
+
+        if id == self.a {
+            Some(Slot::A)
+        } else if id == self.b {
+            Some(Slot::B)
+        } else {
+            None
+        }
+    }
+}
+
+impl SystemdBootConf {
+    /// Get the entry of the given slot.
+    pub fn slot_entry(&self, slots: &AbSlots, slot: Slot) -> Option<&Entry> {
+        let id = slots.id(slot);
+        self.entries.iter().find(|e| e.base_id() == id)
+    }
+
+    /// The slot which is currently set as the default entry.
+    pub fn active_slot(&self, slots: &AbSlots) -> Option<Slot> {
+        self.loader_conf
+            .default
+            .as_ref()
+            .and_then(|default| slots.slot_of(default))
+    }
+
+    /// Set the given slot as the default entry.
+    ///
+    /// Call `overwrite_loader_conf` to persist the change.
+    pub fn activate_slot(&mut self, slots: &AbSlots, slot: Slot) -> Result<(), Error> {
+        if self.slot_entry(slots, slot).is_none() {
+            return Err(Error::NotFound);
+        }
+
+        self.loader_conf.default = Some(slots.id(slot).into());
+
+        Ok(())
+    }
+
+    /// Set the inactive slot as the default entry, returning the slot that is now active.
+    ///
+    /// If neither slot is the default, slot A is activated.
+    pub fn flip_slot(&mut self, slots: &AbSlots) -> Result<Slot, Error> {
+        let slot = self.active_slot(slots).map_or(Slot::A, Slot::other);
+        self.activate_slot(slots, slot)?;
+        Ok(slot)
+    }
+
+    /// Fall back to the inactive slot if the active slot has run out of boot attempts.
+    ///
+    /// Returns the slot which was activated, if a rollback was necessary and the fallback slot is
+    /// not also marked as bad. Call `overwrite_loader_conf` to persist the change.
+    pub fn rollback_failed_slot(&mut self, slots: &AbSlots) -> Result<Option<Slot>, Error> {
+        let is_bad = |entry: &Entry| entry.boot_counter().is_some_and(BootCounter::is_bad);
+
+        let active = match self.active_slot(slots) {
+            Some(active) => active,
+            None => return Ok(None),
+        };
+
+        if !self.slot_entry(slots, active).is_some_and(is_bad) {
+            return Ok(None);
+        }
+
+        let fallback = active.other();
+        match self.slot_entry(slots, fallback) {
+            Some(entry) if !is_bad(entry) => {
+                self.activate_slot(slots, fallback)?;
+                Ok(Some(fallback))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::MemoryFilesystem;
+    use crate::DefaultState;
+
+    const ENTRY: &str = "title Pop!_OS\nlinux /vmlinuz\n";
+
+    fn conf(fs: &MemoryFilesystem, default: &str, a: &str, b: &str) -> SystemdBootConf {
+        fs.add_file("/efi/loader/loader.conf", format!("default {}\n", default));
+        fs.add_file(format!("/efi/loader/entries/{}.conf", a), ENTRY);
+        fs.add_file(format!("/efi/loader/entries/{}.conf", b), ENTRY);
+
+        SystemdBootConf::builder("/efi")
+            .filesystem(fs.clone())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn flip_slot_alternates() {
+        let fs = MemoryFilesystem::new();
+        let mut conf = conf(&fs, "b+2", "a", "b+2");
+        let slots = AbSlots::new("a", "b");
+
+        assert_eq!(conf.active_slot(&slots), Some(Slot::B));
+        assert_eq!(conf.flip_slot(&slots).unwrap(), Slot::A);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("a"));
+        assert_eq!(conf.default_entry_exists(), DefaultState::Exists);
+        assert_eq!(conf.flip_slot(&slots).unwrap(), Slot::B);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("b"));
+        assert_eq!(conf.default_entry_exists(), DefaultState::Exists);
+        assert!(conf.is_default(&conf.entries[1]));
+    }
+
+    #[test]
+    fn rollback_falls_back_to_good_slot() {
+        let fs = MemoryFilesystem::new();
+        let mut conf = conf(&fs, "a", "a+0-3", "b");
+        let slots = AbSlots::new("a", "b");
+
+        assert_eq!(conf.rollback_failed_slot(&slots).unwrap(), Some(Slot::B));
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("b"));
+        assert_eq!(conf.default_entry_exists(), DefaultState::Exists);
+
+        // The active slot is now good, so there is nothing to roll back.
+        assert_eq!(conf.rollback_failed_slot(&slots).unwrap(), None);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn rollback_keeps_slot_if_fallback_is_bad() {
+        let fs = MemoryFilesystem::new();
+        let mut conf = conf(&fs, "a", "a+0-3", "b+0-1");
+        let slots = AbSlots::new("a", "b");
+
+        assert_eq!(conf.rollback_failed_slot(&slots).unwrap(), None);
+        assert_eq!(conf.loader_conf.default.as_deref(), Some("a"));
+    }
+}
@@ -0,0 +1,85 @@
+//! An aggregated report of the boot loader's state, like `bootctl status`.
+
+use crate::efivars::EfiVarError;
+use crate::esp_path::EspPath;
+use crate::parse::ParseWarning;
+use crate::pe::PeImage;
+use crate::DefaultState;
+use std::path::PathBuf;
+
+/// The marker around the version string that systemd-boot embeds in its image.
+const LOADER_INFO_PREFIX: &[u8] = b"#### LoaderInfo: ";
+const LOADER_INFO_SUFFIX: &[u8] = b" ####";
+
+/// The state of the boot loader, its configuration, and the running system.
+#[derive(Debug, Default)]
+pub struct Status {
+    pub efi_mount: PathBuf,
+    /// Whether EFI variables are available, which they are not when booted in legacy BIOS mode.
+    pub efivars_available: bool,
+    /// The name and version of the boot loader that booted the system.
+    pub loader_info: Option<String>,
+    /// The vendor and version of the firmware.
+    pub firmware_info: Option<String>,
+    /// The path on the ESP of the image which the firmware executed.
+    pub loader_image: Option<EspPath>,
+    /// The partition UUID of the ESP that the system was booted from.
+    pub loader_partition_uuid: Option<String>,
+    /// The boot loaders installed on the ESP.
+    pub installed_loaders: Vec<InstalledLoader>,
+    /// The default entry in `loader.conf`.
+    pub default_entry: Option<String>,
+    pub default_state: DefaultState,
+    /// The default entry in EFI variables, which takes precedence over `loader.conf`.
+    pub efivar_default_entry: Option<String>,
+    pub oneshot_entry: Option<String>,
+    /// The entry that was booted.
+    pub selected_entry: Option<String>,
+    pub entries: usize,
+    /// The ids of entries which systemd-boot hides, as they are for another architecture.
+    pub foreign_entries: Vec<String>,
+    /// The ids of entries which do not load microcode before their other initrds.
+    pub misordered_microcode: Vec<String>,
+    pub non_utf8_entries: Vec<PathBuf>,
+    /// Problems that were tolerated while loading the configuration.
+    pub warnings: Vec<ParseWarning>,
+    /// Failures to read EFI variables, which leave the corresponding fields empty.
+    pub efivar_errors: Vec<EfiVarError>,
+}
+
+/// A boot loader on the ESP.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstalledLoader {
+    pub image: EspPath,
+    /// The name and version that the image reports, such as `systemd-boot 255`.
+    pub version: Option<String>,
+}
+
+/// The name and version that a systemd-boot image reports in `LoaderInfo`.
+///
+/// This is read from the image's `.sdmagic` section, or found in the image's data for versions
+/// of systemd-boot which predate the section.
+pub fn loader_version_from_image(data: &[u8]) -> Option<String> {
+    let magic = PeImage::parse(data)
+        .ok()
+        .and_then(|image| image.section_data(".sdmagic"));
+
+    let text = match magic {
+        Some(magic) => magic,
+        None => {
+            let start = data
+                .windows(LOADER_INFO_PREFIX.len())
+                .position(|window| window == LOADER_INFO_PREFIX)?;
+            &data[start..]
+        }
+    };
+
+    let text = text.strip_prefix(LOADER_INFO_PREFIX)?;
+    let end = text
+        .windows(LOADER_INFO_SUFFIX.len())
+        .position(|window| window == LOADER_INFO_SUFFIX)?;
+
+    std::str::from_utf8(&text[..end.min(256)])
+        .ok()
+        .map(str::to_owned)
+}
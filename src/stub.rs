@@ -0,0 +1,137 @@
+//! The files which systemd-stub loads alongside a unified kernel image: encrypted credentials
+//! and addons, from the loader directory of the ESP and from the UKI's own `.extra.d` directory.
+//!
+//! The `.extra.d` directory of a UKI belongs to it, and is removed with it by
+//! `SystemdBootConf::remove_uki`.
+
+use crate::pe::{PeError, PeImage};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Where systemd-stub finds the credentials given to every UKI, relative to the ESP.
+pub const CREDENTIALS_DIR: &str = "loader/credentials";
+
+/// Where systemd-stub finds the addons loaded by every UKI, relative to the ESP.
+pub const ADDONS_DIR: &str = "loader/addons";
+
+/// The suffix of the file names of credentials.
+pub const CREDENTIAL_SUFFIX: &str = ".cred";
+
+/// The suffix of the file names of addons.
+pub const ADDON_SUFFIX: &str = ".addon.efi";
+
+/// Which UKIs systemd-stub gives a file to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StubScope {
+    /// Every UKI on the ESP.
+    Global,
+    /// Only the UKI at this path, from its `.extra.d` directory.
+    Uki(PathBuf),
+}
+
+impl StubScope {
+    /// The directory of the files of this scope, where the global files are in `global`,
+    /// relative to the ESP.
+    pub(crate) fn dir(&self, efi_mount: &Path, global: &str) -> PathBuf {
+        match self {
+            StubScope::Global => efi_mount.join(global),
+            StubScope::Uki(uki) => extra_dir(uki),
+        }
+    }
+}
+
+/// A credential which systemd-stub passes to the system, which should be encrypted with
+/// `systemd-creds encrypt`, as the ESP is not authenticated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Credential {
+    /// The name of the credential, which is its file name without the `.cred` extension.
+    pub name: String,
+    pub path: PathBuf,
+    /// The size of the file in bytes.
+    pub size: u64,
+}
+
+/// A PE image whose sections systemd-stub adds to those of a UKI, such as a `.cmdline` which is
+/// appended to the UKI's command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Addon {
+    /// The name of the addon, which is its file name without the `.addon.efi` suffix.
+    pub name: String,
+    pub path: PathBuf,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The command line which the addon appends, if it has one.
+    pub cmdline: Result<Option<String>, PeError>,
+}
+
+/// The contents of the `.extra.d` directory of a UKI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UkiExtras {
+    pub uki: PathBuf,
+    /// The `.extra.d` directory, which may not exist.
+    pub dir: PathBuf,
+    pub addons: Vec<Addon>,
+    pub credentials: Vec<Credential>,
+    /// The other files in the directory, such as system extension images, which are removed
+    /// with the UKI but otherwise left alone.
+    pub other: Vec<PathBuf>,
+}
+
+impl UkiExtras {
+    pub fn is_empty(&self) -> bool {
+        self.addons.is_empty() && self.credentials.is_empty() && self.other.is_empty()
+    }
+}
+
+/// The UKI whose files are in this `.extra.d` directory, if its name is that of one.
+pub fn extra_dir_uki(dir: &Path) -> Option<PathBuf> {
+    let name = dir.file_name()?.to_str()?;
+    let uki = name.strip_suffix(".extra.d")?;
+
+    let is_efi = Path::new(uki)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("efi"));
+
+    if is_efi {
+        Some(dir.with_file_name(uki))
+    } else {
+        None
+    }
+}
+
+/// The command line in the `.cmdline` section of an addon, if it has one.
+pub fn addon_cmdline(image: &[u8]) -> Result<Option<String>, PeError> {
+    let image = PeImage::parse(image)?;
+    Ok(image
+        .section_text(".cmdline")
+        .map(|cmdline| cmdline.trim().to_owned()))
+}
+
+/// The directory of the files which systemd-stub loads for one UKI only, such as
+/// `EFI/Linux/foo.efi.extra.d` for `EFI/Linux/foo.efi`.
+pub fn extra_dir(uki: &Path) -> PathBuf {
+    let mut name = uki.file_name().map_or_else(OsString::new, OsString::from);
+    name.push(".extra.d");
+    uki.with_file_name(name)
+}
+
+/// Whether a credential may be given this name, which must be a valid file name without
+/// control characters, as systemd requires.
+pub fn is_valid_credential_name(name: &str) -> bool {
+    is_valid_name(name, CREDENTIAL_SUFFIX)
+}
+
+/// Whether an addon may be given this name, under the same rules as credentials.
+pub fn is_valid_addon_name(name: &str) -> bool {
+    is_valid_name(name, ADDON_SUFFIX)
+}
+
+pub(crate) fn is_valid_name(name: &str, suffix: &str) -> bool {
+    !name.is_empty()
+        && name.len() + suffix.len() <= 255
+        && name != "."
+        && name != ".."
+        && !name.contains('/')
+        && !name.chars().any(char::is_control)
+}
@@ -0,0 +1,90 @@
+//! How the loader conf and entries are formatted when they are written.
+
+use std::io::{self, Write};
+
+/// The formatting of the files which are written, so that their exact contents may be pinned.
+///
+/// The default style writes each key and its value separated by a single space, in the order
+/// that systemd-boot documents them, and ends the file with a newline.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WriteStyle {
+    /// Pad keys to the width of the longest key in the file, so that the values line up.
+    pub align_keys: bool,
+    /// The number of spaces between a key and its value, after any padding.
+    pub spacing: usize,
+    /// Whether the last line ends with a newline.
+    pub trailing_newline: bool,
+    /// Keys which are written first, in this order. Other keys follow in the default order.
+    pub key_order: Vec<String>,
+    /// Write the `options` lines of an entry as they were read, if the options are unchanged,
+    /// rather than joining them into one line formatted in this style.
+    pub keep_options_lines: bool,
+}
+
+impl Default for WriteStyle {
+    fn default() -> Self {
+        WriteStyle {
+            align_keys: false,
+            spacing: 1,
+            trailing_newline: true,
+            key_order: Vec::new(),
+            keep_options_lines: true,
+        }
+    }
+}
+
+/// A line of a file, before it is formatted.
+pub(crate) struct Line<'a> {
+    pub key: &'a str,
+    pub value: String,
+    /// The value includes the whitespace after the key, and is written as it is.
+    pub verbatim: bool,
+}
+
+impl<'a> Line<'a> {
+    pub fn new<V: ToString>(key: &'a str, value: V) -> Self {
+        Line {
+            key,
+            value: value.to_string(),
+            verbatim: false,
+        }
+    }
+}
+
+impl WriteStyle {
+    pub(crate) fn write(&self, mut lines: Vec<Line>, file: &mut Vec<u8>) -> io::Result<()> {
+        if !self.key_order.is_empty() {
+            let position = |key: &str| {
+                self.key_order
+                    .iter()
+                    .position(|ordered| ordered.eq_ignore_ascii_case(key))
+                    .unwrap_or(self.key_order.len())
+            };
+
+            lines.sort_by_key(|line| position(line.key));
+        }
+
+        let width = if self.align_keys {
+            lines.iter().map(|line| line.key.len()).max().unwrap_or(0)
+        } else {
+            0
+        };
+
+        let spacing = self.spacing.max(1);
+        for (number, line) in lines.iter().enumerate() {
+            if line.verbatim {
+                write!(file, "{}{}", line.key, line.value)?;
+            } else {
+                let padding = width.saturating_sub(line.key.len()) + spacing;
+                write!(file, "{}{:padding$}", line.key, "", padding = padding)?;
+                file.extend_from_slice(line.value.as_bytes());
+            }
+
+            if self.trailing_newline || number + 1 < lines.len() {
+                file.push(b'\n');
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! Assembling unified kernel images, as ukify does, by appending the kernel, initrds, command
+//! line, and other resources to systemd-stub as PE sections.
+
+use crate::kernel::version_from_image;
+use crate::pe::{self, PeError, PeImage, SECURITY_DIRECTORY};
+use crate::stub;
+use crate::ErrorKind;
+use std::convert::TryFrom;
+
+/// The suffix of the file names of unified kernel images.
+pub const UKI_SUFFIX: &str = ".efi";
+
+const SECTION_HEADER_LEN: usize = 40;
+
+/// The characteristics of the sections which are added: initialized, read-only data.
+const DATA_CHARACTERISTICS: u32 = 0x4000_0040;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UkiError {
+    #[error("image is not a valid PE image")]
+    InvalidImage(#[from] PeError),
+    #[error("image has no {} section", _0)]
+    MissingSection(&'static str),
+    #[error("systemd-stub has no room for more section headers")]
+    NoRoom,
+    #[error("image would be larger than a PE image may be")]
+    TooLarge,
+}
+
+impl UkiError {
+    /// A stable classification of this error.
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::InvalidData
+    }
+
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+}
+
+/// Whether a UKI may be given this name, which excludes its `.efi` suffix, under the same rules
+/// as credentials.
+pub fn is_valid_name(name: &str) -> bool {
+    stub::is_valid_name(name, UKI_SUFFIX)
+}
+
+/// Check that an image is a unified kernel image, which is a PE image with a `.linux` section.
+pub fn validate(image: &[u8]) -> Result<(), UkiError> {
+    match PeImage::parse(image)?.section(".linux") {
+        Some(_) => Ok(()),
+        None => Err(UkiError::MissingSection(".linux")),
+    }
+}
+
+/// Assembles a unified kernel image from systemd-stub and a kernel, ready to be installed with
+/// `SystemdBootConf::install_uki`.
+#[derive(Debug, Clone)]
+pub struct UkiBuilder {
+    stub: Vec<u8>,
+    linux: Vec<u8>,
+    initrds: Vec<Vec<u8>>,
+    cmdline: Option<String>,
+    os_release: Option<String>,
+    uname: Option<String>,
+    splash: Option<Vec<u8>>,
+    devicetree: Option<Vec<u8>>,
+}
+
+impl UkiBuilder {
+    /// Start from systemd-stub, such as `linuxx64.efi.stub`, and the kernel that it boots.
+    pub fn new(stub: Vec<u8>, linux: Vec<u8>) -> Self {
+        Self {
+            stub,
+            linux,
+            initrds: Vec::new(),
+            cmdline: None,
+            os_release: None,
+            uname: None,
+            splash: None,
+            devicetree: None,
+        }
+    }
+
+    /// Add an initrd, which is concatenated with the others in the order they are added, so that
+    /// microcode must come first.
+    pub fn initrd(mut self, initrd: Vec<u8>) -> Self {
+        self.initrds.push(initrd);
+        self
+    }
+
+    /// The kernel command line, which may be built with `Cmdline`.
+    pub fn cmdline<S: Into<String>>(mut self, cmdline: S) -> Self {
+        self.cmdline = Some(cmdline.into());
+        self
+    }
+
+    /// The contents of the `os-release` file of the system, which names the image in the menu.
+    pub fn os_release<S: Into<String>>(mut self, os_release: S) -> Self {
+        self.os_release = Some(os_release.into());
+        self
+    }
+
+    /// The version of the kernel, which is read from the kernel when it is not given.
+    pub fn uname<S: Into<String>>(mut self, uname: S) -> Self {
+        self.uname = Some(uname.into());
+        self
+    }
+
+    /// A BMP image which is shown while the kernel boots.
+    pub fn splash(mut self, splash: Vec<u8>) -> Self {
+        self.splash = Some(splash);
+        self
+    }
+
+    /// A devicetree which systemd-stub installs before booting the kernel.
+    pub fn devicetree(mut self, devicetree: Vec<u8>) -> Self {
+        self.devicetree = Some(devicetree);
+        self
+    }
+
+    /// Build the image.
+    ///
+    /// The sections are added in the order that ukify adds them, and a signature of the stub is
+    /// removed, as it does not cover them. The image must be signed again for secure boot.
+    pub fn build(self) -> Result<Vec<u8>, UkiError> {
+        let linux = self.linux;
+        let uname = self.uname.or_else(|| version_from_image(&linux));
+
+        let mut sections: Vec<(&str, Vec<u8>)> = Vec::new();
+        if let Some(os_release) = self.os_release {
+            sections.push((".osrel", os_release.into_bytes()));
+        }
+
+        if let Some(cmdline) = self.cmdline {
+            sections.push((".cmdline", cmdline.into_bytes()));
+        }
+
+        if let Some(devicetree) = self.devicetree {
+            sections.push((".dtb", devicetree));
+        }
+
+        if let Some(uname) = uname {
+            sections.push((".uname", uname.into_bytes()));
+        }
+
+        if let Some(splash) = self.splash {
+            sections.push((".splash", splash));
+        }
+
+        if !self.initrds.is_empty() {
+            sections.push((".initrd", self.initrds.concat()));
+        }
+
+        sections.push((".linux", linux));
+
+        append_sections(&self.stub, &sections)
+    }
+}
+
+/// Append sections of initialized data to a PE image, after its last section.
+fn append_sections(stub: &[u8], sections: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, UkiError> {
+    let image = PeImage::parse(stub)?;
+    let optional_header = image.optional_header_offset;
+    let coff = optional_header - 20;
+    let optional_header_len = pe::read_u16(stub, coff + 16)? as usize;
+    let section_alignment = pe::read_u32(stub, optional_header + 32)?.max(1);
+    let file_alignment = pe::read_u32(stub, optional_header + 36)?.max(1);
+
+    // New headers must fit between the section table and the data of the first section.
+    let table_end =
+        optional_header + optional_header_len + image.sections.len() * SECTION_HEADER_LEN;
+    let headers_end = image
+        .sections
+        .iter()
+        .filter(|section| section.raw_size != 0)
+        .map(|section| section.raw_offset as usize)
+        .min()
+        .unwrap_or(pe::read_u32(stub, optional_header + 60)? as usize);
+
+    if table_end + sections.len() * SECTION_HEADER_LEN > headers_end {
+        return Err(UkiError::NoRoom);
+    }
+
+    // Anything after the data of the last section, such as a signature, is dropped.
+    let data_end = image
+        .sections
+        .iter()
+        .map(|section| section.raw_offset as usize + section.raw_size as usize)
+        .max()
+        .unwrap_or(headers_end);
+
+    let mut virtual_end = image
+        .sections
+        .iter()
+        .map(|section| section.virtual_address + section.virtual_size.max(section.raw_size))
+        .max()
+        .unwrap_or(0);
+
+    let mut out = stub.get(..data_end).ok_or(PeError::Truncated)?.to_vec();
+    let mut initialized_data = pe::read_u32(stub, optional_header + 8)?;
+
+    for (index, (name, data)) in sections.iter().enumerate() {
+        let len = u32::try_from(data.len()).map_err(|_| UkiError::TooLarge)?;
+        let raw_size = align(len, file_alignment)?;
+        let raw_offset = align(
+            u32::try_from(out.len()).map_err(|_| UkiError::TooLarge)?,
+            file_alignment,
+        )?;
+        let virtual_address = align(virtual_end, section_alignment)?;
+
+        let mut header = [0; SECTION_HEADER_LEN];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[8..12].copy_from_slice(&len.to_le_bytes());
+        header[12..16].copy_from_slice(&virtual_address.to_le_bytes());
+        header[16..20].copy_from_slice(&raw_size.to_le_bytes());
+        header[20..24].copy_from_slice(&raw_offset.to_le_bytes());
+        header[36..40].copy_from_slice(&DATA_CHARACTERISTICS.to_le_bytes());
+
+        let header_offset = table_end + index * SECTION_HEADER_LEN;
+        out[header_offset..header_offset + SECTION_HEADER_LEN].copy_from_slice(&header);
+
+        out.resize(raw_offset as usize, 0);
+        out.extend_from_slice(data);
+        out.resize(raw_offset as usize + raw_size as usize, 0);
+
+        virtual_end = virtual_address.checked_add(len).ok_or(UkiError::TooLarge)?;
+        initialized_data = initialized_data.saturating_add(raw_size);
+    }
+
+    let section_count = image.sections.len() + sections.len();
+    let section_count = u16::try_from(section_count).map_err(|_| UkiError::TooLarge)?;
+    write(&mut out, coff + 2, &section_count.to_le_bytes());
+    write(
+        &mut out,
+        optional_header + 8,
+        &initialized_data.to_le_bytes(),
+    );
+    write(
+        &mut out,
+        optional_header + 56,
+        &align(virtual_end, section_alignment)?.to_le_bytes(),
+    );
+
+    // Clear the certificate table, whose signature was removed with it.
+    let directories = optional_header + if image.pe32_plus { 112 } else { 96 };
+    if image.data_directory(SECURITY_DIRECTORY).is_some() {
+        let offset = directories + SECURITY_DIRECTORY * 8;
+        write(&mut out, offset, &[0; 8]);
+    }
+
+    let checksum = checksum(&out, optional_header + 64);
+    write(&mut out, optional_header + 64, &checksum.to_le_bytes());
+
+    Ok(out)
+}
+
+/// The checksum of a PE image, with the checksum field at `field` counted as zero.
+fn checksum(data: &[u8], field: usize) -> u32 {
+    let mut sum: u64 = 0;
+    for (index, word) in data.chunks(2).enumerate() {
+        let offset = index * 2;
+        if offset == field || offset == field + 2 {
+            continue;
+        }
+
+        let word = u16::from_le_bytes([word[0], word.get(1).copied().unwrap_or(0)]);
+        sum += u64::from(word);
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum = (sum & 0xffff) + (sum >> 16);
+    (sum as u32).wrapping_add(data.len() as u32)
+}
+
+fn align(value: u32, alignment: u32) -> Result<u32, UkiError> {
+    value
+        .checked_add(alignment - 1)
+        .map(|value| value / alignment * alignment)
+        .ok_or(UkiError::TooLarge)
+}
+
+fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
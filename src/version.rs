@@ -0,0 +1,141 @@
+//! Version comparison matching systemd's `strverscmp_improved`, which systemd-boot uses to sort
+//! entries by their `version` field.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A version string which is ordered the way systemd-boot orders versions.
+#[derive(Debug, Clone, Eq)]
+pub struct Version(String);
+
+impl Version {
+    pub fn new<S: Into<String>>(version: S) -> Self {
+        Version(version.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Compare two version strings the way systemd-boot does.
+///
+/// Versions are split into numeric and alphabetic segments, which may be prefixed with:
+///
+/// - `~`, for pre-releases, which sort before everything else, even the end of the string
+/// - `-`, separating the version from the release
+/// - `^`, for patched releases, which sort after `-`
+/// - `.`, for point releases
+///
+/// All other characters are treated as segment separators, so `123_a` is equal to `123a`.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    fn is_valid(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || c == b'~' || c == b'-' || c == b'^' || c == b'.'
+    }
+
+    fn skip_invalid(s: &[u8]) -> &[u8] {
+        let pos = s.iter().position(|&c| is_valid(c)).unwrap_or(s.len());
+        &s[pos..]
+    }
+
+    fn leading(s: &[u8], pred: fn(&u8) -> bool) -> usize {
+        s.iter().position(|c| !pred(c)).unwrap_or(s.len())
+    }
+
+    // The string which has the prefix is older, and otherwise both prefixes are skipped.
+    fn prefixed(a: &mut &[u8], b: &mut &[u8], prefix: u8) -> Option<Ordering> {
+        let (pa, pb) = (a.first() == Some(&prefix), b.first() == Some(&prefix));
+        if !pa && !pb {
+            return None;
+        }
+
+        match (!pa).cmp(&!pb) {
+            Ordering::Equal => {
+                *a = &a[1..];
+                *b = &b[1..];
+                None
+            }
+            ordering => Some(ordering),
+        }
+    }
+
+    loop {
+        a = skip_invalid(a);
+        b = skip_invalid(b);
+
+        if let Some(ordering) = prefixed(&mut a, &mut b, b'~') {
+            return ordering;
+        }
+
+        if a.is_empty() || b.is_empty() {
+            return a.cmp(b);
+        }
+
+        for &prefix in b"-^." {
+            if let Some(ordering) = prefixed(&mut a, &mut b, prefix) {
+                return ordering;
+            }
+        }
+
+        let (la, lb);
+        if a.first().is_some_and(u8::is_ascii_digit) || b.first().is_some_and(u8::is_ascii_digit) {
+            la = leading(a, u8::is_ascii_digit);
+            lb = leading(b, u8::is_ascii_digit);
+
+            // Numeric segments are newer than alphabetic segments.
+            let ordering = (la != 0).cmp(&(lb != 0));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+
+            let (na, nb) = (trim_zeros(&a[..la]), trim_zeros(&b[..lb]));
+            let ordering = na.len().cmp(&nb.len()).then_with(|| na.cmp(nb));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            la = leading(a, u8::is_ascii_alphabetic);
+            lb = leading(b, u8::is_ascii_alphabetic);
+
+            let common = la.min(lb);
+            let ordering = a[..common].cmp(&b[..common]).then(la.cmp(&lb));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        a = &a[la..];
+        b = &b[lb..];
+    }
+}
+
+fn trim_zeros(s: &[u8]) -> &[u8] {
+    let pos = s.iter().position(|&c| c != b'0').unwrap_or(s.len());
+    &s[pos..]
+}